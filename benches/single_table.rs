@@ -12,7 +12,7 @@ use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::time::Duration;
 
 const RANDOM_SEED: u64 = 12345;
-const SQLITE_SCHEMA: &'static str = "
+const SQLITE_SCHEMA: &str = "
 CREATE TABLE users(
     id INTEGER NOT NULL PRIMARY KEY,
     name TEXT NOT NULL,
@@ -30,9 +30,13 @@ enum Error {
     UserNotFound,
     UserIdExists,
     UserEmailExists,
+    DocumentNotFound,
+    DocumentIdExists,
+    DocumentTitleExists,
 }
 
 type UserId = u64;
+type DocumentId = u64;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct User {
@@ -53,6 +57,14 @@ impl Default for User {
     }
 }
 
+/// Row with a large payload, where cloning the row dominates the cost of an operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Document {
+    id: DocumentId,
+    title: String,
+    body: Vec<u8>,
+}
+
 macro_rules! table_impl {
     ($type:ty) => {
         impl $type {
@@ -133,6 +145,22 @@ struct AADatabase {
     users_by_name: AATreeMap<String, AATreeSet<UserId>>,
 }
 
+#[derive(Default, Clone)]
+struct DocumentDatabase {
+    documents: BTreeMap<DocumentId, Document>,
+    document_by_title: BTreeMap<String, DocumentId>,
+}
+
+impl DocumentDatabase {
+    table!(
+        documents: Document,
+        id: DocumentId,
+        missing Error => Error::DocumentNotFound,
+        primary documents id => Error::DocumentIdExists,
+        unique document_by_title title => Error::DocumentTitleExists
+    );
+}
+
 table_impl!(BTreeDatabase);
 table_impl!(BTreeSlabDatabase);
 table_impl!(OrdMapDatabase);
@@ -144,13 +172,31 @@ table_impl!(AADatabase);
 
 fn generate_user(id: u64, num: u64) -> User {
     User {
-        id: id,
+        id,
         email: format!("user-{num}@example.com"),
         name: format!("user-{num}"),
         age: 21 + (num % 50) as u32,
     }
 }
 
+fn generate_document(id: u64, size: usize) -> Document {
+    Document {
+        id,
+        title: format!("document-{id}"),
+        body: vec![id as u8; size],
+    }
+}
+
+fn generate_documents(limit: u64, size: usize) -> DocumentDatabase {
+    let mut database = DocumentDatabase::default();
+    for id in 0..limit {
+        database
+            .documents_insert(generate_document(id, size))
+            .unwrap();
+    }
+    database
+}
+
 fn random_range(limit: u64) -> Vec<u64> {
     let mut rng = ChaCha20Rng::seed_from_u64(RANDOM_SEED);
     let mut indices: Vec<u64> = (0..limit).collect();
@@ -318,6 +364,54 @@ macro_rules! table_benchmark {
     };
 }
 
+macro_rules! payload_benchmark {
+    ($c:expr, $name:expr, $operations:expr, $size:expr) => {
+        let mut group = $c.benchmark_group($name);
+        group.sample_size(10);
+        group.measurement_time(Duration::from_secs(10));
+        for ops in $operations.into_iter() {
+            group.throughput(Throughput::Elements(ops));
+
+            group.bench_with_input(format!("update-{ops}"), &ops, |b, elems| {
+                b.iter_batched(
+                    || {
+                        let database = generate_documents(*elems, $size);
+                        let updates: Vec<Document> = (0..*elems)
+                            .map(|id| Document {
+                                title: format!("new-{id}"),
+                                ..generate_document(id, $size)
+                            })
+                            .collect();
+                        (database, updates)
+                    },
+                    |(mut database, updates)| {
+                        for document in updates.into_iter() {
+                            black_box(database.documents_update(document).unwrap());
+                        }
+                        black_box(database)
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+
+            group.bench_with_input(format!("delete-{ops}"), &ops, |b, elems| {
+                b.iter_batched(
+                    || generate_documents(*elems, $size),
+                    |mut database| {
+                        for id in 0..*elems {
+                            black_box(database.documents_delete(id).unwrap());
+                        }
+                        black_box(database)
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+
+        group.finish();
+    };
+}
+
 macro_rules! sqlite_benchmark {
     ($c:expr, $name:expr, $operations:expr) => {
         let mut group = $c.benchmark_group($name);
@@ -572,6 +666,8 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     table_benchmark!(c, "avl::tree", AvlDatabase, operations);
     table_benchmark!(c, "aatree", AADatabase, operations);
 
+    payload_benchmark!(c, "payload::64k", [1_000], 64 * 1024);
+
     sqlite_benchmark!(c, "sqlite", operations);
 }
 
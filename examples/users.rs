@@ -1,4 +1,4 @@
-use macrodb::table;
+use macrodb::{errors, table};
 use std::collections::*;
 
 type UserId = u64;
@@ -17,15 +17,12 @@ pub struct Group {
     name: String,
 }
 
-pub enum Error {
-    UserNotFound,
-    UserIdExists,
-    UserNameExists,
-    GroupNotFound,
-    GroupIdExists,
-    GroupNameExists,
-    GroupHasUsers,
-}
+errors!(
+    pub enum Error {
+        users { missing, primary, foreign groups, unique user_by_name },
+        groups { missing, primary, unique group_by_name, reverse users_by_group },
+    }
+);
 
 #[derive(Default)]
 pub struct Database {
@@ -40,22 +37,22 @@ impl Database {
     table!(
         users: User,
         id: UserId,
-        missing Error => Error::UserNotFound,
-        primary users id => Error::UserIdExists,
-        foreign groups group => Error::GroupNotFound,
+        missing Error => Error::UsersNotFound,
+        primary users id => Error::UsersExists,
+        foreign groups group => Error::UsersGroupsNotFound,
         index users_by_group group => (),
-        unique user_by_name name => Error::UserNameExists
+        unique user_by_name name => Error::UserByNameExists
     );
     table!(
         groups: Group,
         id: GroupId,
-        missing Error => Error::GroupNotFound,
-        primary groups id => Error::GroupIdExists,
-        unique group_by_name name => Error::GroupNameExists,
-        reverse users_by_group id => Error::GroupHasUsers
+        missing Error => Error::GroupsNotFound,
+        primary groups id => Error::GroupsExists,
+        unique group_by_name name => Error::GroupByNameExists,
+        reverse users_by_group id => Error::UsersByGroupNotEmpty
     );
 }
 
 fn main() {
-    let database = Database::default();
+    let _database = Database::default();
 }
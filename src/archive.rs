@@ -0,0 +1,130 @@
+//! Memory-mapped snapshots used by the [archived_table](macro@crate::archived_table) macro.
+use alloc::borrow::ToOwned;
+use alloc::string::ToString;
+use core::hash::Hash;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use memmap2::Mmap;
+use rkyv::collections::btree_map::ArchivedBTreeMap;
+use rkyv::collections::hash_map::ArchivedHashMap;
+use rkyv::option::ArchivedOption;
+use rkyv::ser::serializers::AllocSerializer;
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::{Archive, CheckBytes, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Archived map type that can be used as a table of an archived database.
+///
+/// This is the read-only counterpart of [TableMap](crate::TableMap), implemented for the
+/// archived versions of [BTreeMap](alloc::collections::BTreeMap) and
+/// [HashMap](std::collections::HashMap).
+pub trait ArchivedTableMap {
+    /// Archived key of the map.
+    type Key;
+    /// Archived value of the map.
+    type Value;
+
+    /// Look up an archived value by its archived key.
+    fn get(&self, key: &Self::Key) -> Option<&Self::Value>;
+}
+
+impl<K: Ord, V> ArchivedTableMap for ArchivedBTreeMap<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn get(&self, key: &K) -> Option<&V> {
+        ArchivedBTreeMap::get(self, key)
+    }
+}
+
+impl<K: Hash + Eq, V> ArchivedTableMap for ArchivedHashMap<K, V> {
+    type Key = K;
+    type Value = V;
+
+    fn get(&self, key: &K) -> Option<&V> {
+        ArchivedHashMap::get(self, key)
+    }
+}
+
+/// Archived [Tombstone](crate::Tombstone) field of a table with soft deletion.
+#[doc(hidden)]
+pub trait ArchivedTombstone {
+    fn is_deleted(&self) -> bool;
+}
+
+impl ArchivedTombstone for bool {
+    fn is_deleted(&self) -> bool {
+        *self
+    }
+}
+
+impl<T> ArchivedTombstone for ArchivedOption<T> {
+    fn is_deleted(&self) -> bool {
+        self.is_some()
+    }
+}
+
+/// Read-only database that is memory-mapped from an archive file.
+///
+/// The archive is validated once when it is opened, after that it dereferences to the archived
+/// database without copying or deserializing it. Opening an archive is unsafe, since the file
+/// must not be modified while it is mapped.
+pub struct MappedArchive<T> {
+    map: Mmap,
+    marker: PhantomData<T>,
+}
+
+impl<T: Serialize<AllocSerializer<4096>>> MappedArchive<T> {
+    /// Archive `database` to the file at `path`.
+    ///
+    /// The archive is written to a temporary file next to `path` first and renamed once it is
+    /// complete, so that a mapped archive is never modified.
+    pub fn save(database: &T, path: &Path) -> io::Result<()> {
+        let bytes = rkyv::to_bytes::<_, 4096>(database)
+            .map_err(|error| io::Error::other(error.to_string()))?;
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+}
+
+impl<T: Archive> MappedArchive<T>
+where
+    T::Archived: for<'a> CheckBytes<DefaultValidator<'a>>,
+{
+    /// Map and validate the archive at `path`.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated, by this or any other process, while the
+    /// returned archive (or any reference obtained from it) is alive. The archive is only
+    /// validated once, so changes to the file afterwards are undefined behaviour. Archives
+    /// written by [save](MappedArchive::save) are replaced by renaming a new file over them,
+    /// which does not modify a file that is already mapped.
+    pub unsafe fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the caller guarantees that the file is not modified while it is mapped, and
+        // the archive is validated below before it is used
+        let map = unsafe { Mmap::map(&file)? };
+        rkyv::check_archived_root::<T>(&map)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+        Ok(MappedArchive {
+            map,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl<T: Archive> Deref for MappedArchive<T> {
+    type Target = T::Archived;
+
+    fn deref(&self) -> &T::Archived {
+        // SAFETY: the archive was validated when it was opened
+        unsafe { rkyv::archived_root::<T>(&self.map) }
+    }
+}
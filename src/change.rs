@@ -0,0 +1,114 @@
+//! Change events emitted by the generated mutation methods.
+//!
+//! Tables that declare a `changes` entry report every insert, update and delete to a
+//! [ChangeSink] stored in the database struct. Events are only emitted once all checks have
+//! passed, and before the database is changed, so that a sink which fails to receive an event
+//! can abort the operation.
+use alloc::vec::Vec;
+use core::convert::Infallible;
+
+/// Change that was made to a single row of a table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Change<T> {
+    /// Row was inserted.
+    Inserted(T),
+    /// Row was updated, replacing `old` with `new`.
+    Updated {
+        /// Row before the update.
+        old: T,
+        /// Row after the update.
+        new: T,
+    },
+    /// Row was deleted.
+    Deleted(T),
+}
+
+impl<T> Change<T> {
+    /// Convert the rows of this change using `f`.
+    pub fn map<U, F: FnMut(T) -> U>(self, mut f: F) -> Change<U> {
+        match self {
+            Change::Inserted(row) => Change::Inserted(f(row)),
+            Change::Updated { old, new } => Change::Updated {
+                old: f(old),
+                new: f(new),
+            },
+            Change::Deleted(row) => Change::Deleted(f(row)),
+        }
+    }
+
+    /// Borrow the rows of this change.
+    pub fn as_ref(&self) -> Change<&T> {
+        match self {
+            Change::Inserted(row) => Change::Inserted(row),
+            Change::Updated { old, new } => Change::Updated { old, new },
+            Change::Deleted(row) => Change::Deleted(row),
+        }
+    }
+
+    /// Get the row that this change was made to, which is the new row for updates.
+    #[doc(hidden)]
+    pub fn row(&self) -> &T {
+        match self {
+            Change::Inserted(row) | Change::Deleted(row) => row,
+            Change::Updated { new, .. } => new,
+        }
+    }
+
+    /// Get the row as it is after this change, if it still exists.
+    pub fn current(&self) -> Option<&T> {
+        match self {
+            Change::Inserted(row) => Some(row),
+            Change::Updated { new, .. } => Some(new),
+            Change::Deleted(_) => None,
+        }
+    }
+}
+
+impl<T: Clone> Change<&T> {
+    /// Clone the rows of this change.
+    pub fn cloned(self) -> Change<T> {
+        self.map(Clone::clone)
+    }
+}
+
+/// Receiver of the change events of one or more tables.
+///
+/// The sink is stored as a field of the database struct, and declared in the
+/// [table](macro@crate::table) macro with `changes $field _ => ()`. A single sink can receive
+/// the changes of several tables by implementing this trait once per row type.
+///
+/// Sinks that can fail (such as a [Wal](crate::Wal)) return an error, which aborts the operation
+/// that made the change. Their `changes` entry maps the error to the error type of the table,
+/// sinks that cannot fail use [Infallible] as their error.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot receive changes of `{T}` rows",
+    note = "implement `macrodb::ChangeSink<{T}>` for it"
+)]
+pub trait ChangeSink<T> {
+    /// Error returned when a change cannot be received.
+    type Error;
+
+    /// Receive a change made to `table`, which is about to be applied.
+    fn emit(&mut self, table: &'static str, change: Change<&T>) -> Result<(), Self::Error>;
+}
+
+impl<T: Clone> ChangeSink<T> for Vec<Change<T>> {
+    type Error = Infallible;
+
+    fn emit(&mut self, _table: &'static str, change: Change<&T>) -> Result<(), Infallible> {
+        self.push(change.cloned());
+        Ok(())
+    }
+}
+
+impl<T, S: ChangeSink<T>> ChangeSink<T> for Option<S> {
+    type Error = S::Error;
+
+    fn emit(&mut self, table: &'static str, change: Change<&T>) -> Result<(), S::Error> {
+        match self {
+            Some(sink) => sink.emit(table, change),
+            None => Ok(()),
+        }
+    }
+}
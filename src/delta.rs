@@ -0,0 +1,118 @@
+//! Dirty tracking used by the `dirty` entries of tables and the delta snapshots generated by
+//! the `delta` macro.
+#[cfg(feature = "serde")]
+use crate::TableMap;
+use alloc::collections::BTreeSet;
+#[cfg(feature = "serde")]
+use alloc::vec::Vec;
+#[cfg(feature = "serde")]
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+
+/// Set of primary keys of the rows that were changed since the last delta marker.
+///
+/// The set is stored as a field of the database struct, and declared in the
+/// [table](macro@crate::table) macro with `dirty $field _ => ()`. This is implemented for
+/// [BTreeSet] and, with the `std` feature, for [HashSet](std::collections::HashSet).
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be used to track changed rows",
+    note = "use a `BTreeSet` or a `HashSet` of the primary key type"
+)]
+pub trait DirtySet {
+    /// Type of the primary keys.
+    type Key;
+
+    /// Mark the row with primary key `key` as changed.
+    fn mark(&mut self, key: &Self::Key);
+
+    /// Iterate over the primary keys of the changed rows.
+    fn keys(&self) -> impl Iterator<Item = &Self::Key>;
+
+    /// Forget all changed rows.
+    fn clear(&mut self);
+}
+
+impl<K: Ord + Clone> DirtySet for BTreeSet<K> {
+    type Key = K;
+
+    fn mark(&mut self, key: &K) {
+        if !self.contains(key) {
+            self.insert(key.clone());
+        }
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter()
+    }
+
+    fn clear(&mut self) {
+        BTreeSet::clear(self);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: core::hash::Hash + Eq + Clone> DirtySet for std::collections::HashSet<K> {
+    type Key = K;
+
+    fn mark(&mut self, key: &K) {
+        if !self.contains(key) {
+            self.insert(key.clone());
+        }
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter()
+    }
+
+    fn clear(&mut self) {
+        std::collections::HashSet::clear(self);
+    }
+}
+
+/// Serializes the changed rows of a table: the keys of all changed rows, and the current
+/// version of the rows that still exist.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub struct DeltaRows<'a, M, D> {
+    pub table: &'a M,
+    pub dirty: &'a D,
+}
+
+#[cfg(feature = "serde")]
+impl<M, D> Serialize for DeltaRows<'_, M, D>
+where
+    M: TableMap,
+    M::Value: Serialize,
+    D: DirtySet<Key = M::Key>,
+    D::Key: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Keys<'a, D>(&'a D);
+        impl<D: DirtySet<Key: Serialize>> Serialize for Keys<'_, D> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.keys())
+            }
+        }
+
+        struct Rows<'a, M, D>(&'a M, &'a D);
+        impl<M: TableMap<Value: Serialize>, D: DirtySet<Key = M::Key>> Serialize for Rows<'_, M, D> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.1.keys().filter_map(|key| self.0.get(key)))
+            }
+        }
+
+        let mut state = serializer.serialize_struct("DeltaTable", 2)?;
+        state.serialize_field("keys", &Keys(self.dirty))?;
+        state.serialize_field("rows", &Rows(self.table, self.dirty))?;
+        state.end()
+    }
+}
+
+/// Changed rows of a table, as read from a delta snapshot.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeltaTable<K, T> {
+    pub keys: Vec<K>,
+    pub rows: Vec<T>,
+}
@@ -0,0 +1,31 @@
+//! Differences between databases, generated by the [diff](macro@crate::diff) macro.
+use alloc::vec::Vec;
+
+/// Differences between the rows of a table in two databases.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableDiff<T> {
+    /// Rows that only exist in the other database.
+    pub inserted: Vec<T>,
+    /// Rows that only exist in this database.
+    pub deleted: Vec<T>,
+    /// Rows that exist in both databases but are not equal, as pairs of the row in this
+    /// database and the row in the other database.
+    pub changed: Vec<(T, T)>,
+}
+
+impl<T> TableDiff<T> {
+    /// Determine if the table is the same in both databases.
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.deleted.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl<T> Default for TableDiff<T> {
+    fn default() -> Self {
+        TableDiff {
+            inserted: Vec::new(),
+            deleted: Vec::new(),
+            changed: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Support types for error enums generated by the [errors](macro@crate::errors) macro.
+use core::fmt;
+
+/// Kind of check that an error was produced by.
+///
+/// Every variant of an error enum generated by [errors](macro@crate::errors) maps to one of
+/// these, which makes it possible to handle errors generically (for example, to map them to
+/// HTTP status codes) without matching on every variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ErrorKind {
+    /// Row with the given primary key does not exist (`missing`).
+    NotFound,
+    /// Row with the same primary key or unique value already exists (`primary`, `unique`).
+    AlreadyExists,
+    /// Row references a row in another table that does not exist (`foreign`).
+    MissingReference,
+    /// Row is still referenced by rows in another table (`reverse`).
+    StillReferenced,
+    /// Update tried to change a field that cannot be changed (`immutable`).
+    Immutable,
+    /// Update was based on an outdated version of the row (`version`).
+    Stale,
+    /// Row was rejected by a constraint.
+    Constraint,
+    /// Update was rejected by a transition.
+    Transition,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorKind::NotFound => "not found",
+            ErrorKind::AlreadyExists => "already exists",
+            ErrorKind::MissingReference => "missing reference",
+            ErrorKind::StillReferenced => "still referenced",
+            ErrorKind::Immutable => "immutable",
+            ErrorKind::Stale => "stale",
+            ErrorKind::Constraint => "constraint violated",
+            ErrorKind::Transition => "invalid transition",
+        })
+    }
+}
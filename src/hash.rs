@@ -0,0 +1,428 @@
+//! Hashes of the contents of tables, used to compare databases without transferring them.
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+use core::ops::{Bound, RangeBounds};
+
+/// Hash the value with a hasher that does not depend on the process, so that hashes computed by
+/// different databases can be compared.
+fn stable_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher(0xcbf2_9ce4_8422_2325);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Combine two hashes, such that the result depends on their order.
+fn combine(hash: u64, value: u64) -> u64 {
+    mix(hash.rotate_left(5) ^ value)
+}
+
+/// Finalizer of SplitMix64, which spreads the bits of the input over the whole output.
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    value ^ (value >> 31)
+}
+
+/// FNV-1a hasher, with its output mixed to improve the distribution of the low bits.
+struct StableHasher(u64);
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        mix(self.0)
+    }
+}
+
+/// Hash of all rows of a table, which is updated whenever a row is changed.
+///
+/// The hash is stored as a field of the database struct, and declared in the
+/// [table](macro@crate::table) macro with `hash $field _ => ()`. It does not depend on the order
+/// in which rows were inserted, so two tables with the same rows have the same hash. Only rows
+/// that were not soft deleted are included.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TableHash {
+    sum: u64,
+    count: u64,
+}
+
+impl TableHash {
+    /// Add a row to the hash.
+    pub fn insert<T: Hash + ?Sized>(&mut self, row: &T) {
+        self.sum = self.sum.wrapping_add(stable_hash(row));
+        self.count += 1;
+    }
+
+    /// Remove a row from the hash.
+    pub fn remove<T: Hash + ?Sized>(&mut self, row: &T) {
+        self.sum = self.sum.wrapping_sub(stable_hash(row));
+        self.count -= 1;
+    }
+
+    /// Number of rows in the table.
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    /// Determine if the table is empty.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Hash of the table as a single value.
+    pub fn value(&self) -> u64 {
+        combine(mix(self.sum), self.count)
+    }
+
+    /// Add the rows of another hash.
+    fn add(&mut self, other: &TableHash) {
+        self.sum = self.sum.wrapping_add(other.sum);
+        self.count += other.count;
+    }
+
+    /// Remove the rows of another hash, which must be contained in this one.
+    fn subtract(&mut self, other: &TableHash) {
+        self.sum = self.sum.wrapping_sub(other.sum);
+        self.count -= other.count;
+    }
+}
+
+/// Hashes maintained by the generated methods of a table with a `hash` entry.
+#[doc(hidden)]
+pub trait RowHasher<M, K, V> {
+    /// Add a row that is about to be inserted into `table`. Other rows of the table are the
+    /// same as when the row was added, except for the row itself, which may already be stored.
+    fn insert_row(&mut self, table: &M, live: fn(&V) -> bool, key: &K, row: &V);
+
+    /// Remove a row from the hash.
+    fn remove_row(&mut self, table: &M, key: &K, row: &V);
+
+    /// Replace the hash of a row that was updated.
+    fn update_row(&mut self, table: &M, key: &K, old: &V, new: &V);
+}
+
+impl<M, K, V: Hash> RowHasher<M, K, V> for TableHash {
+    fn insert_row(&mut self, _table: &M, _live: fn(&V) -> bool, _key: &K, row: &V) {
+        self.insert(row);
+    }
+
+    fn remove_row(&mut self, _table: &M, _key: &K, row: &V) {
+        self.remove(row);
+    }
+
+    fn update_row(&mut self, _table: &M, _key: &K, old: &V, new: &V) {
+        self.remove(old);
+        self.insert(new);
+    }
+}
+
+/// Every level of the digest groups this many segments of the level below, on average.
+const FANOUT_BITS: u32 = 4;
+
+/// Number of levels of a [TableDigest], which is enough for tables with billions of rows.
+const LEVELS: usize = 8;
+
+/// Segment of a [TableDigest], containing the rows from `start` up to `end` (exclusive). A
+/// missing `start` or `end` extends the segment to the start or end of the table.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DigestSegment<K> {
+    /// First key of the segment.
+    pub start: Option<K>,
+    /// First key of the next segment.
+    pub end: Option<K>,
+    /// Hash of the rows of the segment.
+    pub hash: TableHash,
+}
+
+/// Segments of one level of a [TableDigest].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Level<K: Ord> {
+    /// Hash of the rows before the first segment.
+    head: TableHash,
+    /// Hash of the rows of every segment, by the key that it starts at.
+    segments: BTreeMap<K, TableHash>,
+}
+
+impl<K: Ord> Default for Level<K> {
+    fn default() -> Self {
+        Level {
+            head: TableHash::default(),
+            segments: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord> Level<K> {
+    /// Hash of the segment that contains `key`.
+    fn containing(&mut self, key: &K) -> &mut TableHash {
+        match self.segments.range_mut(..=key).next_back() {
+            Some((_, hash)) => hash,
+            None => &mut self.head,
+        }
+    }
+
+    /// Hash of the segments that start in `range`, including the head if it is unbounded.
+    fn sum(&self, range: impl RangeBounds<K>) -> TableHash {
+        let mut sum = match range.start_bound() {
+            Bound::Unbounded => self.head,
+            _ => TableHash::default(),
+        };
+        for hash in self.segments.range(range).map(|(_, hash)| hash) {
+            sum.add(hash);
+        }
+        sum
+    }
+}
+
+/// Tree of hashes over ranges of primary keys of a table stored in a [BTreeMap].
+///
+/// The rows are split into segments at keys determined by the hash of the key, and the segments
+/// are grouped into larger segments at every level of the tree, with level 0 having segments of
+/// about 16 rows. Since the split points only depend on the keys, two tables that differ in a
+/// few rows share most of their segments, and the ranges that differ can be located by only
+/// descending into segments whose hashes differ.
+///
+/// The digest is stored as a field of the database struct and declared with a `hash` entry,
+/// like a [TableHash], which keeps it up to date as rows are changed. It only stores the hash of
+/// every segment, and can be serialized (with the `serde` feature), so that replicas can
+/// compare their tables top-down: starting at the highest level, [segments](Self::segments)
+/// returns the segments of the next level in every range whose hashes differ, until the rows
+/// of the remaining ranges are exchanged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TableDigest<K: Ord> {
+    /// Hash of the whole table.
+    total: TableHash,
+    /// Segments of every level, starting with the smallest ones.
+    levels: Vec<Level<K>>,
+}
+
+impl<K: Ord> Default for TableDigest<K> {
+    fn default() -> Self {
+        TableDigest {
+            total: TableHash::default(),
+            levels: (0..LEVELS).map(|_| Level::default()).collect(),
+        }
+    }
+}
+
+impl<K: Ord + Clone + Hash> TableDigest<K> {
+    /// Build the digest of a table.
+    pub fn new<V: Hash>(table: &BTreeMap<K, V>) -> Self {
+        let mut digest = Self::default();
+        for (key, row) in table {
+            digest.total.insert(row);
+            for (level, segments) in digest.levels.iter_mut().enumerate() {
+                if Self::splits(key, level) {
+                    segments.segments.insert(key.clone(), TableHash::default());
+                }
+                segments.containing(key).insert(row);
+            }
+        }
+        digest
+    }
+
+    /// Determine if a segment of `level` starts at `key`.
+    fn splits(key: &K, level: usize) -> bool {
+        (stable_hash(key).trailing_zeros() / FANOUT_BITS) as usize > level
+    }
+
+    /// Hash of the whole table as a single value.
+    pub fn root(&self) -> u64 {
+        self.total.value()
+    }
+
+    /// Hash of the whole table.
+    pub fn hash(&self) -> TableHash {
+        self.total
+    }
+
+    /// Number of levels of the digest. Segments get larger with every level, and there is a
+    /// single segment above the highest level.
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// All segments of `level`, see [segments](Self::segments).
+    pub fn level(&self, level: usize) -> Vec<DigestSegment<K>> {
+        self.segments(level, ..)
+    }
+
+    /// Segments of `level` that start in `range`, including the segment before the first key of
+    /// the level if the range is unbounded at the start. Levels above the highest level consist
+    /// of a single segment for the whole table. Segments that are empty are skipped.
+    pub fn segments(&self, level: usize, range: impl RangeBounds<K>) -> Vec<DigestSegment<K>> {
+        let Some(segments) = self.levels.get(level) else {
+            return match range.start_bound() {
+                Bound::Unbounded if !self.total.is_empty() => Vec::from([DigestSegment {
+                    start: None,
+                    end: None,
+                    hash: self.total,
+                }]),
+                _ => Vec::new(),
+            };
+        };
+        let mut result = Vec::new();
+        if let Bound::Unbounded = range.start_bound() {
+            result.push(DigestSegment {
+                start: None,
+                end: segments.segments.keys().next().cloned(),
+                hash: segments.head,
+            });
+        }
+        let start = range.start_bound().cloned();
+        let mut iter = segments
+            .segments
+            .range((start, Bound::Unbounded))
+            .peekable();
+        while let Some((key, hash)) = iter.next() {
+            if !range.contains(key) {
+                break;
+            }
+            result.push(DigestSegment {
+                start: Some(key.clone()),
+                end: iter.peek().map(|(key, _)| (*key).clone()),
+                hash: *hash,
+            });
+        }
+        result.retain(|segment| !segment.hash.is_empty());
+        result
+    }
+
+    /// Hash of the rows in `range`. This is exact for ranges that start and end at the keys
+    /// of segments of level 0 (such as the ranges returned by [segments](Self::segments) and
+    /// [mismatches](Self::mismatches)), otherwise the segments that start in the range are
+    /// included completely.
+    pub fn digest(&self, range: impl RangeBounds<K>) -> TableHash {
+        self.levels[0].sum(range)
+    }
+
+    /// Find the ranges of primary keys in which this table and `other` differ.
+    ///
+    /// Every range is returned as the key that it starts at and the key that ends it
+    /// (exclusive), where `None` extends the range to the start or end of the table. Keys inside
+    /// a range that are missing from both tables do not affect the result. Instead of the
+    /// digest of another table, this only needs its segments in the differing ranges, which
+    /// can be requested level by level.
+    pub fn mismatches(&self, other: &Self) -> Vec<(Option<K>, Option<K>)> {
+        let mut ranges = Vec::from([(None, None)]);
+        for level in (0..=self.levels.len().max(other.levels.len())).rev() {
+            let mut differing = Vec::new();
+            for (start, end) in &ranges {
+                let range = (
+                    start.clone().map_or(Bound::Unbounded, Bound::Included),
+                    end.clone().map_or(Bound::Unbounded, Bound::Excluded),
+                );
+                let ours = self.segments(level, range.clone());
+                let theirs = other.segments(level, range);
+                let same = |a: &DigestSegment<K>, b: &DigestSegment<K>| {
+                    a.start == b.start && a.hash == b.hash
+                };
+                differing.extend(
+                    ours.iter()
+                        .filter(|segment| !theirs.iter().any(|other| same(segment, other)))
+                        .cloned(),
+                );
+                differing.extend(
+                    theirs
+                        .iter()
+                        .filter(|segment| !ours.iter().any(|other| same(segment, other)))
+                        .cloned(),
+                );
+            }
+            differing.sort_by(|a, b| a.start.cmp(&b.start));
+            ranges = Self::merge(differing);
+        }
+        ranges
+    }
+
+    /// Merge the ranges of overlapping or adjacent segments, which must be sorted by start.
+    fn merge(segments: Vec<DigestSegment<K>>) -> Vec<(Option<K>, Option<K>)> {
+        let mut ranges: Vec<(Option<K>, Option<K>)> = Vec::new();
+        for segment in segments {
+            match ranges.last_mut() {
+                Some((_, end))
+                    if end.as_ref().is_none_or(|end| {
+                        segment.start.as_ref().is_none_or(|start| end >= start)
+                    }) =>
+                {
+                    *end = match (end.take(), segment.end) {
+                        (Some(end), Some(new)) => Some(end.max(new)),
+                        _ => None,
+                    };
+                }
+                _ => ranges.push((segment.start, segment.end)),
+            }
+        }
+        ranges
+    }
+}
+
+impl<K: Ord + Clone + Hash, V: Hash> RowHasher<BTreeMap<K, V>, K, V> for TableDigest<K> {
+    fn insert_row(&mut self, table: &BTreeMap<K, V>, live: fn(&V) -> bool, key: &K, row: &V) {
+        self.total.insert(row);
+        for level in 0..self.levels.len() {
+            if !Self::splits(key, level) {
+                self.levels[level].containing(key).insert(row);
+                continue;
+            }
+            // the rows after the key move to the new segment, up to the next one
+            let next = self.levels[level]
+                .segments
+                .range((Bound::Excluded(key), Bound::Unbounded))
+                .next()
+                .map(|(next, _)| next.clone());
+            let end = next.map_or(Bound::Unbounded, Bound::Excluded);
+            let mut moved = TableHash::default();
+            match level.checked_sub(1) {
+                None => {
+                    let rows = table
+                        .range((Bound::Excluded(key), end.as_ref()))
+                        .map(|(_, row)| row)
+                        .filter(|row| live(row));
+                    rows.for_each(|row| moved.insert(row));
+                    moved.insert(row);
+                }
+                // the level below already contains the row and is split at the key
+                Some(below) => moved = self.levels[below].sum((Bound::Included(key), end.as_ref())),
+            }
+            let segments = &mut self.levels[level];
+            let containing = segments.containing(key);
+            containing.subtract(&moved);
+            containing.insert(row);
+            segments.segments.insert(key.clone(), moved);
+        }
+    }
+
+    fn remove_row(&mut self, _table: &BTreeMap<K, V>, key: &K, row: &V) {
+        self.total.remove(row);
+        for segments in &mut self.levels {
+            match segments.segments.remove(key) {
+                // the rest of the segment is merged into the one before it
+                Some(mut hash) => {
+                    hash.remove(row);
+                    segments.containing(key).add(&hash);
+                }
+                None => segments.containing(key).remove(row),
+            }
+        }
+    }
+
+    fn update_row(&mut self, _table: &BTreeMap<K, V>, key: &K, old: &V, new: &V) {
+        self.total.remove(old);
+        self.total.insert(new);
+        for segments in &mut self.levels {
+            let hash = segments.containing(key);
+            hash.remove(old);
+            hash.insert(new);
+        }
+    }
+}
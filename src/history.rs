@@ -0,0 +1,171 @@
+//! Undo history used by the [history](macro@crate::history) macro.
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::mem;
+
+/// Operation that reverts a change made to a row.
+///
+/// The generated mutation methods of tables with a `history` or `journal` entry record the
+/// inverse of every change they make. The [history](macro@crate::history) macro replays it
+/// through the normal checked methods to undo the change, while the
+/// [journal](macro@crate::journal) macro puts the row back exactly as it was.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Inverse<T> {
+    /// Insert the row again, which undoes a delete.
+    Insert(T),
+    /// Update the row back to this version, which undoes an update.
+    Update(T),
+    /// Delete the row, which undoes an insert.
+    Delete(T),
+    /// Restore the soft deleted row back to this version, which undoes a soft delete.
+    Restore(T),
+    /// Soft delete the row again, back to this version, which undoes a restore.
+    SoftDelete(T),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Record the inverses of changes into the current action.
+    Record,
+    /// Collect the inverses of the changes made while undoing or redoing an action.
+    Replay,
+    /// Do not record anything, while rolling back an action that failed.
+    Ignore,
+}
+
+/// History of the changes made to a database, which can be undone and redone.
+///
+/// The history is stored as a field of the database struct, and declared as the sink of a
+/// `history` entry of every table whose changes it records. The inverses of the changes are
+/// grouped into actions: all changes made until [commit](History::commit) is called form a
+/// single action, which is undone and redone as a whole.
+#[derive(Clone, Debug)]
+pub struct History<R> {
+    undo: VecDeque<Vec<R>>,
+    redo: Vec<Vec<R>>,
+    current: Vec<R>,
+    replayed: Vec<R>,
+    mode: Mode,
+    limit: Option<usize>,
+}
+
+impl<R> History<R> {
+    /// Create an empty history that keeps all actions.
+    pub fn new() -> Self {
+        History {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            current: Vec::new(),
+            replayed: Vec::new(),
+            mode: Mode::Record,
+            limit: None,
+        }
+    }
+
+    /// Only keep the last `actions` actions, dropping older ones.
+    pub fn with_limit(mut self, actions: usize) -> Self {
+        self.limit = Some(actions);
+        self.trim();
+        self
+    }
+
+    /// End the current action, so that the next change starts a new one.
+    pub fn commit(&mut self) {
+        if !self.current.is_empty() {
+            let action = mem::take(&mut self.current);
+            self.push_undo(action);
+        }
+    }
+
+    /// Determine if there is an action that can be undone.
+    pub fn can_undo(&self) -> bool {
+        !self.current.is_empty() || !self.undo.is_empty()
+    }
+
+    /// Determine if there is an action that can be redone.
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Forget all actions.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.current.clear();
+    }
+
+    fn push_undo(&mut self, action: Vec<R>) {
+        self.undo.push_back(action);
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        if let Some(limit) = self.limit {
+            while self.undo.len() > limit {
+                self.undo.pop_front();
+            }
+        }
+    }
+
+    /// Record the inverse of a change.
+    #[doc(hidden)]
+    pub fn record<T>(&mut self, inverse: Inverse<T>)
+    where
+        R: From<Inverse<T>>,
+    {
+        match self.mode {
+            Mode::Record => {
+                self.current.push(inverse.into());
+                self.redo.clear();
+            }
+            Mode::Replay => self.replayed.push(inverse.into()),
+            Mode::Ignore => {}
+        }
+    }
+
+    /// Take the last action to undo it.
+    #[doc(hidden)]
+    pub fn begin_undo(&mut self) -> Option<Vec<R>> {
+        self.commit();
+        let action = self.undo.pop_back()?;
+        self.mode = Mode::Replay;
+        Some(action)
+    }
+
+    /// Take the last undone action to redo it.
+    #[doc(hidden)]
+    pub fn begin_redo(&mut self) -> Option<Vec<R>> {
+        self.commit();
+        let action = self.redo.pop()?;
+        self.mode = Mode::Replay;
+        Some(action)
+    }
+
+    /// Take the inverses recorded while replaying an action that failed, to roll it back.
+    #[doc(hidden)]
+    pub fn begin_rollback(&mut self) -> Vec<R> {
+        self.mode = Mode::Ignore;
+        mem::take(&mut self.replayed)
+    }
+
+    /// Finish replaying `action`. If it succeeded, the inverses recorded while replaying it
+    /// become the action that reverts it, otherwise it is put back.
+    #[doc(hidden)]
+    pub fn finish(&mut self, action: Vec<R>, undo: bool, success: bool) {
+        self.mode = Mode::Record;
+        let action = match success {
+            true => mem::take(&mut self.replayed),
+            false => action,
+        };
+        match undo != success {
+            true => self.push_undo(action),
+            false => self.redo.push(action),
+        }
+    }
+}
+
+impl<R> Default for History<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,117 @@
+//! Journal of changes used by the [journal](macro@crate::journal) macro to roll back to
+//! savepoints.
+use crate::Inverse;
+use alloc::vec::Vec;
+
+/// Handle of a savepoint, returned when it is created.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Savepoint {
+    id: u64,
+}
+
+/// Journal of the inverses of the changes made since the oldest savepoint that was not
+/// released.
+///
+/// The journal is stored as a field of the database struct, and declared as the sink of a
+/// `journal` entry of every table whose changes can be rolled back. Changes are only recorded
+/// while there is a savepoint, so the journal does not grow otherwise.
+#[derive(Clone, Debug)]
+pub struct Journal<R> {
+    entries: Vec<R>,
+    /// Identifier and position in the entries of every savepoint, oldest first.
+    savepoints: Vec<(u64, usize)>,
+    next: u64,
+    rolling_back: bool,
+}
+
+impl<R> Journal<R> {
+    /// Create an empty journal without savepoints.
+    pub fn new() -> Self {
+        Journal {
+            entries: Vec::new(),
+            savepoints: Vec::new(),
+            next: 0,
+            rolling_back: false,
+        }
+    }
+
+    /// Create a savepoint, which changes made from now on can be rolled back to.
+    pub fn savepoint(&mut self) -> Savepoint {
+        let id = self.next;
+        self.next += 1;
+        self.savepoints.push((id, self.entries.len()));
+        Savepoint { id }
+    }
+
+    /// Release a savepoint and all savepoints created after it, keeping the changes made since.
+    ///
+    /// Panics if the savepoint was already released.
+    pub fn release(&mut self, savepoint: Savepoint) {
+        let index = self.find(savepoint);
+        self.savepoints.truncate(index);
+        if self.savepoints.is_empty() {
+            self.entries.clear();
+        }
+    }
+
+    /// Number of savepoints that were not released.
+    pub fn depth(&self) -> usize {
+        self.savepoints.len()
+    }
+
+    /// Number of changes that can be rolled back.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Determine if no changes can be rolled back.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Determine if changes are being recorded, which is the case while there is a savepoint.
+    pub fn is_active(&self) -> bool {
+        !self.savepoints.is_empty() && !self.rolling_back
+    }
+
+    fn find(&self, savepoint: Savepoint) -> usize {
+        self.savepoints
+            .iter()
+            .position(|(id, _)| *id == savepoint.id)
+            .expect("savepoint was released")
+    }
+
+    /// Record the inverse of a change.
+    #[doc(hidden)]
+    pub fn record<T>(&mut self, inverse: Inverse<T>)
+    where
+        R: From<Inverse<T>>,
+    {
+        if self.is_active() {
+            self.entries.push(inverse.into());
+        }
+    }
+
+    /// Take the inverses of the changes made since the savepoint, discarding the savepoints
+    /// created after it.
+    #[doc(hidden)]
+    pub fn begin_rollback(&mut self, savepoint: Savepoint) -> Vec<R> {
+        let index = self.find(savepoint);
+        let position = self.savepoints[index].1;
+        self.savepoints.truncate(index + 1);
+        self.rolling_back = true;
+        self.entries.split_off(position)
+    }
+
+    /// Finish rolling back, recording changes again.
+    #[doc(hidden)]
+    pub fn finish_rollback(&mut self) {
+        self.rolling_back = false;
+    }
+}
+
+impl<R> Default for Journal<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -56,10 +56,165 @@
 //! ```
 //!
 //! See the documentation on [table](macro@table) for more information.
+//!
+//! ## Features
+//!
+//! This crate is `no_std` compatible, it only requires `alloc`. The `std` feature, which is
+//! enabled by default, adds support for the [HashMap][std::collections::HashMap] and
+//! [HashSet][std::collections::HashSet] types from the standard library. Without it,
+//! [BTreeMap][alloc::collections::BTreeMap] and [BTreeSet][alloc::collections::BTreeSet] from
+//! `alloc` can be used.
+//!
+//! The `serde` feature enables the [snapshot](macro@snapshot) macro, which saves and loads the
+//! tables of a database using [serde](https://serde.rs), and the [delta](macro@delta) macro,
+//! which saves and applies only the rows that were changed since a previous snapshot. The `wal` feature (which implies
+//! `std` and `serde`) enables the [wal](macro@wal) macro and the [Wal] type, which persist a
+//! database to disk using snapshots and a write-ahead log. The `rkyv` feature (which implies
+//! `std`) enables the [archived_table](macro@archived_table) macro and the [MappedArchive]
+//! type, which memory-map a read-only database archived with [rkyv](https://rkyv.org).
+#![no_std]
 #![macro_use]
 
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "rkyv")]
+mod archive;
+mod change;
+mod delta;
+mod diff;
+mod error;
+mod hash;
+mod history;
+mod journal;
+mod map;
+mod replication;
+#[cfg(feature = "serde")]
+mod snapshot;
+mod tombstone;
+#[cfg(feature = "wal")]
+mod wal;
+
+#[doc(hidden)]
+#[cfg(feature = "rkyv")]
+pub use archive::ArchivedTombstone;
+#[cfg(feature = "rkyv")]
+pub use archive::{ArchivedTableMap, MappedArchive};
+pub use change::{Change, ChangeSink};
+pub use delta::DirtySet;
+#[doc(hidden)]
+#[cfg(feature = "serde")]
+pub use delta::{DeltaRows, DeltaTable};
+pub use diff::TableDiff;
+pub use error::ErrorKind;
+pub use hash::{DigestSegment, RowHasher, TableDigest, TableHash};
+pub use history::{History, Inverse};
+pub use journal::{Journal, Savepoint};
+pub use map::{IndexSet, MultiMap, OrderedMap, TableMap};
 /// Re-expport of paste, which is used internally.
 pub use paste::paste;
+pub use replication::{ChangeLog, ReplicationError, Sequenced};
+#[cfg(feature = "serde")]
+pub use snapshot::SnapshotError;
+#[doc(hidden)]
+#[cfg(feature = "serde")]
+pub use snapshot::SnapshotRows;
+pub use tombstone::Tombstone;
+#[doc(hidden)]
+#[cfg(feature = "wal")]
+pub use wal::WalPending;
+#[cfg(feature = "wal")]
+pub use wal::{SyncPolicy, Wal, WalCodec, WalCommitter, WalError, WalRecovery};
+
+#[doc(hidden)]
+pub use alloc::{string::String, vec::Vec};
+#[doc(hidden)]
+#[cfg(feature = "rkyv")]
+pub use rkyv;
+#[doc(hidden)]
+#[cfg(feature = "serde")]
+pub use serde;
+
+/// Run a check, adding its error to `errors` if it fails. Used by the validation methods, which
+/// collect all errors instead of stopping at the first one.
+#[doc(hidden)]
+pub fn collect_error<E>(errors: &mut Vec<E>, check: impl FnOnce() -> Result<(), E>) {
+    if let Err(error) = check() {
+        errors.push(error);
+    }
+}
+
+/// Determine if `prop` is declared `immutable` in a table's entries, given as pairs of the
+/// stringified entry type and property. Used to skip the index updates for those fields.
+#[doc(hidden)]
+pub const fn is_immutable(entries: &[(&str, &str)], prop: &str) -> bool {
+    const fn equal(left: &[u8], right: &[u8]) -> bool {
+        if left.len() != right.len() {
+            return false;
+        }
+        let mut index = 0;
+        while index < left.len() {
+            if left[index] != right[index] {
+                return false;
+            }
+            index += 1;
+        }
+        true
+    }
+    let mut index = 0;
+    while index < entries.len() {
+        let (itype, field) = entries[index];
+        if equal(itype.as_bytes(), b"immutable") && equal(field.as_bytes(), prop.as_bytes()) {
+            return true;
+        }
+        index += 1;
+    }
+    false
+}
+
+/// Range over all string keys that start with a given prefix, used by [table_prefix].
+///
+/// The range is only bounded from below, callers need to stop iterating once a key no longer
+/// starts with the prefix.
+#[doc(hidden)]
+pub struct PrefixRange<'a>(pub &'a str);
+
+impl<'a> core::ops::RangeBounds<str> for PrefixRange<'a> {
+    fn start_bound(&self) -> core::ops::Bound<&str> {
+        core::ops::Bound::Included(self.0)
+    }
+
+    fn end_bound(&self) -> core::ops::Bound<&str> {
+        core::ops::Bound::Unbounded
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_index_rows {
+    ($self:expr, $table:ident, index, $name:ident, $iter:expr) => {
+        $iter
+            .flat_map(|(_, ids)| $crate::IndexSet::iter(ids))
+            .map(move |id| $crate::table_index_row!($self, $table, $name, id))
+    };
+    ($self:expr, $table:ident, unique, $name:ident, $iter:expr) => {
+        $iter.map(move |(_, id)| $crate::table_index_row!($self, $table, $name, id))
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_index_row {
+    ($self:expr, $table:ident, $name:ident, $id:expr) => {
+        $crate::TableMap::get(&$self.$table, $id).expect(concat!(
+            stringify!($name),
+            " index points to missing ",
+            stringify!($table),
+            " row"
+        ))
+    };
+}
 
 #[doc(hidden)]
 #[macro_export]
@@ -67,9 +222,7 @@ macro_rules! table_next_id {
     ($table:ident: $type:ty) => {
         $crate::paste! {
             pub fn [<$table _next_id>](&self) -> $type {
-                self.$table
-                    .keys()
-                    .max()
+                $crate::TableMap::max_key(&self.$table)
                     .map(|key| *key + 1)
                     .unwrap_or_default()
             }
@@ -77,15 +230,75 @@ macro_rules! table_next_id {
     };
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_error {
+    ((|$row:tt $(: $rowty:ty)?, $key:tt $(: $keyty:ty)?| $body:expr), $rowexpr:expr, $keyexpr:expr) => {{
+        let $row $(: $rowty)? = $rowexpr;
+        let $key $(: $keyty)? = $keyexpr;
+        $body
+    }};
+    ((|$key:tt $(: $keyty:ty)?| $body:expr), $keyexpr:expr) => {{
+        let $key $(: $keyty)? = $keyexpr;
+        $body
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_row {
+    ($self:expr, $table:ident, $id:expr) => {
+        $crate::paste! {
+            $crate::TableMap::get(&$self.$table, $id).filter(|row| Self::[<$table _is_live>](row))
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_live {
+    ($table:ident: $type:ty, []) => {
+        $crate::paste! {
+            #[doc(hidden)]
+            pub fn [<$table _is_live>](_row: &$type) -> bool {
+                true
+            }
+        }
+    };
+    ($table:ident: $type:ty, [$field:ident $value:tt]) => {
+        $crate::paste! {
+            #[doc(hidden)]
+            pub fn [<$table _is_live>](row: &$type) -> bool {
+                !$crate::Tombstone::is_deleted(&row.$field)
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_get {
+    ($table:ident: $type:ty, $pkty:ty) => {
+        $crate::paste! {
+            pub fn [<$table _get>](&self, id: &$pkty) -> Option<&$type> {
+                $crate::table_row!(self, $table, id)
+            }
+        }
+    };
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_insert {
-    ($table:ident: $type:ty, $pk:ident, $errty:ty) => {
+    ($table:ident: $type:ty, $pk:ident => $missing:tt, $errty:ty, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
         $crate::paste! {
-            pub fn [<$table _insert>](&mut self, data: $type) -> Result<(), $errty> {
-                self.[<$table _insert_check>](&data)?;
+            pub fn [<$table _insert>](&mut self, #[allow(unused_mut)] mut data: $type) -> Result<(), $errty> {
+                $($crate::table_before_insert!(self, $itype, $name, data);)*
+                self.[<$table _can_insert>](&data)?;
+                $crate::table_emit!(self, $table, $pk, $crate::Change::Inserted(&data), error => return Err(error), $($itype $name $err),*);
                 self.[<$table _insert_indices>](&data);
-                self.$table.insert(data.$pk.clone(), data);
+                $($crate::table_record!(self, $itype, $name, $crate::Inverse::Delete(data.clone()));)*
+                $crate::TableMap::insert(&mut self.$table, data.$pk.clone(), data);
                 Ok(())
             }
         }
@@ -95,35 +308,42 @@ macro_rules! table_insert {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_insert_check {
-    ($self:expr, foreign, $table:ident, $data:ident, $expr:expr, $err:expr) => {
-        if $self.$table.get(&$expr).is_none() {
-            return Err($err);
+    ($self:expr, foreign, $table:ident, $data:ident, $expr:expr, $err:tt) => {
+        if $crate::TableMap::get(&$self.$table, &$expr).is_none() {
+            return Err($crate::table_error!($err, $data, &$expr));
         }
     };
-    ($self:expr, unique, $table:ident, $data:ident, $expr:expr, $err:expr) => {
-        if $self.$table.get(&$expr).is_some() {
-            return Err($err);
+    ($self:expr, live_foreign, $table:ident, $data:ident, $expr:expr, $err:tt) => {
+        if $crate::table_row!($self, $table, &$expr).is_none() {
+            return Err($crate::table_error!($err, $data, &$expr));
         }
     };
-    ($self:expr, primary, $table:ident, $data:ident, $expr:expr, $err:expr) => {
-        if $self.$table.get(&$expr).is_some() {
-            return Err($err);
+    ($self:expr, unique, $table:ident, $data:ident, $expr:expr, $err:tt) => {
+        if let Some(existing) = $crate::TableMap::get(&$self.$table, &$expr) {
+            return Err($crate::table_error!($err, $data, existing));
         }
     };
-    ($self:expr, constraint, $table:ident, $data:ident, $expr:expr, $err:expr) => {
-        if let Err(error) = $self.$table($data) {
-            return Err(error);
+    ($self:expr, primary, $table:ident, $data:ident, $expr:expr, $err:tt) => {
+        if $crate::TableMap::get(&$self.$table, &$expr).is_some() {
+            return Err($crate::table_error!($err, $data, &$expr));
         }
     };
-    ($self:expr, $other:ident, $table:ident, $data:ident, $expr:expr, $err:expr) => {};
+    ($self:expr, constraint, $table:ident, $data:ident, $expr:expr, $err:tt) => {
+        $self.$table($data)?;
+    };
+    ($self:expr, $other:ident, $table:ident, $data:ident, $expr:expr, $err:tt) => {};
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_insert_checks {
-    ($table:ident: $type:ty, $errty:ty, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
+    ($table:ident: $type:ty, $pk:ident, $errty:ty, $missing:tt, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
         $crate::paste! {
-            fn [<$table _insert_check>](&mut self, data: &$type) -> Result<(), $errty> {
+            pub fn [<$table _can_insert>](&self, data: &$type) -> Result<(), $errty> {
+                // rows are deleted through the delete method, so a deleted row cannot be inserted
+                if !Self::[<$table _is_live>](data) {
+                    return Err($crate::table_error!($missing, &data.$pk));
+                }
                 $($crate::table_insert_check!(self, $itype, $name, data, $crate::table_prop!(data, $prop), $err);)*
                 Ok(())
             }
@@ -134,42 +354,496 @@ macro_rules! table_insert_checks {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_delete {
-    ($table:ident: $type:ty, $pk:ty, $errty:ty) => {
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $missing:tt, [], $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
         $crate::paste! {
-            pub fn [<$table _delete>](&mut self, id: $pk) -> Result<$type, $errty> {
-                let data = self.[<$table _delete_check>](id.clone())?;
+            pub fn [<$table _delete>](&mut self, id: $pkty) -> Result<$type, $errty> {
+                self.[<$table _can_delete>](&id)?;
+                let row = $crate::TableMap::get(&self.$table, &id)
+                    .expect(concat!(stringify!($table), " row missing"));
+                $crate::table_emit!(self, $table, $pk, $crate::Change::Deleted(row), error => return Err(error), $($itype $name $err),*);
+                let data = $crate::TableMap::remove(&mut self.$table, &id)
+                    .expect(concat!(stringify!($table), " row missing"));
                 self.[<$table _delete_indices>](&data);
-                self.$table.remove(&id);
+                $($crate::table_record!(self, $itype, $name, $crate::Inverse::Insert(data.clone()));)*
+                $($crate::table_after_delete!(self, $itype, $name, data);)*
+                Ok(data)
+            }
+
+            /// Revert a change by replaying its inverse through the checked methods.
+            #[doc(hidden)]
+            pub fn [<$table _revert>](&mut self, inverse: $crate::Inverse<$type>) -> Result<(), $errty> {
+                match inverse {
+                    $crate::Inverse::Insert(row) => self.[<$table _insert>](row),
+                    $crate::Inverse::Update(row) => {
+                        let row = self.[<$table _rebase>](row);
+                        self.[<$table _update>](row).map(drop)
+                    }
+                    $crate::Inverse::Delete(row) => self.[<$table _delete>](row.$pk).map(drop),
+                    $crate::Inverse::Restore(_) | $crate::Inverse::SoftDelete(_) => {
+                        unreachable!(concat!(stringify!($table), " rows are not soft deleted"))
+                    }
+                }
+            }
+        }
+    };
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $missing:tt, [$field:ident $value:tt], $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
+        $crate::paste! {
+            pub fn [<$table _delete>](&mut self, id: $pkty) -> Result<$type, $errty> {
+                self.[<$table _can_delete>](&id)?;
+                let row = $crate::TableMap::get(&self.$table, &id)
+                    .expect(concat!(stringify!($table), " row missing"));
+                let mut data = row.clone();
+                data.$field = $crate::table_error!($value, row, &id);
+                $crate::table_emit!(self, $table, $pk, $crate::Change::Deleted(&data), error => return Err(error), $($itype $name $err),*);
+                $($crate::table_delete_index!(self, $table, row.$pk, $itype, $name, $crate::table_prop!(row, $prop));)*
+                let row = $crate::TableMap::get_mut(&mut self.$table, &id)
+                    .expect(concat!(stringify!($table), " row missing"));
+                let old = ::core::mem::replace(row, data.clone());
+                $($crate::table_record!(self, $itype, $name, $crate::Inverse::Restore(old.clone()));)*
+                $($crate::table_after_delete!(self, $itype, $name, data);)*
+                Ok(data)
+            }
+
+            pub fn [<$table _restore>](&mut self, id: $pkty) -> Result<(), $errty> {
+                let row = match $crate::TableMap::get_mut(&mut self.$table, &id) {
+                    Some(row) if !Self::[<$table _is_live>](row) => row,
+                    _ => return Err($crate::table_error!($missing, &id)),
+                };
+                let tombstone = ::core::mem::replace(&mut row.$field, $crate::Tombstone::restored());
+                let row = $crate::TableMap::get(&self.$table, &id)
+                    .expect(concat!(stringify!($table), " row missing"));
+                if let Err(error) = self.[<$table _restore_check>](row) {
+                    if let Some(row) = $crate::TableMap::get_mut(&mut self.$table, &id) {
+                        row.$field = tombstone;
+                    }
+                    return Err(error);
+                }
+                $crate::table_emit!(self, $table, $pk, $crate::Change::Inserted(row), error => {
+                    if let Some(row) = $crate::TableMap::get_mut(&mut self.$table, &id) {
+                        row.$field = tombstone;
+                    }
+                    return Err(error);
+                }, $($itype $name $err),*);
+                let row = $crate::TableMap::get(&self.$table, &id)
+                    .expect(concat!(stringify!($table), " row missing"));
+                $($crate::table_insert_index!(self, $table, row.$pk, $itype, $name, $crate::table_prop!(row, $prop));)*
+                $($crate::table_record!(self, $itype, $name, $crate::Inverse::SoftDelete({
+                    let mut old = row.clone();
+                    old.$field = tombstone.clone();
+                    old
+                }));)*
+                Ok(())
+            }
+
+            pub fn [<$table _purge>](&mut self, id: $pkty) -> Result<$type, $errty> {
+                let row = match $crate::TableMap::get(&self.$table, &id) {
+                    Some(row) if !Self::[<$table _is_live>](row) => row,
+                    _ => return Err($crate::table_error!($missing, &id)),
+                };
+                // live rows of other tables can still reference a deleted row
+                self.[<$table _delete_check>](row)?;
+                $crate::table_emit!(self, $table, $pk, $crate::Change::Deleted(row), error => return Err(error), $($itype $name $err),*);
+                let data = $crate::TableMap::remove(&mut self.$table, &id)
+                    .expect(concat!(stringify!($table), " row missing"));
+                $($crate::table_record!(self, $itype, $name, $crate::Inverse::Insert(data.clone()));)*
                 Ok(data)
             }
+
+            /// Revert a change by replaying its inverse through the checked methods.
+            #[doc(hidden)]
+            pub fn [<$table _revert>](&mut self, inverse: $crate::Inverse<$type>) -> Result<(), $errty> {
+                match inverse {
+                    // purged rows are loaded back as they were, since they cannot be inserted
+                    $crate::Inverse::Insert(row) if !Self::[<$table _is_live>](&row) => {
+                        self.[<$table _load>](row.clone())?;
+                        $($crate::table_record!(self, $itype, $name, $crate::Inverse::Delete(row.clone()));)*
+                        Ok(())
+                    }
+                    $crate::Inverse::Insert(row) => self.[<$table _insert>](row),
+                    $crate::Inverse::Update(row) => {
+                        let row = self.[<$table _rebase>](row);
+                        self.[<$table _update>](row).map(drop)
+                    }
+                    // inserted rows are purged, so that they can be inserted again
+                    $crate::Inverse::Delete(row) => {
+                        if Self::[<$table _is_live>](&row) {
+                            self.[<$table _delete>](row.$pk.clone())?;
+                        }
+                        self.[<$table _purge>](row.$pk).map(drop)
+                    }
+                    $crate::Inverse::Restore(row) => self.[<$table _restore>](row.$pk),
+                    $crate::Inverse::SoftDelete(row) => self.[<$table _delete>](row.$pk).map(drop),
+                }
+            }
+
+            fn [<$table _restore_check>](&self, data: &$type) -> Result<(), $errty> {
+                $($crate::table_restore_check!(self, $itype, $name, data, $crate::table_prop!(data, $prop), $err);)*
+                Ok(())
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_load {
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
+        $crate::paste! {
+            /// Insert a row loaded from storage, checking it and adding it to the indices without
+            /// running hooks or emitting changes. Deleted rows only have their primary key checked.
+            #[doc(hidden)]
+            pub fn [<$table _load>](&mut self, data: $type) -> Result<(), $errty> {
+                if Self::[<$table _is_live>](&data) {
+                    self.[<$table _can_insert>](&data)?;
+                    self.[<$table _insert_indices>](&data);
+                } else {
+                    let data = &data;
+                    $($crate::table_load_check!(self, $itype, $name, data, $crate::table_prop!(data, $prop), $err);)*
+                }
+                $crate::TableMap::insert(&mut self.$table, data.$pk.clone(), data);
+                Ok(())
+            }
+
+            /// Store a row loaded from storage, only checking its primary key. The row is
+            /// checked and added to the indices by the load stored method once all rows are
+            /// stored, so that it can reference rows that are stored after it.
+            #[doc(hidden)]
+            pub fn [<$table _store>](&mut self, data: $type) -> Result<$pkty, $errty> {
+                {
+                    let data = &data;
+                    $($crate::table_load_check!(self, $itype, $name, data, $crate::table_prop!(data, $prop), $err);)*
+                    // hashes are kept consistent with the stored rows, see table_store_index
+                    if Self::[<$table _is_live>](data) {
+                        $($crate::table_store_index!(self, $table, data.$pk, $itype, $name, $crate::table_prop!(data, $prop), insert);)*
+                    }
+                }
+                let id = data.$pk.clone();
+                $crate::TableMap::insert(&mut self.$table, id.clone(), data);
+                Ok(id)
+            }
+
+            /// Check a stored row like an insert would, and add it to the indices.
+            #[doc(hidden)]
+            pub fn [<$table _load_stored>](&mut self, id: &$pkty) -> Result<(), $errty> {
+                let data = $crate::TableMap::get(&self.$table, id).expect(concat!(stringify!($table), " row not stored"));
+                if !Self::[<$table _is_live>](data) {
+                    return Ok(());
+                }
+                $($crate::table_restore_check!(self, $itype, $name, data, $crate::table_prop!(data, $prop), $err);)*
+                // the row is taken out while its indices are added, and put back in its place
+                let data = $crate::TableMap::remove(&mut self.$table, id).expect(concat!(stringify!($table), " row not stored"));
+                $($crate::table_store_index!(self, $table, data.$pk, $itype, $name, $crate::table_prop!(data, $prop), remove);)*
+                self.[<$table _insert_indices>](&data);
+                $crate::TableMap::insert(&mut self.$table, id.clone(), data);
+                Ok(())
+            }
+
+            /// Remove a row and its index entries without any checks, used while replacing
+            /// rows from storage.
+            #[doc(hidden)]
+            pub fn [<$table _unload>](&mut self, id: &$pkty) -> Option<$type> {
+                let data = $crate::TableMap::remove(&mut self.$table, id)?;
+                if Self::[<$table _is_live>](&data) {
+                    $($crate::table_unload_index!(self, $table, data.$pk, $itype, $name, $crate::table_prop!(data, $prop));)*
+                }
+                Some(data)
+            }
+
+            /// Check that a row which was unloaded and not replaced could have been deleted.
+            #[doc(hidden)]
+            pub fn [<$table _check_unloaded>](&self, data: &$type) -> Result<(), $errty> {
+                match $crate::TableMap::get(&self.$table, &data.$pk) {
+                    None if Self::[<$table _is_live>](data) => self.[<$table _delete_check>](data),
+                    _ => Ok(()),
+                }
+            }
+
+            /// Put back the row of an inverse exactly as it is (or remove the row, for a delete),
+            /// used to roll back changes. Nothing is checked and no hooks are run, but the change
+            /// is emitted and recorded so that sinks and the history follow the rollback. Errors
+            /// of sinks are ignored, since the rollback cannot be aborted.
+            #[doc(hidden)]
+            pub fn [<$table _reset>](&mut self, inverse: $crate::Inverse<$type>) {
+                let row = match inverse {
+                    $crate::Inverse::Delete(row) => Err(row.$pk),
+                    $crate::Inverse::Insert(row)
+                    | $crate::Inverse::Update(row)
+                    | $crate::Inverse::Restore(row)
+                    | $crate::Inverse::SoftDelete(row) => Ok(row),
+                };
+                let id = match &row {
+                    Ok(row) => row.$pk.clone(),
+                    Err(id) => id.clone(),
+                };
+                let current = $crate::TableMap::get(&self.$table, &id);
+                let changes = match (current, row.as_ref().ok()) {
+                    (Some(old), Some(new)) => match (Self::[<$table _is_live>](old), Self::[<$table _is_live>](new)) {
+                        (true, true) => [Some($crate::Change::Updated { old, new }), None],
+                        (true, false) => [Some($crate::Change::Deleted(new)), None],
+                        (false, true) => [Some($crate::Change::Inserted(new)), None],
+                        (false, false) => [Some($crate::Change::Deleted(old)), Some($crate::Change::Inserted(new))],
+                    },
+                    (Some(old), None) => [Some($crate::Change::Deleted(old)), None],
+                    (None, Some(new)) => [Some($crate::Change::Inserted(new)), None],
+                    (None, None) => [None, None],
+                };
+                for change in changes.into_iter().flatten() {
+                    $crate::table_emit!(self, $table, $pk, change, error => drop(error), $($itype $name $err),*);
+                }
+                // the inverses of the reset are recorded like those of any other change
+                let inverses = match (self.[<$table _unload>](&id), row.as_ref().ok()) {
+                    (Some(old), Some(new)) => match (Self::[<$table _is_live>](&old), Self::[<$table _is_live>](new)) {
+                        (true, true) => [Some($crate::Inverse::Update(old)), None],
+                        (true, false) => [Some($crate::Inverse::Restore(old)), None],
+                        (false, true) => [Some($crate::Inverse::SoftDelete(old)), None],
+                        (false, false) => [Some($crate::Inverse::Insert(old)), Some($crate::Inverse::Delete(new.clone()))],
+                    },
+                    (Some(old), None) => [Some($crate::Inverse::Insert(old)), None],
+                    (None, Some(new)) => [Some($crate::Inverse::Delete(new.clone())), None],
+                    (None, None) => [None, None],
+                };
+                if let Ok(row) = row {
+                    if Self::[<$table _is_live>](&row) {
+                        self.[<$table _insert_indices>](&row);
+                    }
+                    $crate::TableMap::insert(&mut self.$table, id, row);
+                }
+                for inverse in inverses.into_iter().flatten() {
+                    $($crate::table_record!(self, $itype, $name, inverse.clone());)*
+                }
+            }
+        }
+    };
+}
+
+/// Hashes of stored rows, which are added when the row is stored, and removed again right
+/// before the row is added to the indices. Hashes that depend on the other rows of the table
+/// (such as a [TableDigest]) are thereby always consistent with the rows in the table.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_store_index {
+    ($self:expr, $table:ident, $pk:expr, hash, $name:ident, $row:expr, insert) => {
+        $crate::table_insert_index!($self, $table, $pk, hash, $name, $row);
+    };
+    ($self:expr, $table:ident, $pk:expr, hash, $name:ident, $row:expr, remove) => {
+        $crate::table_delete_index!($self, $table, $pk, hash, $name, $row);
+    };
+    ($self:expr, $table:ident, $pk:expr, $other:ident, $name:ident, $row:expr, $op:ident) => {};
+}
+
+/// Index removal used when unloading rows. Reverse indices are not checked, since the rows
+/// referencing an unloaded row may be replaced as well.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_unload_index {
+    ($self:expr, $table:ident, $pk:expr, reverse, $name:ident, $prop:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, $other:ident, $name:ident, $prop:expr) => {
+        $crate::table_delete_index!($self, $table, $pk, $other, $name, $prop);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_apply {
+    ($table:ident: $type:ty, $pk:ident => $missing:tt, $errty:ty, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
+        $crate::paste! {
+            /// Apply a change that was emitted by this table, for example when replaying a log.
+            /// The change is checked and the indices are updated, but hooks are not run and no
+            /// changes are emitted.
+            #[doc(hidden)]
+            pub fn [<$table _apply>](&mut self, change: $crate::Change<$type>) -> Result<(), $errty> {
+                $($crate::table_mark!(self, $itype, $name, &change.row().$pk);)*
+                match change {
+                    $crate::Change::Inserted(data) => {
+                        // restoring a deleted row replaces its tombstoned version
+                        let deleted = match $crate::TableMap::get(&self.$table, &data.$pk) {
+                            Some(row) if !Self::[<$table _is_live>](row) => $crate::TableMap::remove(&mut self.$table, &data.$pk),
+                            _ => None,
+                        };
+                        let id = data.$pk.clone();
+                        if let Err(error) = self.[<$table _load>](data) {
+                            if let Some(deleted) = deleted {
+                                $crate::TableMap::insert(&mut self.$table, id, deleted);
+                            }
+                            return Err(error);
+                        }
+                        Ok(())
+                    }
+                    $crate::Change::Updated { new, .. } => {
+                        let old = match $crate::table_row!(self, $table, &new.$pk).filter(|_| Self::[<$table _is_live>](&new)) {
+                            Some(value) => value,
+                            None => return Err($crate::table_error!($missing, &new.$pk)),
+                        };
+                        $($crate::table_apply_check!(self, old.$pk, $itype, $name, old, (&new), $crate::table_prop!(old, $prop), $crate::table_prop!(new, $prop), $err);)*
+                        $crate::table_update_indices!(self, $table, old, new, $pk, $($itype $name $prop => $err),*);
+                        let slot = $crate::TableMap::get_mut(&mut self.$table, &new.$pk)
+                            .expect(concat!(stringify!($table), " row missing"));
+                        *slot = new;
+                        Ok(())
+                    }
+                    $crate::Change::Deleted(data) => {
+                        match $crate::TableMap::get(&self.$table, &data.$pk) {
+                            // purging a row that was already deleted
+                            Some(row) if !Self::[<$table _is_live>](row) => {
+                                $crate::TableMap::remove(&mut self.$table, &data.$pk);
+                                return Ok(());
+                            }
+                            Some(_) => {}
+                            None => return Err($crate::table_error!($missing, &data.$pk)),
+                        }
+                        self.[<$table _can_delete>](&data.$pk)?;
+                        let row = $crate::TableMap::remove(&mut self.$table, &data.$pk)
+                            .expect(concat!(stringify!($table), " row missing"));
+                        self.[<$table _delete_indices>](&row);
+                        // soft deletion keeps the row with its tombstone set
+                        if !Self::[<$table _is_live>](&data) {
+                            $crate::TableMap::insert(&mut self.$table, data.$pk.clone(), data);
+                        }
+                        Ok(())
+                    }
+                }
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_apply_change {
+    ($table:ident: $type:ty, $pk:ident => $missing:tt, $errty:ty, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
+        $crate::paste! {
+            /// Apply a change that was replicated from another database. The change was already
+            /// checked by the database that made it, so only the primary key and the `unique`
+            /// indices are checked (which a diverged database can violate) before the indices
+            /// are updated, without running hooks or emitting changes.
+            #[doc(hidden)]
+            pub fn [<$table _apply_change>](&mut self, change: $crate::Change<$type>) -> Result<(), $errty> {
+                $($crate::table_mark!(self, $itype, $name, &change.row().$pk);)*
+                match change {
+                    $crate::Change::Inserted(data) => {
+                        // restoring a deleted row replaces its tombstoned version
+                        let deleted = $crate::TableMap::get(&self.$table, &data.$pk).is_some_and(|row| !Self::[<$table _is_live>](row));
+                        {
+                            let data = &data;
+                            if !deleted {
+                                $($crate::table_load_check!(self, $itype, $name, data, $crate::table_prop!(data, $prop), $err);)*
+                            }
+                            if Self::[<$table _is_live>](data) {
+                                $($crate::table_replica_check!(insert self, $itype, $name, data, $crate::table_prop!(data, $prop), $err);)*
+                            }
+                        }
+                        if deleted {
+                            $crate::TableMap::remove(&mut self.$table, &data.$pk);
+                        }
+                        if Self::[<$table _is_live>](&data) {
+                            self.[<$table _insert_indices>](&data);
+                        }
+                        $crate::TableMap::insert(&mut self.$table, data.$pk.clone(), data);
+                    }
+                    $crate::Change::Updated { new, .. } => {
+                        let old = match $crate::table_row!(self, $table, &new.$pk) {
+                            Some(value) => value,
+                            None => return Err($crate::table_error!($missing, &new.$pk)),
+                        };
+                        {
+                            let new = &new;
+                            $($crate::table_replica_check!(update self, old.$pk, $itype, $name, old, new, $crate::table_prop!(old, $prop), $crate::table_prop!(new, $prop), $err);)*
+                        }
+                        $crate::table_update_indices!(self, $table, old, new, $pk, $($itype $name $prop => $err),*);
+                        $crate::TableMap::insert(&mut self.$table, new.$pk.clone(), new);
+                    }
+                    $crate::Change::Deleted(data) => {
+                        let row = match $crate::TableMap::remove(&mut self.$table, &data.$pk) {
+                            Some(row) => row,
+                            None => return Err($crate::table_error!($missing, &data.$pk)),
+                        };
+                        if Self::[<$table _is_live>](&row) {
+                            $($crate::table_unload_index!(self, $table, row.$pk, $itype, $name, $crate::table_prop!(row, $prop));)*
+                            // soft deletion keeps the row with its tombstone set
+                            if !Self::[<$table _is_live>](&data) {
+                                $crate::TableMap::insert(&mut self.$table, data.$pk.clone(), data);
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
         }
     };
 }
 
+/// Checks used when applying replicated changes, which only reject rows that conflict with the
+/// `unique` indices of a diverged database.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_replica_check {
+    (insert $self:expr, unique, $name:ident, $data:ident, $expr:expr, $err:tt) => {
+        $crate::table_insert_check!($self, unique, $name, $data, $expr, $err);
+    };
+    (insert $self:expr, $other:ident, $name:ident, $data:ident, $expr:expr, $err:tt) => {};
+    (update $self:expr, $pk:expr, unique, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {
+        $crate::table_update_check!($self, $pk, unique, $name, $olddata, $data, $old, $new, $err);
+    };
+    (update $self:expr, $pk:expr, $other:ident, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {};
+}
+
+/// Update checks used when applying changes. Versions are skipped, since the new row of an
+/// emitted change already has the incremented version.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_apply_check {
+    ($self:expr, $pk:expr, version, $name:ident, $olddata:ident, $data:tt, $old:expr, $new:expr, $err:tt) => {};
+    ($self:expr, $pk:expr, $other:ident, $name:ident, $olddata:ident, ($data:expr), $old:expr, $new:expr, $err:tt) => {
+        let data = $data;
+        $crate::table_update_check!($self, $pk, $other, $name, $olddata, data, $old, $new, $err);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_load_check {
+    ($self:expr, primary, $table:ident, $data:ident, $expr:expr, $err:tt) => {
+        $crate::table_insert_check!($self, primary, $table, $data, $expr, $err);
+    };
+    ($self:expr, $other:ident, $table:ident, $data:ident, $expr:expr, $err:tt) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_restore_check {
+    ($self:expr, primary, $table:ident, $data:ident, $expr:expr, $err:tt) => {};
+    ($self:expr, $other:ident, $table:ident, $data:ident, $expr:expr, $err:tt) => {
+        $crate::table_insert_check!($self, $other, $table, $data, $expr, $err);
+    };
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_insert_index {
-    ($self:expr, $pk:expr, unique, $name:ident, $prop:expr) => {
-        if $self.$name.insert($prop.clone(), $pk.clone()).is_some() {
+    ($self:expr, $table:ident, $pk:expr, hash, $name:ident, $row:expr) => {
+        $crate::paste! {
+            $crate::RowHasher::insert_row(&mut $self.$name, &$self.$table, Self::[<$table _is_live>], &$pk, &$row);
+        }
+    };
+    ($self:expr, $table:ident, $pk:expr, unique, $name:ident, $prop:expr) => {
+        if $crate::TableMap::insert(&mut $self.$name, $prop.clone(), $pk.clone()).is_some() {
             panic!(concat!(stringify!($name), " index entry already existsted"));
         }
     };
-    ($self:expr, $pk:expr, index, $name:ident, $prop:expr) => {
-        if !$self
-            .$name
-            .entry($prop.clone())
-            .or_default()
-            .insert($pk.clone())
-        {
+    ($self:expr, $table:ident, $pk:expr, index, $name:ident, $prop:expr) => {
+        if !$crate::MultiMap::insert_value(&mut $self.$name, $prop.clone(), $pk.clone()) {
             panic!(concat!(stringify!($name), " index already had new user"));
         }
     };
-    ($self:expr, $pk:expr, $other:ident, $name:ident, $prop:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, $other:ident, $name:ident, $prop:expr) => {};
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_prop {
+    ($data:expr, (@row)) => {
+        $data
+    };
     ($data:expr, $prop:ident) => {
         $data.$prop
     };
@@ -183,10 +857,10 @@ macro_rules! table_prop {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_insert_indices {
-    ($table:ident: $type:ty, $pk:ident, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
+    ($table:ident: $type:ty, $pk:ident, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
         $crate::paste! {
             fn [<$table _insert_indices>](&mut self, data: &$type) {
-                $($crate::table_insert_index!(self, data.$pk, $itype, $name, $crate::table_prop!(data, $prop));)*
+                $($crate::table_insert_index!(self, $table, data.$pk, $itype, $name, $crate::table_prop!(data, $prop));)*
             }
         }
     }
@@ -195,8 +869,11 @@ macro_rules! table_insert_indices {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_delete_index {
-    ($self:expr, $pk:expr, unique, $name:ident, $prop:expr) => {
-        match $self.$name.remove(&$prop) {
+    ($self:expr, $table:ident, $pk:expr, hash, $name:ident, $row:expr) => {
+        $crate::RowHasher::remove_row(&mut $self.$name, &$self.$table, &$pk, &$row);
+    };
+    ($self:expr, $table:ident, $pk:expr, unique, $name:ident, $prop:expr) => {
+        match $crate::TableMap::remove(&mut $self.$name, &$prop) {
             None => panic!(concat!(stringify!($name), " unique index missing item")),
             Some(value) if value != $pk => {
                 panic!(concat!(stringify!($name), " unique index had wrong key"))
@@ -204,36 +881,26 @@ macro_rules! table_delete_index {
             _ => {}
         }
     };
-    ($self:expr, $pk:expr, reverse, $name:ident, $prop:expr) => {
-        match $self.$name.remove(&$pk) {
-            Some(value) if !value.is_empty() => {
-                panic!(concat!(stringify!($name, " reverse index not empty")))
-            }
-            _ => {}
+    ($self:expr, $table:ident, $pk:expr, reverse, $name:ident, $prop:expr) => {
+        if $crate::MultiMap::has_values(&$self.$name, &$pk) {
+            panic!(concat!(stringify!($name), " reverse index not empty"));
         }
     };
-    ($self:expr, $pk:expr, index, $name:ident, $prop:expr) => {
-        let values = $self
-            .$name
-            .get_mut(&$prop)
-            .expect(concat!(stringify!($name), " index missing"));
-        if !values.remove(&$pk) {
-            panic!(concat!(stringify!($name), " index already had new user"));
-        }
-        if values.is_empty() {
-            $self.$name.remove(&$prop);
+    ($self:expr, $table:ident, $pk:expr, index, $name:ident, $prop:expr) => {
+        if !$crate::MultiMap::remove_value(&mut $self.$name, &$prop, &$pk) {
+            panic!(concat!(stringify!($name), " index missing row"));
         }
     };
-    ($self:expr, $pk:expr, $other:ident, $name:ident, $prop:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, $other:ident, $name:ident, $prop:expr) => {};
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_delete_indices {
-    ($table:ident: $type:ty, $pk:ident, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
+    ($table:ident: $type:ty, $pk:ident, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
         $crate::paste! {
             fn [<$table _delete_indices>](&mut self, data: &$type) {
-                $($crate::table_delete_index!(self, data.$pk, $itype, $name, $crate::table_prop!(data, $prop));)*
+                $($crate::table_delete_index!(self, $table, data.$pk, $itype, $name, $crate::table_prop!(data, $prop));)*
             }
         }
     }
@@ -242,54 +909,108 @@ macro_rules! table_delete_indices {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_update_index {
-    ($self:expr, $pk:expr, primary, $name:ident, $old:expr, $new:expr) => {};
-    ($self:expr, $pk:expr, constraint, $name:ident, $old:expr, $new:expr) => {};
-    ($self:expr, $pk:expr, $kind:ident, $name:ident, $old:expr, $new:expr) => {
+    ($self:expr, $table:ident, $pk:expr, primary, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, constraint, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, transition, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, immutable, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, version, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, changes, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, dirty, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, history, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, journal, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, hash, $name:ident, $old:expr, $new:expr) => {
+        $crate::RowHasher::update_row(&mut $self.$name, &$self.$table, &$pk, &*$old, &$new);
+    };
+    ($self:expr, $table:ident, $pk:expr, before_insert, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, before_update, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, after_delete, $name:ident, $old:expr, $new:expr) => {};
+    ($self:expr, $table:ident, $pk:expr, $kind:ident, $name:ident, $old:expr, $new:expr) => {
         if $old != $new {
-            $crate::table_delete_index!($self, $pk, $kind, $name, $old);
-            $crate::table_insert_index!($self, $pk, $kind, $name, $new);
+            $crate::table_delete_index!($self, $table, $pk, $kind, $name, $old);
+            $crate::table_insert_index!($self, $table, $pk, $kind, $name, $new);
         }
     };
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_version {
+    ($new:ident, version, $field:ident) => {
+        $new.$field = $new.$field.wrapping_add(1);
+    };
+    ($new:ident, $other:ident, $prop:tt) => {};
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_update_indices {
-    ($table:ident: $type:ty, $pk:ident, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
-        $crate::paste! {
-            fn [<$table _update_indices>](&mut self, old: &$type, new: &$type) {
-                $($crate::table_update_index!(self, old.$pk, $itype, $name, $crate::table_prop!(old, $prop), $crate::table_prop!(new, $prop));)*
+    // the update checks have rejected changes to `immutable` fields, so the indices on them are
+    // left alone without comparing the old and new values
+    (checked $self:expr, $table:ident, $old:ident, $new:ident, $pk:ident, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
+        const ENTRIES: &[(&str, &str)] = &[$((stringify!($itype), stringify!($prop))),*];
+        $({
+            const IMMUTABLE: bool = $crate::is_immutable(ENTRIES, stringify!($prop));
+            if !IMMUTABLE {
+                $crate::table_update_index!($self, $table, $old.$pk, $itype, $name, $crate::table_prop!($old, $prop), $crate::table_prop!($new, $prop));
             }
-        }
+        })*
+    };
+    ($self:expr, $table:ident, $old:ident, $new:ident, $pk:ident, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
+        $($crate::table_update_index!($self, $table, $old.$pk, $itype, $name, $crate::table_prop!($old, $prop), $crate::table_prop!($new, $prop));)*
     }
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_update_check {
-    ($self:expr, $pk:expr, unique, $name:ident, $data:ident, $old:expr, $new:expr, $err:expr) => {
+    ($self:expr, $pk:expr, unique, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {
         if $old != $new {
             $crate::table_insert_check!($self, unique, $name, $data, $new, $err);
         }
     };
-    ($self:expr, $pk:expr, foreign, $name:ident, $data:ident, $old:expr, $new:expr, $err:expr) => {
+    ($self:expr, $pk:expr, foreign, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {
         if $old != $new {
             $crate::table_insert_check!($self, foreign, $name, $data, $new, $err);
         }
     };
-    ($self:expr, $pk:expr, constraint, $name:ident, $data:ident, $old:expr, $new:expr, $err:expr) => {
+    ($self:expr, $pk:expr, live_foreign, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {
+        if $old != $new {
+            $crate::table_insert_check!($self, live_foreign, $name, $data, $new, $err);
+        }
+    };
+    ($self:expr, $pk:expr, constraint, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {
         $crate::table_insert_check!($self, constraint, $name, $data, $new, $err);
     };
-    ($self:expr, $pk:expr, $other:ident, $name:ident, $data:ident, $old:expr, $new:expr, $err:expr) => {};
+    ($self:expr, $pk:expr, immutable, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {
+        if $old != $new {
+            return Err($crate::table_error!($err, $data, &$old));
+        }
+    };
+    ($self:expr, $pk:expr, transition, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {
+        $self.$name($olddata, $data)?;
+    };
+    ($self:expr, $pk:expr, version, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {
+        if $old != $new {
+            return Err($crate::table_error!($err, $data, &$old));
+        }
+    };
+    ($self:expr, $pk:expr, $other:ident, $name:ident, $olddata:ident, $data:ident, $old:expr, $new:expr, $err:tt) => {};
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_update_checks {
-    ($table:ident: $type:ty, $pk:ident, $errty:ty, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
+    ($table:ident: $type:ty, $pk:ident, $errty:ty, $missing:tt, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
         $crate::paste! {
-            fn [<$table _update_check>](&mut self, old: &$type, new: &$type) -> Result<(), $errty> {
-                $($crate::table_update_check!(self, old.$pk, $itype, $name, new, $crate::table_prop!(old, $prop), $crate::table_prop!(new, $prop), $err);)*
+            pub fn [<$table _can_update>](&self, new: &$type) -> Result<(), $errty> {
+                match $crate::table_row!(self, $table, &new.$pk).filter(|_| Self::[<$table _is_live>](new)) {
+                    Some(old) => self.[<$table _update_check>](old, new),
+                    None => Err($crate::table_error!($missing, &new.$pk)),
+                }
+            }
+
+            fn [<$table _update_check>](&self, old: &$type, new: &$type) -> Result<(), $errty> {
+                $($crate::table_update_check!(self, old.$pk, $itype, $name, old, new, $crate::table_prop!(old, $prop), $crate::table_prop!(new, $prop), $err);)*
                 Ok(())
             }
         }
@@ -299,32 +1020,29 @@ macro_rules! table_update_checks {
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_delete_check {
-    ($self:expr, $pk:expr, reverse, $name:ident, $prop:expr, $err:expr) => {
-        match $self.$name.get(&$pk) {
-            None => {}
-            Some(items) if items.is_empty() => {
-                panic!(concat!(stringify!($name), " has empty index"))
-            }
-            Some(_items) => return Err($err),
+    ($self:expr, $pk:expr, reverse, $name:ident, $data:ident, $prop:expr, $err:tt) => {
+        if $crate::MultiMap::has_values(&$self.$name, &$pk) {
+            return Err($crate::table_error!($err, $data, &$pk));
         }
     };
-    ($self:expr, $pk:expr, $other:ident, $name:ident, $prop:expr, $err:expr) => {};
+    ($self:expr, $pk:expr, $other:ident, $name:ident, $data:ident, $prop:expr, $err:tt) => {};
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! table_delete_checks {
-    ($table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $missing:expr, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $missing:tt, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
         $crate::paste! {
-            fn [<$table _delete_check>](&mut self, id: $pkty) -> Result<$type, $errty> {
-                let row = match self.$table.get(&id) {
-                    Some(row) => row.clone(),
-                    None => return Err($missing),
-                };
-
-                $($crate::table_delete_check!(self, row.$pk, $itype, $name, $crate::table_prop!(row, $prop), $err);)*
+            pub fn [<$table _can_delete>](&self, id: &$pkty) -> Result<(), $errty> {
+                match $crate::table_row!(self, $table, id) {
+                    Some(row) => self.[<$table _delete_check>](row),
+                    None => Err($crate::table_error!($missing, id)),
+                }
+            }
 
-                Ok(row)
+            fn [<$table _delete_check>](&self, row: &$type) -> Result<(), $errty> {
+                $($crate::table_delete_check!(self, row.$pk, $itype, $name, row, $crate::table_prop!(row, $prop), $err);)*
+                Ok(())
             }
         }
     };
@@ -332,40 +1050,204 @@ macro_rules! table_delete_checks {
 
 #[doc(hidden)]
 #[macro_export]
-macro_rules! table_indices {
-    ($table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $error:expr, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
-        $crate::table_insert_checks!($table: $type, $errty, $($itype $name $prop => $err),*);
-        $crate::table_update_checks!($table: $type, $pk, $errty, $($itype $name $prop => $err),*);
-        $crate::table_delete_checks!($table: $type, $pk: $pkty, $errty, $error, $($itype $name $prop => $err),*);
-        $crate::table_insert_indices!($table: $type, $pk, $($itype $name $prop => $err),*);
-        $crate::table_delete_indices!($table: $type, $pk, $($itype $name $prop => $err),*);
-        $crate::table_update_indices!($table: $type, $pk, $($itype $name $prop => $err),*);
-    }
+macro_rules! table_emit {
+    // sinks that can fail receive the change first, so that an error aborts the operation before
+    // the infallible sinks and dirty sets have seen it
+    ($self:expr, $table:ident, $pk:ident, $change:expr, $error:ident => $fail:expr, $($itype:ident $name:ident $err:tt),*) => {
+        $crate::paste! {
+            let () = Self::[<$table:upper _FALLIBLE_SINKS>];
+        }
+        $($crate::table_emit!(@fallible $self, $table, $pk, $itype, $name, $err, $change, $error => $fail);)*
+        $($crate::table_emit!(@infallible $self, $table, $pk, $itype, $name, $err, $change);)*
+    };
+    // sinks declared without an error cannot fail
+    (@fallible $self:expr, $table:ident, $pk:ident, changes, $name:ident, (|_, _| ()), $change:expr, $error:ident => $fail:expr) => {};
+    (@fallible $self:expr, $table:ident, $pk:ident, changes, $name:ident, $err:tt, $change:expr, $error:ident => $fail:expr) => {
+        if let Err(error) = $crate::ChangeSink::emit(&mut $self.$name, stringify!($table), $change)
+        {
+            let $error = $crate::table_error!($err, $change.row(), error);
+            $fail
+        }
+    };
+    (@fallible $self:expr, $table:ident, $pk:ident, $other:ident, $name:ident, $err:tt, $change:expr, $error:ident => $fail:expr) => {};
+    (@infallible $self:expr, $table:ident, $pk:ident, changes, $name:ident, (|_, _| ()), $change:expr) => {
+        if let Err(error) = $crate::ChangeSink::emit(&mut $self.$name, stringify!($table), $change)
+        {
+            match error {}
+        }
+    };
+    (@infallible $self:expr, $table:ident, $pk:ident, dirty, $name:ident, $err:tt, $change:expr) => {
+        $crate::table_mark!($self, dirty, $name, &$change.row().$pk);
+    };
+    (@infallible $self:expr, $table:ident, $pk:ident, $other:ident, $name:ident, $err:tt, $change:expr) => {};
+    // counts the sinks that can fail, which is limited to one per table
+    (@count changes (|_, _| ())) => { 0 };
+    (@count changes $err:tt) => { 1 };
+    (@count $other:ident $err:tt) => { 0 };
 }
 
 #[doc(hidden)]
 #[macro_export]
-macro_rules! table_update {
-    ($table:ident: $type:ty, $pk:ident => $err:expr, $errty:ty) => {
+macro_rules! table_mark {
+    ($self:expr, dirty, $name:ident, $id:expr) => {
+        $crate::DirtySet::mark(&mut $self.$name, $id);
+    };
+    ($self:expr, $other:ident, $name:ident, $id:expr) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_record {
+    ($self:expr, history, $name:ident, $inverse:expr) => {
+        $crate::History::record(&mut $self.$name, $inverse);
+    };
+    ($self:expr, journal, $name:ident, $inverse:expr) => {
+        if $crate::Journal::is_active(&$self.$name) {
+            $crate::Journal::record(&mut $self.$name, $inverse);
+        }
+    };
+    ($self:expr, $other:ident, $name:ident, $inverse:expr) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_before_insert {
+    ($self:expr, before_insert, $name:ident, $data:ident) => {
+        $self.$name(&mut $data)?;
+    };
+    ($self:expr, $other:ident, $name:ident, $data:ident) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_before_update {
+    ($self:expr, $table:ident, $pk:ident, $missing:tt, before_update, $name:ident, $new:ident) => {
+        // the hook gets a copy of the old row, since it needs mutable access to the database
+        // and the row has to stay in the table (and its indices) while it runs.
+        let old = match $crate::table_row!($self, $table, &$new.$pk) {
+            Some(old) => old.clone(),
+            None => return Err($crate::table_error!($missing, &$new.$pk)),
+        };
+        $self.$name(&old, &mut $new)?;
+    };
+    ($self:expr, $table:ident, $pk:ident, $missing:tt, $other:ident, $name:ident, $new:ident) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_after_delete {
+    ($self:expr, after_delete, $name:ident, $data:ident) => {
+        $self.$name(&$data);
+    };
+    ($self:expr, $other:ident, $name:ident, $data:ident) => {};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_validate {
+    ($table:ident: $type:ty, $pk:ident, $errty:ty, $missing:tt, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
         $crate::paste! {
-            pub fn [<$table _update>](&mut self, new: $type) -> Result<$type, $errty> {
-                let old = match self.$table.get(&new.$pk) {
-                    Some(value) => value.clone(),
-                    None => return Err($err),
+            pub fn [<$table _validate_insert>](&self, data: &$type) -> $crate::Vec<$errty> {
+                let mut errors = $crate::Vec::new();
+                if !Self::[<$table _is_live>](data) {
+                    errors.push($crate::table_error!($missing, &data.$pk));
+                    return errors;
+                }
+                $($crate::collect_error(&mut errors, || {
+                    $crate::table_insert_check!(self, $itype, $name, data, $crate::table_prop!(data, $prop), $err);
+                    Ok(())
+                });)*
+                errors
+            }
+
+            pub fn [<$table _validate_update>](&self, new: &$type) -> $crate::Vec<$errty> {
+                let mut errors = $crate::Vec::new();
+                let old = match $crate::table_row!(self, $table, &new.$pk).filter(|_| Self::[<$table _is_live>](new)) {
+                    Some(value) => value,
+                    None => {
+                        errors.push($crate::table_error!($missing, &new.$pk));
+                        return errors;
+                    }
                 };
-                self.[<$table _update_check>](&old, &new)?;
-                self.[<$table _update_indices>](&old, &new);
-                self.$table.insert(new.$pk.clone(), new);
-                Ok(old)
+                $($crate::collect_error(&mut errors, || {
+                    $crate::table_update_check!(self, old.$pk, $itype, $name, old, new, $crate::table_prop!(old, $prop), $crate::table_prop!(new, $prop), $err);
+                    Ok(())
+                });)*
+                errors
             }
         }
     };
 }
 
-/// # Table Macro
-///
-/// Generate database methods (insert, update and delete) for a table.
-/// This macro takes a definition of the database table and indices and generates helper
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_indices {
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $error:tt, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
+        $crate::paste! {
+            /// Emitting a change to two sinks that can fail is not atomic, so tables can only
+            /// have one of them.
+            const [<$table:upper _FALLIBLE_SINKS>]: () = assert!(
+                0 $(+ $crate::table_emit!(@count $itype $err))* < 2,
+                concat!("table `", stringify!($table), "` has more than one `changes` sink that can fail"),
+            );
+        }
+        $crate::table_insert_checks!($table: $type, $pk, $errty, $error, $($itype $name $prop => $err),*);
+        $crate::table_update_checks!($table: $type, $pk, $errty, $error, $($itype $name $prop => $err),*);
+        $crate::table_delete_checks!($table: $type, $pk: $pkty, $errty, $error, $($itype $name $prop => $err),*);
+        $crate::table_validate!($table: $type, $pk, $errty, $error, $($itype $name $prop => $err),*);
+        $crate::table_insert_indices!($table: $type, $pk, $($itype $name $prop => $err),*);
+        $crate::table_delete_indices!($table: $type, $pk, $($itype $name $prop => $err),*);
+    }
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_update {
+    ($table:ident: $type:ty, $pk:ident => $missing:tt, $errty:ty, $($itype:ident $name:ident $prop:tt => $err:tt),*) => {
+        $crate::paste! {
+            pub fn [<$table _update>](&mut self, #[allow(unused_mut)] mut new: $type) -> Result<$type, $errty> {
+                $($crate::table_before_update!(self, $table, $pk, $missing, $itype, $name, new);)*
+                let old = match $crate::table_row!(self, $table, &new.$pk).filter(|_| Self::[<$table _is_live>](&new)) {
+                    Some(value) => value,
+                    None => return Err($crate::table_error!($missing, &new.$pk)),
+                };
+                self.[<$table _update_check>](old, &new)?;
+                $($crate::table_version!(new, $itype, $prop);)*
+                $crate::table_emit!(self, $table, $pk, $crate::Change::Updated { old, new: &new }, error => return Err(error), $($itype $name $err),*);
+                $crate::table_update_indices!(checked self, $table, old, new, $pk, $($itype $name $prop => $err),*);
+                $($crate::table_record!(self, $itype, $name, $crate::Inverse::Update(old.clone()));)*
+                let slot = $crate::TableMap::get_mut(&mut self.$table, &new.$pk)
+                    .expect(concat!(stringify!($table), " row missing"));
+                Ok(::core::mem::replace(slot, new))
+            }
+
+            /// Copy the version of the current row into `new`, so that it can be used to update
+            /// the row even if it was taken from another database.
+            #[doc(hidden)]
+            #[allow(unused_mut, unused_variables)]
+            pub fn [<$table _rebase>](&self, mut new: $type) -> $type {
+                if let Some(old) = $crate::TableMap::get(&self.$table, &new.$pk) {
+                    $($crate::table_rebase!(new, old, $itype, $prop);)*
+                }
+                new
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_rebase {
+    ($new:ident, $old:ident, version, $field:ident) => {
+        $new.$field = $old.$field.clone();
+    };
+    ($new:ident, $old:ident, $other:ident, $prop:tt) => {};
+}
+
+/// # Table Macro
+///
+/// Generate database methods (insert, update and delete) for a table.
+/// This macro takes a definition of the database table and indices and generates helper
 /// methods used to safely insert, update and delete rows in the database.
 ///
 /// ## Usage
@@ -399,7 +1281,7 @@ macro_rules! table_update {
 /// ### Row Types
 ///
 /// For every table, you need a row type. This can just be a regular Rust struct. It needs to
-/// derive [Clone][], and it needs to have some kind of primary key. The primary key uniquely
+/// have some kind of primary key, which must implement [Clone][]. The primary key uniquely
 /// identifies the row for update and deletion operations. Usually, an integer type is
 /// recommended here. For visual clarity, you can create a type alias for this field. We will
 /// refer to the row type as `RowType` and to the type of the primary key as `RowId`.
@@ -409,7 +1291,6 @@ macro_rules! table_update {
 /// ```rust
 /// type UserId = u64;
 ///
-/// #[derive(Clone)]
 /// pub struct User {
 ///     id: UserId,
 ///     name: String,
@@ -419,7 +1300,6 @@ macro_rules! table_update {
 ///
 /// type GroupId = u64;
 ///
-/// #[derive(Clone)]
 /// pub struct Group {
 ///     id: GroupId,
 ///     name: String,
@@ -430,17 +1310,17 @@ macro_rules! table_update {
 /// ### Database Struct
 ///
 /// The database struct must contain one map per table and one per index.
-/// The table maps must be of the shape `MapType<RowPrimaryKey, RowType>`. The type of map that is used does not matter, although common choices are [BTreeMap][std::collections::BTreeMap]
+/// The table maps must be of the shape `MapType<RowPrimaryKey, RowType>`. The type of map that is used does not matter, although common choices are [BTreeMap][alloc::collections::BTreeMap]
 /// and [HashMap][std::collections::HashMap].
 /// For every unique index, a map of the shape `MapType<IndexType,
 /// RowId>` must be added.
 /// For every index, a map of the shape `MapType<IndexType, Set<RowId>>`
 /// needs to be added.
 /// The set type that is used
-/// does not matter, although common choices are [BTreeSet][std::collections::BTreeSet]
+/// does not matter, although common choices are [BTreeSet][alloc::collections::BTreeSet]
 /// and [HashSet][std::collections::HashSet].
 /// The *IndexType* is the type of the field of the index. For instance, if
-/// the index is on a field of type [String][], that is the IndexType.
+/// the index is on a field of type [String][alloc::string::String], that is the IndexType.
 ///
 /// | Kind | Type |
 /// | --- | --- |
@@ -448,6 +1328,11 @@ macro_rules! table_update {
 /// | Index | `MapType<IndexType, Set<RowId>>` |
 /// | Unique index | `MapType<IndexType, RowId>` |
 ///
+/// The generated methods access these maps through the [TableMap] and [IndexSet] traits. These
+/// are implemented for the standard library collections, and for collections from other crates
+/// when the cargo feature of the same name is enabled (`hashbrown`, `im`, `indexmap`, `avl`,
+/// `aatree` and `btree-slab`). Other map types can be used by implementing these traits.
+///
 /// For example, to define a database struct with two tables (*users* and *groups*),
 /// two unique indices (*user_by_email* and *group_by_name*), and one regular index
 /// (*users_by_group*), this struct definition could be used:
@@ -474,6 +1359,29 @@ macro_rules! table_update {
 /// }
 /// ```
 ///
+/// ### Shared Rows
+///
+/// Rows can also be stored behind an [Arc][alloc::sync::Arc] (or [Rc][alloc::rc::Rc]), by
+/// declaring the table as `MapType<RowId, Arc<RowType>>` and passing `Arc<RowType>` as the row
+/// type to the macro. Reading a row then only needs an [Arc][alloc::sync::Arc] clone, and a
+/// clone of the database shares all rows with the original. Indices are maintained by reading
+/// through the [Arc][alloc::sync::Arc], so the row type does not need to implement [Clone][].
+/// The insert and update methods take the stored type, so rows are passed to them wrapped in
+/// an [Arc][alloc::sync::Arc] as well.
+///
+/// ```rust
+/// # type UserId = u64;
+/// # struct User { id: UserId, email: String }
+/// use std::collections::BTreeMap;
+/// use std::sync::Arc;
+///
+/// #[derive(Clone)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, Arc<User>>,
+///     user_by_email: BTreeMap<String, UserId>,
+/// }
+/// ```
+///
 /// ## Syntax
 ///
 /// The basic syntax of the macro looks like this:
@@ -536,6 +1444,32 @@ macro_rules! table_update {
 /// constraint name_not_empty _ => ()
 /// ```
 ///
+/// Constraints are checked on insert and on update, but only see the new row. Constraints
+/// that depend on the previous state of a row can be declared as transitions, which are only
+/// checked on update and receive both the old and the new row:
+///
+/// ```rust,ignore
+/// transition fn_name _ => (),
+/// ```
+///
+/// For example, a transition that prevents the balance of an account from decreasing by more
+/// than a fixed amount might look like this:
+///
+/// ```rust
+/// # enum Error { AccountWithdrawalLimit, }
+/// # struct Account { balance: u64 }
+/// # struct Database {}
+/// impl Database {
+///     fn withdrawal_limit(&self, old: &Account, new: &Account) -> Result<(), Error> {
+///         if old.balance.saturating_sub(new.balance) > 1000 {
+///             return Err(Error::AccountWithdrawalLimit);
+///         }
+///
+///         Ok(())
+///     }
+/// }
+/// ```
+///
 /// ### Indices
 ///
 /// The macro also needs to be told of the various indices. The syntax for indices looks like
@@ -545,9 +1479,9 @@ macro_rules! table_update {
 /// $type $map $field => $error
 /// ```
 ///
-/// Here, `$type` refers to the type of index (can be `index`, `unique`, `foreign` or
-/// `reverse`). `$map` is the name of the map field in the database struct that represents this
-/// index. `$field` is the name of the field of the `RowType` that this index is on. The field can
+/// Here, `$type` refers to the type of index (can be `index`, `unique`, `foreign`, `reverse` or
+/// `immutable`). `$map` is the name of the map field in the database struct that represents this
+/// index (`immutable` entries can leave it out, or name the table itself). `$field` is the name of the field of the `RowType` that this index is on. The field can
 /// also be a compound key, by writing it as a tuple (for example `(first_name, last_name)`. Finally,
 /// `$error` is the error that is thrown when this index is violated. Here is an overview of the
 /// index types:
@@ -556,15 +1490,44 @@ macro_rules! table_update {
 /// | --- | --- | --- |
 /// | Index | `index users_by_group group => ()` | Defines a simple index to look up rows based on their group. Does not need an error. |
 /// | Foreign | `foreign groups group => Error::GroupNotFound` | Defines a foreign key constraint which enforces that the `group` field point to an existing row in the `groups` table. |
+/// | Live foreign | `foreign live groups group => Error::GroupNotFound` | Like `foreign`, but also rejects references to rows of a [soft delete](#soft-delete) table that are deleted. |
 /// | Unique | `unique user_by_email email => Error::UserEmailExists` | Defines a unique index which uses the `user_by_email` map and enforces that no two users share the same email. |
 /// | Reverse | `reverse users_by_group id => Error::GroupHasUsers` | Declares a reverse dependency (on an index by another table) that prevents a group row being deleted if there are still users with that group. |
+/// | Immutable | `immutable group => Error::UserGroupImmutable` | Declares that the `group` field of a row cannot be changed by an update. Updates leave indices on the field alone. |
+///
+/// ### Error Context
+///
+/// Instead of a constant expression, the error of a `primary`, `unique`, `foreign`, `reverse`,
+/// `immutable` or `version` entry can be written as a closure taking the offending row and the
+/// conflicting key. The closure is expanded inline, so its parameters may be annotated with
+/// types but must be plain identifiers or `_`:
+///
+/// | Type | Row | Key |
+/// | --- | --- | --- |
+/// | Primary | New row | Its primary key |
+/// | Unique | New row | Primary key of the row that already has the value |
+/// | Foreign | New row | Foreign key that does not exist |
+/// | Reverse | Row being deleted | Its primary key |
+/// | Immutable | New row | Old value of the field |
+/// | Version | New row | Version of the stored row |
+///
+/// The `missing` error can be a closure taking only the primary key that was not found.
+///
+/// ```rust,ignore
+/// missing Error => |id| Error::UserNotFound(*id),
+/// primary users id => Error::UserIdExists,
+/// unique user_by_email email => |row, by| Error::EmailTaken { email: row.email.clone(), by: *by }
+/// ```
 ///
 /// The result of this is that the macro generates insertion, update and deletion methods for
 /// every table. It uses the table map name as the prefix for those methods. For example,
-/// calling it on a table with the name *users* results in three methods being generated:
+/// calling it on a table with the name *users* results in these methods being generated:
 ///
 /// ```rust,ignore
 /// impl Database {
+///     /// Look up a User row by its primary key.
+///     pub fn users_get(id: &UserId) -> Option<&User>;
+///
 ///     /// Insert a User into the database, or return an error.
 ///     pub fn users_insert(row: User) -> Result<(), Error>;
 ///
@@ -575,9 +1538,277 @@ macro_rules! table_update {
 ///     /// Delete a User row (identified by the primary key), returning the row, or return an
 ///     /// error.
 ///     pub fn users_delete(id: UserId) -> Result<User, Error>;
+///
+///     /// Determine if a User row can be inserted, without inserting it.
+///     pub fn users_can_insert(row: &User) -> Result<(), Error>;
+///
+///     /// Determine if a User row can be updated, without updating it.
+///     pub fn users_can_update(row: &User) -> Result<(), Error>;
+///
+///     /// Determine if a User row can be deleted, without deleting it.
+///     pub fn users_can_delete(id: &UserId) -> Result<(), Error>;
+///
+///     /// Run all checks for inserting a User row, returning every error.
+///     pub fn users_validate_insert(row: &User) -> Vec<Error>;
+///
+///     /// Run all checks for updating a User row, returning every error.
+///     pub fn users_validate_update(row: &User) -> Vec<Error>;
+/// }
+/// ```
+///
+/// The `can_*` methods run the same checks as the corresponding mutation and return the same
+/// error, but only need a shared reference to the database. This allows checking whether an
+/// operation would succeed (for example, whether a row can be deleted) without attempting it.
+/// Hooks are not run by these methods.
+///
+/// The insert, update and delete methods stop at the first check that fails. The validation
+/// methods instead run every check (primary key, unique and foreign keys, constraints and
+/// transitions) without modifying the database, and return the errors of all checks that
+/// failed. An empty list means that the row would be accepted. Hooks are not run by the
+/// validation methods.
+///
+/// ### Change Data Capture
+///
+/// A table can report every change made to it to a sink stored in the database struct:
+///
+/// ```text
+/// changes $sink _ => ()
+/// ```
+///
+/// Here, `$sink` is the name of a field of the database struct that implements
+/// [ChangeSink] for the row type. After an insert, update or delete has passed all of its
+/// checks, and before the database is changed, a [Change] event containing the affected rows
+/// is emitted to the sink. Failed operations do not emit any events. A `Vec<Change<RowType>>`
+/// can be used as a simple sink that collects clones of the rows.
+///
+/// Sinks that can fail, such as a `Wal`, need an error for the table, which is created from
+/// the row and the error of the sink:
+///
+/// ```text
+/// changes $sink _ => |row, error| Error::LogFailed
+/// ```
+///
+/// When the sink returns an error, the operation is aborted and returns it, leaving the table
+/// unchanged. Sinks that cannot fail use [Infallible](core::convert::Infallible) as their
+/// error and are declared with `=> ()`. The sink that can fail receives the change before them
+/// (and before any dirty sets), regardless of the order of the entries, so that they never see a
+/// change that was aborted. Since two sinks that can fail cannot both be rolled back, a table can
+/// only have one of them, which is checked at compile time.
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::{table, Change};
+/// # pub enum Error { UserIdExists, UserNotFound }
+/// # type UserId = u64;
+/// # #[derive(Clone, Debug, PartialEq)]
+/// # pub struct User { id: UserId, name: String }
+///
+/// #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     changes: Vec<Change<User>>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         changes changes _ => ()
+///     );
+/// }
+///
+/// # let mut database = Database::default();
+/// let user = User { id: 0, name: "alice".into() };
+/// database.users_insert(user.clone());
+/// assert_eq!(database.changes, [Change::Inserted(user)]);
+/// ```
+///
+/// ### Dirty Tracking
+///
+/// A table can record the primary keys of the rows that were changed in a set stored in the
+/// database struct:
+///
+/// ```text
+/// dirty $set _ => ()
+/// ```
+///
+/// Here, `$set` is the name of a field of the database struct that implements [DirtySet] for
+/// the primary key type, usually a `BTreeSet<RowId>` or a `HashSet<RowId>`. Every successful
+/// insert, update, delete, restore and purge adds the primary key of the row to the set. The
+/// set is used by the [delta](macro@delta) macro to save only the rows that were changed since
+/// the last snapshot.
+///
+/// ### Table Hashes
+///
+/// A table can maintain a hash of all of its rows in a field of the database struct:
+///
+/// ```text
+/// hash $field _ => ()
+/// ```
+///
+/// Here, `$field` is a [TableHash], and the row type must implement [Hash][core::hash::Hash].
+/// The hash is updated whenever a row is inserted, updated or deleted, and does not depend on
+/// the order of the rows. Two databases can therefore be compared by comparing the hashes of
+/// their tables. For tables stored in a [BTreeMap][alloc::collections::BTreeMap], `$field` can
+/// also be a [TableDigest], which additionally keeps hashes of ranges of primary keys, so that
+/// the rows that differ can be located without comparing all of them.
+///
+/// ### Undo History
+///
+/// Changes made to a table can be recorded so that they can be undone:
+///
+/// ```text
+/// history $field _ => ()
+/// ```
+///
+/// Here, `$field` is a [History], shared by all tables of the database. Every insert, update
+/// and delete records its [Inverse], which the methods generated by the [history] macro
+/// replay through the normal checked methods to undo or redo the change, so that versions,
+/// hooks and transitions apply to undone changes as well. Changes replayed from
+/// a write-ahead log or replicated with the [replication] macro are not recorded.
+///
+/// ### Savepoints
+///
+/// Changes made to a table can also be journaled, so that a failed step of a larger operation
+/// can be rolled back without losing the work done before it:
+///
+/// ```text
+/// journal $field _ => ()
+/// ```
+///
+/// Here, `$field` is a [Journal], shared by all tables of the database. While a savepoint
+/// exists, every insert, update and delete records its [Inverse] in the journal, which the
+/// methods generated by the [journal] macro use to put the rows back exactly as they were at
+/// the savepoint.
+///
+/// ### Triggers
+///
+/// Tables can declare hooks that call methods on the database struct when rows are changed:
+///
+/// ```text
+/// before_insert $method _ => ()
+/// before_update $method _ => ()
+/// after_delete $method _ => ()
+/// ```
+///
+/// Unlike constraints, hooks get mutable access to the database, and the `before_*` hooks get
+/// mutable access to the new row, which allows them to normalise data (for example, stamping
+/// an `updated_at` field) or to write to other tables. The `before_*` hooks run before any of
+/// the checks, and can abort the operation by returning an error. Changes that a hook made to
+/// other tables are not undone when the operation fails later on.
+///
+/// | Hook | Signature |
+/// | --- | --- |
+/// | `before_insert` | `fn(&mut self, new: &mut RowType) -> Result<(), ErrorType>` |
+/// | `before_update` | `fn(&mut self, old: &RowType, new: &mut RowType) -> Result<(), ErrorType>` |
+/// | `after_delete` | `fn(&mut self, old: &RowType)` |
+///
+/// The `before_update` hook is passed a copy of the old row, so the row type must implement
+/// `Clone` (for shared rows, this only clones the `Arc`). The stored row stays in the table
+/// while the hook runs, so the hook can write to tables that reference it.
+///
+/// ### Versioning
+///
+/// Rows can carry a version field that is used for optimistic concurrency control:
+///
+/// ```text
+/// version $field => $error
+/// ```
+///
+/// When a row is updated, the version of the new row must be equal to the version of the stored
+/// row, otherwise the update is rejected with `$error`. This detects writes that are based on an
+/// outdated copy of the row, which would otherwise silently overwrite changes made in the
+/// meantime. On success, the version is incremented, so the stored row (and the row in the
+/// emitted change) has the next version. The field can be of any integer type, and wraps
+/// around to zero after its maximum value. Inserts store the version as given. Since the
+/// update method writes the version into the new row, tables with a version field need an
+/// owned row type, [shared rows](#shared-rows) are not supported.
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::table;
+/// # #[derive(Debug, PartialEq)]
+/// # pub enum Error { UserIdExists, UserNotFound, UserStale }
+/// # type UserId = u64;
+/// #[derive(Clone)]
+/// pub struct User {
+///     id: UserId,
+///     name: String,
+///     rev: u64,
+/// }
+///
+/// #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         version rev => Error::UserStale
+///     );
 /// }
+///
+/// # let mut database = Database::default();
+/// database.users_insert(User { id: 0, name: "alice".into(), rev: 0 }).unwrap();
+/// let mut first = database.users_get(&0).unwrap().clone();
+/// let mut second = first.clone();
+///
+/// first.name = "bob".into();
+/// database.users_update(first).unwrap();
+/// assert_eq!(database.users_get(&0).unwrap().rev, 1);
+///
+/// second.name = "carol".into();
+/// assert_eq!(database.users_update(second).err(), Some(Error::UserStale));
+/// ```
+///
+/// ### Soft Delete
+///
+/// A table can keep deleted rows around, marking them with a tombstone field instead of
+/// removing them:
+///
+/// ```text
+/// soft_delete $field => $value
+/// ```
+///
+/// Here, `$field` is a field of the row type that implements [Tombstone], such as a `bool`
+/// or an `Option<Timestamp>`, and `$value` is the tombstone that is written to it on delete. Like
+/// errors, the value can be a closure over the row and the primary key. Deleting a row then
+/// sets the tombstone and removes the row from all indices, so that its unique values (such as
+/// email addresses) can be registered again, but keeps it in the table. This requires the row
+/// type to implement Clone, since the delete method returns a copy of the deleted row.
+///
+/// Tombstoned rows are treated as missing by the get, update and delete methods. Updating or
+/// inserting a row with the tombstone set returns the missing error, since rows are deleted
+/// through the delete method. A `foreign` entry only looks up the key in the referenced map,
+/// so it still accepts tombstoned rows. Tables that reference a table with soft delete should
+/// use a `foreign live` entry instead, which also treats tombstoned rows as missing:
+///
+/// ```text
+/// foreign live groups group => Error::GroupNotFound
+/// ```
+///
+/// Two additional methods are generated for these tables:
+///
+/// ```rust,ignore
+/// /// Restore a deleted User row, re-running the insert checks, or return an error.
+/// pub fn users_restore(id: UserId) -> Result<(), Error>;
+///
+/// /// Permanently remove a deleted User row from the table, returning it.
+/// pub fn users_purge(id: UserId) -> Result<User, Error>;
 /// ```
 ///
+/// Both return the missing error if the row does not exist or is not deleted. Restoring a row
+/// fails if, for example, its unique values have been taken by another row in the meantime.
+/// Purging a row runs the `reverse` checks again, since rows referencing it through a `foreign`
+/// entry may have been added after it was deleted. Deleting a row emits a [Change::Deleted] event containing the row with its tombstone set,
+/// restoring it emits [Change::Inserted], and purging it emits another [Change::Deleted].
+///
 /// ## Example
 ///
 /// Here is an example invocation of the macro on the Database struct with two tables (*users* and
@@ -658,17 +1889,1775 @@ macro_rules! table_update {
 /// ```
 #[macro_export]
 macro_rules! table {
-    ($table:ident: $type:ty, $pk:ident: $pkty:ty, missing $errty:ty => $missing:expr, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, missing $errty:ty => $($rest:tt)*) => {
+        $crate::table_parse!(@missing [$table: $type, $pk: $pkty, $errty, autokey] $($rest)*);
+    };
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, noautokey, missing $errty:ty => $($rest:tt)*) => {
+        $crate::table_parse!(@missing [$table: $type, $pk: $pkty, $errty, noautokey] $($rest)*);
+    };
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, errors $errty:ident, $($rest:tt)*) => {
+        $crate::table!($table: $type, $pk: $pkty, missing $errty => $crate::paste!($errty::[<$table:camel NotFound>]), $($rest)*);
+    };
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, noautokey, errors $errty:ident, $($rest:tt)*) => {
+        $crate::table!($table: $type, $pk: $pkty, noautokey, missing $errty => $crate::paste!($errty::[<$table:camel NotFound>]), $($rest)*);
+    };
+}
+
+/// Normalize the error declarations of a table into closures, then generate the table methods.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! table_parse {
+    (@missing [$($head:tt)*] |$key:tt $(: $keyty:ty)?| $missing:expr, $($rest:tt)*) => {
+        $crate::table_parse!(@entries [$($head)*] (|$key $(: $keyty)?| $missing) [] [] $($rest)*);
+    };
+    (@missing [$($head:tt)*] $missing:expr, $($rest:tt)*) => {
+        $crate::table_parse!(@entries [$($head)*] (|_| $missing) [] [] $($rest)*);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] soft_delete $field:ident => |$row:tt $(: $rowty:ty)?, $key:tt $(: $keyty:ty)?| $value:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$field (|$row $(: $rowty)?, $key $(: $keyty)?| $value)] [$($done)*] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] soft_delete $field:ident => $value:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$field (|_, _| $value)] [$($done)*] $($($rest)*)?);
+    };
+    (@entries [$table:ident $($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] immutable $field:tt => |$row:tt $(: $rowty:ty)?, $key:tt $(: $keyty:ty)?| $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table $($head)*] $missing [$($soft)*] [$($done)* immutable $table $field => (|$row $(: $rowty)?, $key $(: $keyty)?| $err),] $($($rest)*)?);
+    };
+    (@entries [$table:ident $($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] immutable $field:tt => $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table $($head)*] $missing [$($soft)*] [$($done)* immutable $table $field => (|_, _| $err),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] version $field:ident => |$row:tt $(: $rowty:ty)?, $key:tt $(: $keyty:ty)?| $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* version $field $field => (|$row $(: $rowty)?, $key $(: $keyty)?| $err),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] version $field:ident => $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* version $field $field => (|_, _| $err),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] hash $name:ident _ => $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* hash $name (@row) => (|_, _| $err),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] foreign live $name:ident $prop:tt => |$row:tt $(: $rowty:ty)?, $key:tt $(: $keyty:ty)?| $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* live_foreign $name $prop => (|$row $(: $rowty)?, $key $(: $keyty)?| $err),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] foreign live $name:ident $prop:tt => $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* live_foreign $name $prop => (|_, _| $err),] $($($rest)*)?);
+    };
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $autokey:ident] $missing:tt [$($soft:tt)*] [$($done:tt)*] foreign live $name:ident $prop:tt $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table: $type, $pk: $pkty, $errty, $autokey] $missing [$($soft)*] [$($done)* live_foreign $name $prop => (|_, _| $crate::paste!(<$errty>::[<$table:camel $name:camel NotFound>])),] $($($rest)*)?);
+    };
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $autokey:ident] $missing:tt [$($soft:tt)*] [$($done:tt)*] primary $name:ident $prop:tt $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table: $type, $pk: $pkty, $errty, $autokey] $missing [$($soft)*] [$($done)* primary $name $prop => (|_, _| $crate::paste!(<$errty>::[<$table:camel Exists>])),] $($($rest)*)?);
+    };
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $autokey:ident] $missing:tt [$($soft:tt)*] [$($done:tt)*] unique $name:ident $prop:tt $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table: $type, $pk: $pkty, $errty, $autokey] $missing [$($soft)*] [$($done)* unique $name $prop => (|_, _| $crate::paste!(<$errty>::[<$name:camel Exists>])),] $($($rest)*)?);
+    };
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $autokey:ident] $missing:tt [$($soft:tt)*] [$($done:tt)*] foreign $name:ident $prop:tt $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table: $type, $pk: $pkty, $errty, $autokey] $missing [$($soft)*] [$($done)* foreign $name $prop => (|_, _| $crate::paste!(<$errty>::[<$table:camel $name:camel NotFound>])),] $($($rest)*)?);
+    };
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $autokey:ident] $missing:tt [$($soft:tt)*] [$($done:tt)*] reverse $name:ident $prop:tt $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table: $type, $pk: $pkty, $errty, $autokey] $missing [$($soft)*] [$($done)* reverse $name $prop => (|_, _| $crate::paste!(<$errty>::[<$name:camel NotEmpty>])),] $($($rest)*)?);
+    };
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $autokey:ident] $missing:tt [$($soft:tt)*] [$($done:tt)*] immutable $field:ident $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table: $type, $pk: $pkty, $errty, $autokey] $missing [$($soft)*] [$($done)* immutable $table $field => (|_, _| $crate::paste!(<$errty>::[<$table:camel $field:camel Immutable>])),] $($($rest)*)?);
+    };
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, $autokey:ident] $missing:tt [$($soft:tt)*] [$($done:tt)*] version $field:ident $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$table: $type, $pk: $pkty, $errty, $autokey] $missing [$($soft)*] [$($done)* version $field $field => (|_, _| $crate::paste!(<$errty>::[<$table:camel $field:camel Stale>])),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] hash $name:ident _ $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* hash $name (@row) => (|_, _| ()),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] $itype:ident $name:ident $prop:tt $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* $itype $name $prop => (|_, _| ()),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] $itype:ident $name:ident $prop:tt => () $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* $itype $name $prop => (|_, _| ()),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] $itype:ident $name:ident $prop:tt => |$row:tt $(: $rowty:ty)?, $key:tt $(: $keyty:ty)?| $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* $itype $name $prop => (|$row $(: $rowty)?, $key $(: $keyty)?| $err),] $($($rest)*)?);
+    };
+    (@entries [$($head:tt)*] $missing:tt [$($soft:tt)*] [$($done:tt)*] $itype:ident $name:ident $prop:tt => $err:expr $(, $($rest:tt)*)?) => {
+        $crate::table_parse!(@entries [$($head)*] $missing [$($soft)*] [$($done)* $itype $name $prop => (|_, _| $err),] $($($rest)*)?);
+    };
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, autokey] $missing:tt [$($soft:tt)*] [$($itype:ident $name:ident $prop:tt => $err:tt,)*]) => {
         $crate::table_next_id!($table: $pkty);
-        $crate::table_indices!($table: $type, $pk: $pkty, $errty, $missing, $($itype $name $prop => $err),*);
-        $crate::table_delete!($table: $type, $pkty, $errty);
-        $crate::table_insert!($table: $type, $pk, $errty);
-        $crate::table_update!($table: $type, $pk => $missing, $errty);
+        $crate::table_parse!(@entries [$table: $type, $pk: $pkty, $errty, noautokey] $missing [$($soft)*] [$($itype $name $prop => $err,)*]);
     };
-    ($table:ident: $type:ty, $pk:ident: $pkty:ty, noautokey, missing $errty:ty => $missing:expr, $($itype:ident $name:ident $prop:tt => $err:expr),*) => {
+    (@entries [$table:ident: $type:ty, $pk:ident: $pkty:ty, $errty:ty, noautokey] $missing:tt [$($soft:tt)*] [$($itype:ident $name:ident $prop:tt => $err:tt,)*]) => {
+        $crate::table_live!($table: $type, [$($soft)*]);
         $crate::table_indices!($table: $type, $pk: $pkty, $errty, $missing, $($itype $name $prop => $err),*);
-        $crate::table_delete!($table: $type, $pkty, $errty);
-        $crate::table_insert!($table: $type, $pk, $errty);
-        $crate::table_update!($table: $type, $pk => $missing, $errty);
-    }
+        $crate::table_get!($table: $type, $pkty);
+        $crate::table_delete!($table: $type, $pk: $pkty, $errty, $missing, [$($soft)*], $($itype $name $prop => $err),*);
+        $crate::table_insert!($table: $type, $pk => $missing, $errty, $($itype $name $prop => $err),*);
+        $crate::table_update!($table: $type, $pk => $missing, $errty, $($itype $name $prop => $err),*);
+        $crate::table_load!($table: $type, $pk: $pkty, $errty, $($itype $name $prop => $err),*);
+        $crate::table_apply!($table: $type, $pk => $missing, $errty, $($itype $name $prop => $err),*);
+        $crate::table_apply_change!($table: $type, $pk => $missing, $errty, $($itype $name $prop => $err),*);
+    };
+}
+
+/// # Table Range Macro
+///
+/// Generate a range lookup method for an ordered index.
+///
+/// The [table] macro only maintains indices, it does not generate methods to query them. For
+/// indices that are stored in an ordered map (such as [BTreeMap][alloc::collections::BTreeMap]),
+/// this macro generates a method that returns an iterator over all rows whose indexed value
+/// falls within a range. The rows are returned in the order of the index.
+///
+/// ## Syntax
+///
+/// ```rust,ignore
+/// table_range!($table_name: RowType, $type $index_name: IndexType);
+/// ```
+///
+/// Here, `$type` is the kind of index (either `index` or `unique`), `$index_name` is the name
+/// of the index map in the database struct and `IndexType` is the type of the indexed field.
+/// This generates a method named `$index_name_range`, which accepts anything that implements
+/// [RangeBounds][core::ops::RangeBounds] over the `IndexType`.
+///
+/// The index map has to implement [OrderedMap]. Using it on a [HashMap][std::collections::HashMap]
+/// index results in a compilation error.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::{BTreeMap, BTreeSet};
+/// use macrodb::{table, table_range};
+/// # pub enum Error {
+/// #     UserIdExists,
+/// #     UserNotFound,
+/// # }
+/// # type UserId = u64;
+/// # #[derive(Clone)]
+/// # pub struct User {
+/// #     id: UserId,
+/// #     age: u32,
+/// # }
+/// # #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     users_by_age: BTreeMap<u32, BTreeSet<UserId>>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         index users_by_age age => ()
+///     );
+///     table_range!(users: User, index users_by_age: u32);
+/// }
+///
+/// # let database = Database::default();
+/// let adults: Vec<&User> = database.users_by_age_range(18..).collect();
+/// ```
+#[macro_export]
+macro_rules! table_range {
+    ($table:ident: $type:ty, $itype:ident $name:ident: $key:ty) => {
+        $crate::paste! {
+            pub fn [<$name _range>]<'a, R: ::core::ops::RangeBounds<$key> + 'a>(&'a self, range: R) -> impl Iterator<Item = &'a $type> + 'a {
+                $crate::table_index_rows!(self, $table, $itype, $name, $crate::OrderedMap::range(&self.$name, range))
+            }
+        }
+    };
+}
+
+/// # Table Prefix Macro
+///
+/// Generate a prefix lookup method for an ordered index over a [String][alloc::string::String] field.
+///
+/// This works like [table_range], except that it generates a method named `$index_name_prefix`
+/// that returns an iterator over all rows whose indexed value starts with the given prefix.
+/// Since the index is ordered, this only visits the matching rows.
+///
+/// ## Syntax
+///
+/// ```rust,ignore
+/// table_prefix!($table_name: RowType, $type $index_name);
+/// ```
+///
+/// Here, `$type` is the kind of index (either `index` or `unique`) and `$index_name` is the name
+/// of the index map in the database struct. The map must be keyed by [String][alloc::string::String] and implement
+/// [OrderedMap].
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::{BTreeMap, BTreeSet};
+/// use macrodb::{table, table_prefix};
+/// # pub enum Error {
+/// #     UserIdExists,
+/// #     UserNotFound,
+/// #     UserEmailExists,
+/// # }
+/// # type UserId = u64;
+/// # #[derive(Clone)]
+/// # pub struct User {
+/// #     id: UserId,
+/// #     email: String,
+/// # }
+/// # #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     user_by_email: BTreeMap<String, UserId>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         unique user_by_email email => Error::UserEmailExists
+///     );
+///     table_prefix!(users: User, unique user_by_email);
+/// }
+///
+/// # let database = Database::default();
+/// let admins: Vec<&User> = database.user_by_email_prefix("admin").collect();
+/// ```
+#[macro_export]
+macro_rules! table_prefix {
+    ($table:ident: $type:ty, $itype:ident $name:ident) => {
+        $crate::paste! {
+            pub fn [<$name _prefix>]<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a $type> + 'a {
+                $crate::table_index_rows!(
+                    self,
+                    $table,
+                    $itype,
+                    $name,
+                    $crate::OrderedMap::range(&self.$name, $crate::PrefixRange(prefix))
+                        .take_while(move |(key, _)| key.starts_with(prefix))
+                )
+            }
+        }
+    };
+}
+
+/// # Errors Macro
+///
+/// Generate an error enum for a database.
+///
+/// Every table needs error cases for missing and existing rows, and one per unique index,
+/// foreign key and reverse dependency. Instead of writing these by hand, this macro generates
+/// an enum with one variant per declared check, named after the table and index names. The
+/// enum implements [Display][core::fmt::Display] and, with the `std` feature,
+/// [Error][std::error::Error], derives
+/// `Clone`, `Copy`, `Debug`, `PartialEq`, `Eq`, `Hash`, `PartialOrd` and `Ord`, and has
+/// `kind()` and `table()` accessors that return the [ErrorKind] of the error and the name of
+/// the table it belongs to.
+///
+/// ## Syntax
+///
+/// ```rust,ignore
+/// errors!(
+///     pub enum ErrorType {
+///         $table_name {
+///             <checks...>
+///         },
+///     }
+/// );
+/// ```
+///
+/// The checks of every table generate these variants:
+///
+/// | Check | Example | Variant | Kind |
+/// | --- | --- | --- | --- |
+/// | `missing` | `users { missing }` | `UsersNotFound` | [NotFound][ErrorKind::NotFound] |
+/// | `primary` | `users { primary }` | `UsersExists` | [AlreadyExists][ErrorKind::AlreadyExists] |
+/// | `unique $index` | `users { unique user_by_email }` | `UserByEmailExists` | [AlreadyExists][ErrorKind::AlreadyExists] |
+/// | `foreign $table` | `users { foreign groups }` | `UsersGroupsNotFound` | [MissingReference][ErrorKind::MissingReference] |
+/// | `reverse $index` | `groups { reverse users_by_group }` | `UsersByGroupNotEmpty` | [StillReferenced][ErrorKind::StillReferenced] |
+/// | `immutable $field` | `users { immutable created }` | `UsersCreatedImmutable` | [Immutable][ErrorKind::Immutable] |
+/// | `version $field` | `users { version rev }` | `UsersRevStale` | [Stale][ErrorKind::Stale] |
+/// | `constraint $name` | `users { constraint name_empty }` | `UsersNameEmpty` | [Constraint][ErrorKind::Constraint] |
+/// | `transition $name` | `users { transition rev_decreased }` | `UsersRevDecreased` | [Transition][ErrorKind::Transition] |
+///
+/// Constraints and transitions return their errors themselves, so their variants are only
+/// declared here to be returned by those methods.
+///
+/// Instead of naming every variant again, a table can declare the generated enum with
+/// `errors ErrorType` in place of its `missing` entry. Entries of that table that leave out
+/// their error (`primary users id`, `unique user_by_email email`, `foreign groups group`,
+/// `reverse users_by_group id`, `immutable created`, `version rev`) then return the variant
+/// that this macro generates for them, and entries that do not have an error (such as `index`
+/// or `constraint` entries) can leave out the `=> ()`. A check that the enum does not declare
+/// then fails to compile, instead of being mapped to the wrong variant.
+///
+/// To use the generated errors within an application error type, implement
+/// `From<ErrorType>` for it and use the [kind](ErrorKind) to decide how to map them.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::{BTreeMap, BTreeSet};
+/// use macrodb::{errors, table, ErrorKind};
+/// # type UserId = u64;
+/// # type GroupId = u64;
+/// # #[derive(Clone)]
+/// # pub struct User { id: UserId, email: String, group: GroupId }
+/// # #[derive(Clone)]
+/// # pub struct Group { id: GroupId }
+///
+/// errors!(
+///     pub enum Error {
+///         users { missing, primary, unique user_by_email, foreign groups },
+///         groups { missing, primary, reverse users_by_group },
+///     }
+/// );
+///
+/// # #[derive(Default)]
+/// # pub struct Database {
+/// #     users: BTreeMap<UserId, User>,
+/// #     user_by_email: BTreeMap<String, UserId>,
+/// #     users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+/// #     groups: BTreeMap<GroupId, Group>,
+/// # }
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UsersNotFound,
+///         primary users id => Error::UsersExists,
+///         unique user_by_email email => Error::UserByEmailExists,
+///         foreign groups group => Error::UsersGroupsNotFound,
+///         index users_by_group group => ()
+///     );
+///     // the same, using the variants generated for every check
+///     table!(
+///         groups: Group,
+///         id: GroupId,
+///         errors Error,
+///         primary groups id,
+///         reverse users_by_group id
+///     );
+/// }
+///
+/// # let mut database = Database::default();
+/// let error = database.groups_delete(0).err().unwrap();
+/// assert_eq!(error, Error::GroupsNotFound);
+/// assert_eq!(error.kind(), ErrorKind::NotFound);
+/// assert_eq!(error.table(), "groups");
+/// assert_eq!(error.to_string(), "groups row not found");
+/// ```
+#[macro_export]
+macro_rules! errors {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($table:ident { $($checks:tt)* }),* $(,)? }) => {
+        $crate::errors_parse!([$(#[$meta])* $vis enum $name] [] $($table [$($checks)*])*);
+    };
+}
+
+/// Turn the checks of every table into a list of variants, then generate the error enum.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! errors_parse {
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [missing $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$table:camel NotFound] concat!(stringify!($table), " row not found"), NotFound, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [primary $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$table:camel Exists] concat!(stringify!($table), " row already exists"), AlreadyExists, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [unique $index:ident $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$index:camel Exists] concat!(stringify!($table), " row already exists in ", stringify!($index)), AlreadyExists, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [foreign $foreign:ident $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$table:camel $foreign:camel NotFound] concat!(stringify!($table), " row references missing ", stringify!($foreign), " row"), MissingReference, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [reverse $index:ident $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$index:camel NotEmpty] concat!(stringify!($table), " row is still referenced by ", stringify!($index)), StillReferenced, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [immutable $field:ident $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$table:camel $field:camel Immutable] concat!(stringify!($table), " field ", stringify!($field), " cannot be changed"), Immutable, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [version $field:ident $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$table:camel $field:camel Stale] concat!(stringify!($table), " row was changed since ", stringify!($field), " was read"), Stale, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [constraint $constraint:ident $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$table:camel $constraint:camel] concat!(stringify!($table), " row violates constraint ", stringify!($constraint)), Constraint, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [transition $transition:ident $(, $($rest:tt)*)?] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)* {
+            [$table:camel $transition:camel] concat!(stringify!($table), " row change rejected by transition ", stringify!($transition)), Transition, $table
+        }] $table [$($($rest)*)?] $($tables)*);
+    };
+    ([$($head:tt)*] [$($done:tt)*] $table:ident [] $($tables:tt)*) => {
+        $crate::errors_parse!([$($head)*] [$($done)*] $($tables)*);
+    };
+    ([$(#[$meta:meta])* $vis:vis enum $name:ident] [$({ [$($variant:tt)*] $message:expr, $kind:ident, $table:ident })*]) => {
+        $crate::paste! {
+            $(#[$meta])*
+            #[allow(clippy::enum_variant_names)]
+            #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+            $vis enum $name {
+                $(
+                    #[doc = $message]
+                    [<$($variant)*>],
+                )*
+            }
+
+            impl $name {
+                /// Kind of check that produced this error.
+                pub fn kind(&self) -> $crate::ErrorKind {
+                    match self {
+                        $($name::[<$($variant)*>] => $crate::ErrorKind::$kind,)*
+                    }
+                }
+
+                /// Name of the table that this error belongs to.
+                pub fn table(&self) -> &'static str {
+                    match self {
+                        $($name::[<$($variant)*>] => stringify!($table),)*
+                    }
+                }
+            }
+
+            impl ::core::fmt::Display for $name {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    f.write_str(match self {
+                        $($name::[<$($variant)*>] => $message,)*
+                    })
+                }
+            }
+
+            $crate::errors_impl_error!($name);
+        }
+    };
+}
+
+/// Implement the standard error trait for a generated error enum, if the `std` feature is
+/// enabled.
+#[doc(hidden)]
+#[cfg(feature = "std")]
+#[macro_export]
+macro_rules! errors_impl_error {
+    ($name:ident) => {
+        impl ::std::error::Error for $name {}
+    };
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "std"))]
+#[macro_export]
+macro_rules! errors_impl_error {
+    ($name:ident) => {};
+}
+
+/// Deserialize the tables of a snapshot, either from a struct with one field per table or from
+/// a sequence of tables.
+#[doc(hidden)]
+#[cfg(feature = "serde")]
+#[macro_export]
+macro_rules! snapshot_tables {
+    ($deserializer:expr, $name:literal, $expecting:literal, $($table:ident: $value:ty),*) => {{
+        const TABLES: &[&str] = &[$(stringify!($table)),*];
+
+        struct Visitor;
+
+        impl<'de> $crate::serde::de::Visitor<'de> for Visitor {
+            type Value = ($($value,)*);
+
+            fn expecting(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                f.write_str($expecting)
+            }
+
+            fn visit_seq<A: $crate::serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut length = 0;
+                Ok(($({
+                    let $table = seq
+                        .next_element()?
+                        .ok_or_else(|| $crate::serde::de::Error::invalid_length(length, &self))?;
+                    length += 1;
+                    $table
+                },)*))
+            }
+
+            fn visit_map<A: $crate::serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                $(let mut $table = None;)*
+                while let Some(key) = map.next_key::<$crate::String>()? {
+                    $(if key == stringify!($table) {
+                        if $table.is_some() {
+                            return Err($crate::serde::de::Error::duplicate_field(stringify!($table)));
+                        }
+                        $table = Some(map.next_value()?);
+                    } else)* {
+                        return Err($crate::serde::de::Error::unknown_field(&key, TABLES));
+                    }
+                }
+                Ok(($($table.ok_or_else(|| $crate::serde::de::Error::missing_field(stringify!($table)))?,)*))
+            }
+        }
+
+        $deserializer.deserialize_struct($name, TABLES, Visitor)
+    }};
+}
+
+/// Serialize the tables of a snapshot as a struct with one field per table.
+#[doc(hidden)]
+#[cfg(feature = "serde")]
+#[macro_export]
+macro_rules! snapshot_serialize {
+    ($serializer:expr, $($table:ident: $rows:expr),*) => {{
+        use $crate::serde::ser::SerializeStruct;
+        let mut state = $serializer.serialize_struct("Snapshot", [$(stringify!($table)),*].len())?;
+        $(state.serialize_field(stringify!($table), &$rows)?;)*
+        state.end()
+    }};
+}
+
+/// Load the rows of a snapshot into an empty database, returning the error created by `$invalid`
+/// from the table, position and error of the first row that is rejected. All rows are stored before any are checked, so that the
+/// order of the tables and of their rows does not matter for foreign keys.
+#[doc(hidden)]
+#[cfg(feature = "serde")]
+#[macro_export]
+macro_rules! snapshot_restore {
+    ($database:expr, $invalid:expr, $($table:ident: $rows:expr),*) => {
+        $(let $table = {
+            let mut keys = $crate::Vec::new();
+            for (row, data) in $rows.into_iter().enumerate() {
+                keys.push($crate::paste! { $database.[<$table _store>](data) }.map_err(|error| $invalid(stringify!($table), row, error))?);
+            }
+            keys
+        };)*
+        $(for (row, key) in $table.iter().enumerate() {
+            $crate::paste! { $database.[<$table _load_stored>](key) }.map_err(|error| $invalid(stringify!($table), row, error))?;
+        })*
+    };
+}
+
+/// # Snapshot Macro
+///
+/// Generate methods to save and load the tables of a database using [serde]. This macro is only
+/// available with the `serde` feature.
+///
+/// Only the tables are saved, the indices are derived data. When a snapshot is loaded, the rows
+/// of all tables are stored first, and every row is then checked as if it were inserted
+/// (primary key, `unique` and `foreign` indices and constraints) and the indices are rebuilt.
+/// Since all rows are present when the checks run, foreign keys may reference rows of tables
+/// that are listed later, and constraints see the whole snapshot. Hooks are not run and no
+/// changes are emitted while loading.
+///
+/// [serde]: https://serde.rs
+///
+/// ## Syntax
+///
+/// The macro is invoked in the impl block of the database, after the tables, with the error type
+/// of the database and the list of tables and their row types:
+///
+/// ```rust,ignore
+/// snapshot!(ErrorType, $table: RowType, ...);
+/// ```
+///
+/// Tables can be listed in any order. The row types must implement `Serialize` and
+/// `Deserialize`, and the database struct must implement [Default]. This generates these
+/// methods:
+///
+/// ```rust,ignore
+/// impl Database {
+///     /// Serialize all tables of the database.
+///     pub fn snapshot_save<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+///
+///     /// Deserialize a database, checking every row and rebuilding the indices.
+///     pub fn snapshot_load<'de, D: Deserializer<'de>>(
+///         deserializer: D,
+///     ) -> Result<Self, SnapshotError<ErrorType, D::Error>>;
+/// }
+/// ```
+///
+/// The snapshot is serialized as a struct with one field per table, which contains the rows of
+/// the table as a sequence. Loading fails with [SnapshotError::Decode] if the snapshot cannot be
+/// deserialized, and with [SnapshotError::Invalid] if one of the rows is rejected by the checks
+/// of its table.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::{snapshot, table};
+/// use serde::{Deserialize, Serialize};
+/// # #[derive(Debug, PartialEq)]
+/// # pub enum Error { UserIdExists, UserNotFound, UserEmailExists }
+/// # type UserId = u64;
+///
+/// #[derive(Clone, Serialize, Deserialize)]
+/// pub struct User {
+///     id: UserId,
+///     email: String,
+/// }
+///
+/// #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     user_by_email: BTreeMap<String, UserId>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         unique user_by_email email => Error::UserEmailExists
+///     );
+///     snapshot!(Error, users: User);
+/// }
+///
+/// # let mut database = Database::default();
+/// database.users_insert(User { id: 0, email: "alice@example.com".into() }).unwrap();
+/// let mut data = Vec::new();
+/// database.snapshot_save(&mut serde_json::Serializer::new(&mut data)).unwrap();
+///
+/// let loaded = Database::snapshot_load(&mut serde_json::Deserializer::from_slice(&data)).unwrap();
+/// assert_eq!(loaded.user_by_email.get("alice@example.com"), Some(&0));
+/// ```
+#[cfg(feature = "serde")]
+#[macro_export]
+macro_rules! snapshot {
+    ($errty:ty, $($table:ident: $type:ty),* $(,)?) => {
+        pub fn snapshot_save<S: $crate::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            $crate::snapshot_serialize!(serializer, $($table: $crate::SnapshotRows(&self.$table)),*)
+        }
+
+        pub fn snapshot_load<'de, D: $crate::serde::Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Self, $crate::SnapshotError<$errty, D::Error>>
+        where
+            Self: Default,
+        {
+            let ($($table,)*) = $crate::snapshot_tables!(deserializer, "Snapshot", "database snapshot", $($table: $crate::Vec<$type>),*)
+                .map_err($crate::SnapshotError::Decode)?;
+            let mut database = Self::default();
+            $crate::snapshot_restore!(
+                database,
+                |table, row, error| $crate::SnapshotError::Invalid { table, row, error },
+                $($table: $table),*
+            );
+            Ok(database)
+        }
+    };
+}
+
+/// # Delta Snapshot Macro
+///
+/// Generate methods to save and apply delta snapshots, which contain only the rows that were
+/// changed since a previous snapshot. This macro is only available with the `serde` feature.
+///
+/// Every table of the delta needs a `dirty` entry (see
+/// [Dirty Tracking](macro@table#dirty-tracking)), whose set holds the primary keys of the rows
+/// that were inserted, updated or deleted since the last marker. Saving a delta writes the
+/// current version of these rows, and the keys of the rows that no longer exist. Setting a
+/// marker empties the sets, so that the next delta only contains the rows that were changed
+/// after it. A database can be restored by loading a full [snapshot](macro@snapshot) and
+/// applying the deltas that were saved after it in order.
+///
+/// ## Syntax
+///
+/// The macro is invoked in the impl block of the database, after the tables, with the error type
+/// of the database and the list of tables with their row types, dirty sets and primary key
+/// types:
+///
+/// ```rust,ignore
+/// delta!(ErrorType, $table: RowType => $dirty: RowId, ...);
+/// ```
+///
+/// Tables are applied in the order they are listed in, so tables that are referenced by
+/// `foreign` entries must be listed before the tables that reference them. The row types and
+/// primary keys must implement `Serialize` and `Deserialize`. This generates these methods:
+///
+/// ```rust,ignore
+/// impl Database {
+///     /// Serialize the rows that were changed since the last marker.
+///     pub fn delta_save<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+///
+///     /// Set a marker, forgetting which rows were changed.
+///     pub fn delta_mark(&mut self);
+///
+///     /// Deserialize a delta and apply it to the database.
+///     pub fn delta_apply<'de, D: Deserializer<'de>>(
+///         &mut self,
+///         deserializer: D,
+///     ) -> Result<(), SnapshotError<ErrorType, D::Error>>;
+/// }
+/// ```
+///
+/// The delta is serialized as a struct with one field per table, which contains the `keys` of
+/// the changed rows and the `rows` that still exist. When a delta is applied, all changed rows
+/// are removed first, and the rows of the delta are then checked like the rows of a snapshot
+/// and added to the indices. Rows that were deleted are checked as if they were deleted, so
+/// that no rows are left referencing them. Applying a delta does not run hooks, emit changes or
+/// mark rows as changed. If applying a delta fails, the database may be left partially updated
+/// and should be discarded.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::{BTreeMap, BTreeSet};
+/// use macrodb::{delta, snapshot, table};
+/// use serde::{Deserialize, Serialize};
+/// # #[derive(Debug)]
+/// # pub enum Error { UserIdExists, UserNotFound }
+/// # type UserId = u64;
+///
+/// #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// pub struct User {
+///     id: UserId,
+///     name: String,
+/// }
+///
+/// #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     users_dirty: BTreeSet<UserId>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         dirty users_dirty _ => ()
+///     );
+///     snapshot!(Error, users: User);
+///     delta!(Error, users: User => users_dirty: UserId);
+/// }
+///
+/// let mut database = Database::default();
+/// database.users_insert(User { id: 0, name: "alice".into() }).ok().unwrap();
+/// database.users_insert(User { id: 1, name: "bob".into() }).ok().unwrap();
+/// let base = database.snapshot_save(serde_json::value::Serializer).unwrap();
+/// database.delta_mark();
+///
+/// database.users_update(User { id: 0, name: "alicia".into() }).ok().unwrap();
+/// database.users_delete(1).ok().unwrap();
+/// let delta = database.delta_save(serde_json::value::Serializer).unwrap();
+///
+/// let mut restored = Database::snapshot_load(base).unwrap();
+/// restored.delta_apply(delta).unwrap();
+/// assert_eq!(restored.users, database.users);
+/// ```
+#[cfg(feature = "serde")]
+#[macro_export]
+macro_rules! delta {
+    ($errty:ty, $($table:ident: $type:ty => $dirty:ident: $pkty:ty),* $(,)?) => {
+        pub fn delta_save<S: $crate::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use $crate::serde::ser::SerializeStruct;
+            let mut state = serializer.serialize_struct("Delta", [$(stringify!($table)),*].len())?;
+            $(state.serialize_field(stringify!($table), &$crate::DeltaRows {
+                table: &self.$table,
+                dirty: &self.$dirty,
+            })?;)*
+            state.end()
+        }
+
+        pub fn delta_mark(&mut self) {
+            $($crate::DirtySet::clear(&mut self.$dirty);)*
+        }
+
+        pub fn delta_apply<'de, D: $crate::serde::Deserializer<'de>>(
+            &mut self,
+            deserializer: D,
+        ) -> Result<(), $crate::SnapshotError<$errty, D::Error>> {
+            let ($($table,)*) = $crate::snapshot_tables!(deserializer, "Delta", "database delta", $($table: $crate::DeltaTable<$pkty, $type>),*)
+                .map_err($crate::SnapshotError::Decode)?;
+            // all changed rows are removed first, so that rows can swap unique values or move
+            // between rows of other tables
+            $(let $table = (
+                $table.keys.iter().filter_map(|id| $crate::paste! { self.[<$table _unload>](id) }).collect::<$crate::Vec<_>>(),
+                $table.rows,
+            );)*
+            $(for (row, data) in $table.1.into_iter().enumerate() {
+                $crate::paste! { self.[<$table _load>](data) }.map_err(|error| $crate::SnapshotError::Invalid {
+                    table: stringify!($table),
+                    row,
+                    error,
+                })?;
+            })*
+            $(for (row, data) in $table.0.iter().enumerate() {
+                $crate::paste! { self.[<$table _check_unloaded>](data) }.map_err(|error| $crate::SnapshotError::Invalid {
+                    table: stringify!($table),
+                    row,
+                    error,
+                })?;
+            })*
+            Ok(())
+        }
+    };
+}
+
+/// # Diff Macro
+///
+/// Generate a type describing the differences between two databases, with methods to compute
+/// and apply them.
+///
+/// Rows are matched by their primary key and compared with [PartialEq]. For every table, the
+/// diff contains the rows that were inserted, deleted and changed (see [TableDiff]). Applying a
+/// diff replays it through the generated `_insert`, `_update` and `_delete` methods of the
+/// tables, so every change is checked, hooks are run and changes are emitted like for any other
+/// change. Tables with soft deletion only compare the rows that were not deleted.
+///
+/// ## Syntax
+///
+/// Unlike the other macros, this macro is invoked outside of the impl block of the database, as
+/// it declares the diff type. It takes the database type, the error type of the database and a
+/// struct declaration with the tables, their row types and the name of their primary key
+/// fields:
+///
+/// ```rust,ignore
+/// diff!(
+///     Database, ErrorType,
+///     pub struct DatabaseDiff {
+///         $table: RowType => $id_field,
+///         ...
+///     }
+/// );
+/// ```
+///
+/// The struct has one [TableDiff] field per table, with the same name as the table. The row
+/// types must implement [Clone] and [PartialEq]. This generates these methods:
+///
+/// ```rust,ignore
+/// impl DatabaseDiff {
+///     /// Determine if the databases are the same.
+///     pub fn is_empty(&self) -> bool;
+/// }
+///
+/// impl Database {
+///     /// Determine the changes that turn this database into `other`.
+///     pub fn diff(&self, other: &Self) -> DatabaseDiff;
+///
+///     /// Apply the changes of a diff to this database.
+///     pub fn apply(&mut self, diff: DatabaseDiff) -> Result<(), ErrorType>;
+/// }
+/// ```
+///
+/// The changes are applied in three steps: first the deleted rows (with the tables in reverse
+/// order), then the changed rows and finally the inserted rows, with the tables in the order
+/// they are listed in. This way, a unique value can move to another row, as it is released
+/// before it is taken. Tables that are referenced by `foreign` entries should be listed before
+/// the tables that reference them. Changes that fail are retried after the others, since they
+/// may depend on them, for example an update that references an inserted row or a delete of a
+/// row that is only referenced until an update. Changed rows are rebased onto the current
+/// version of rows that have a `version` field, which is then incremented like for any other
+/// update.
+///
+/// When a step makes no progress, the changes that were applied are rolled back and the first
+/// error of the step is returned. Rolling back restores the rows exactly, without checks or
+/// hooks, and emits the reverting changes to sinks. Changes made by hooks to other tables are
+/// not rolled back. Two changed rows that swap a unique value cannot be applied, as each of
+/// them conflicts with the other.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::{diff, table};
+/// # #[derive(Debug)]
+/// # pub enum Error { UserIdExists, UserNotFound }
+/// # type UserId = u64;
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct User {
+///     id: UserId,
+///     name: String,
+/// }
+///
+/// #[derive(Clone, Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists
+///     );
+/// }
+///
+/// diff!(
+///     Database, Error,
+///     pub struct DatabaseDiff {
+///         users: User => id,
+///     }
+/// );
+///
+/// let mut database = Database::default();
+/// database.users_insert(User { id: 0, name: "alice".into() }).unwrap();
+/// let mut other = database.clone();
+/// other.users_update(User { id: 0, name: "alicia".into() }).unwrap();
+/// other.users_insert(User { id: 1, name: "bob".into() }).unwrap();
+///
+/// let diff = database.diff(&other);
+/// assert_eq!(diff.users.inserted.len(), 1);
+/// assert_eq!(diff.users.changed.len(), 1);
+///
+/// database.apply(diff).unwrap();
+/// assert_eq!(database.users, other.users);
+/// assert!(database.diff(&other).is_empty());
+/// ```
+#[macro_export]
+macro_rules! diff {
+    ($db:ty, $errty:ty, $(#[$meta:meta])* $vis:vis struct $name:ident { $($table:ident: $type:ty => $pk:ident),* $(,)? }) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($vis $table: $crate::TableDiff<$type>,)*
+        }
+
+        impl $name {
+            /// Determine if the databases are the same.
+            $vis fn is_empty(&self) -> bool {
+                true $(&& self.$table.is_empty())*
+            }
+        }
+
+        impl $db {
+            /// Determine the changes that turn this database into `other`.
+            $vis fn diff(&self, other: &Self) -> $name {
+                $crate::paste! {
+                    $name {
+                        $($table: {
+                            let mut diff = $crate::TableDiff::default();
+                            for row in $crate::TableMap::values(&self.$table).filter(|row| Self::[<$table _is_live>](row)) {
+                                match $crate::table_row!(other, $table, &row.$pk) {
+                                    None => diff.deleted.push(row.clone()),
+                                    Some(new) if new != row => diff.changed.push((row.clone(), new.clone())),
+                                    Some(_) => {}
+                                }
+                            }
+                            for row in $crate::TableMap::values(&other.$table).filter(|row| Self::[<$table _is_live>](row)) {
+                                if $crate::table_row!(self, $table, &row.$pk).is_none() {
+                                    diff.inserted.push(row.clone());
+                                }
+                            }
+                            diff
+                        },)*
+                    }
+                }
+            }
+
+            /// Apply the changes of a diff to this database.
+            $vis fn apply(&mut self, mut diff: $name) -> Result<(), $errty> {
+                $crate::paste! {
+                    $(let mut [<$table _undo>] = $crate::Vec::new();)*
+                    loop {
+                        let mut progress = false;
+                        let mut error = None;
+                        $crate::diff_delete!(self, diff, progress, error, $($table [<$table _undo>] $pk),*);
+                        $(for (old, new) in ::core::mem::take(&mut diff.$table.changed) {
+                            let row = self.[<$table _rebase>](new.clone());
+                            match self.[<$table _update>](row) {
+                                Ok(old) => {
+                                    [<$table _undo>].push($crate::Inverse::Update(old));
+                                    progress = true;
+                                }
+                                Err(failed) => {
+                                    error.get_or_insert(failed);
+                                    diff.$table.changed.push((old, new));
+                                }
+                            }
+                        })*
+                        $(for row in ::core::mem::take(&mut diff.$table.inserted) {
+                            match self.[<$table _insert>](row.clone()) {
+                                Ok(()) => {
+                                    [<$table _undo>].push($crate::Inverse::Delete(row));
+                                    progress = true;
+                                }
+                                Err(failed) => {
+                                    error.get_or_insert(failed);
+                                    diff.$table.inserted.push(row);
+                                }
+                            }
+                        })*
+                        match error {
+                            None => return Ok(()),
+                            // changes that failed are retried, as they may depend on later ones
+                            Some(_) if progress => {}
+                            Some(error) => {
+                                $(for inverse in [<$table _undo>].into_iter().rev() {
+                                    self.[<$table _reset>](inverse);
+                                })*
+                                return Err(error);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+}
+
+/// Delete the rows of a diff, with the tables in reverse order.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! diff_delete {
+    ($self:ident, $diff:ident, $progress:ident, $error:ident, ) => {};
+    ($self:ident, $diff:ident, $progress:ident, $error:ident, $table:ident $undo:ident $pk:ident $(, $tables:ident $undos:ident $pks:ident)*) => {
+        $crate::diff_delete!($self, $diff, $progress, $error, $($tables $undos $pks),*);
+        for row in ::core::mem::take(&mut $diff.$table.deleted) {
+            match $crate::paste! { $self.[<$table _delete>](row.$pk.clone()) } {
+                Ok(_) => {
+                    $undo.push($crate::Inverse::Insert(row));
+                    $progress = true;
+                }
+                Err(failed) => {
+                    $error.get_or_insert(failed);
+                    $diff.$table.deleted.push(row);
+                }
+            }
+        }
+    };
+}
+
+/// # Replication Macro
+///
+/// Generate a record type for replicating changes from a leader database to followers, and a
+/// method to apply these records on a follower.
+///
+/// The leader stores a [ChangeLog] in a field, which is declared as the sink of a `changes`
+/// entry of every replicated table (see [Change Data Capture](macro@table#change-data-capture)).
+/// Every change made to the leader is converted into a record and assigned the next sequence
+/// number. The entries of the log can then be sent to the followers in order, over any kind of
+/// channel. A follower applies the entries with the generated `apply_change` method, which
+/// checks that no entry was skipped and applies the change to the table. Since the change was
+/// already checked by the leader, only the primary key and the `unique` indices are checked
+/// again, so that a follower that has diverged from the leader rejects the change instead of
+/// corrupting its indices. The indices are updated, but hooks are not run and no changes are
+/// emitted.
+///
+/// ## Syntax
+///
+/// This macro is invoked outside of the impl block of the database, as it declares the record
+/// type. It takes the database type, the error type of the database, the name of the field of
+/// the follower that holds the sequence number of the next change to apply (a [u64]), and an
+/// enum declaration with the replicated tables and their row types:
+///
+/// ```rust,ignore
+/// replication!(
+///     Database, ErrorType, $sequence_field,
+///     pub enum Record {
+///         $table: RowType,
+///         ...
+///     }
+/// );
+/// ```
+///
+/// The enum has one variant per table, named after the table in camel case, which holds a
+/// [Change] of its row type. Every row type can only be used by one table. This generates:
+///
+/// ```rust,ignore
+/// impl From<Change<RowType>> for Record { ... }
+///
+/// impl Database {
+///     /// Apply a change that was made to the leader.
+///     pub fn apply_change(&mut self, entry: Sequenced<Record>) -> Result<(), ReplicationError<ErrorType>>;
+/// }
+/// ```
+///
+/// Entries must be applied in the order of their sequence numbers. If an entry was skipped,
+/// [ReplicationError::Gap] is returned with the sequence number that the follower expects, so
+/// that the missing entries can be sent again with [ChangeLog::since]. Entries that were
+/// already applied are rejected with [ReplicationError::Duplicate]. Neither error modifies the
+/// follower.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::{replication, table, ChangeLog, Change};
+/// # #[derive(Debug)]
+/// # pub enum Error { UserIdExists, UserNotFound }
+/// # type UserId = u64;
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct User {
+///     id: UserId,
+///     name: String,
+/// }
+///
+/// #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     log: ChangeLog<Record>,
+///     sequence: u64,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         changes log _ => ()
+///     );
+/// }
+///
+/// replication!(
+///     Database, Error, sequence,
+///     #[derive(Clone, Debug)]
+///     pub enum Record {
+///         users: User,
+///     }
+/// );
+///
+/// let mut leader = Database::default();
+/// let mut follower = Database::default();
+/// leader.users_insert(User { id: 0, name: "alice".into() }).unwrap();
+/// leader.users_update(User { id: 0, name: "alicia".into() }).unwrap();
+///
+/// for entry in leader.log.since(follower.sequence) {
+///     follower.apply_change(entry.clone()).unwrap();
+/// }
+/// assert_eq!(follower.users, leader.users);
+/// ```
+#[macro_export]
+macro_rules! replication {
+    ($db:ty, $errty:ty, $field:ident, $(#[$meta:meta])* $vis:vis enum $name:ident { $($table:ident: $type:ty),* $(,)? }) => {
+        $crate::paste! {
+            $(#[$meta])*
+            $vis enum $name {
+                $([<$table:camel>]($crate::Change<$type>),)*
+            }
+
+            $(impl From<$crate::Change<$type>> for $name {
+                fn from(change: $crate::Change<$type>) -> Self {
+                    $name::[<$table:camel>](change)
+                }
+            })*
+
+            impl $db {
+                /// Apply a change that was made to the leader.
+                $vis fn apply_change(
+                    &mut self,
+                    entry: $crate::Sequenced<$name>,
+                ) -> Result<(), $crate::ReplicationError<$errty>> {
+                    let sequence = entry.sequence;
+                    if sequence < self.$field {
+                        return Err($crate::ReplicationError::Duplicate { sequence });
+                    }
+                    if sequence > self.$field {
+                        return Err($crate::ReplicationError::Gap {
+                            expected: self.$field,
+                            received: sequence,
+                        });
+                    }
+                    let (table, result) = match entry.change {
+                        $($name::[<$table:camel>](change) => (stringify!($table), self.[<$table _apply_change>](change)),)*
+                    };
+                    result.map_err(|error| $crate::ReplicationError::Rejected { sequence, table, error })?;
+                    self.$field += 1;
+                    Ok(())
+                }
+            }
+        }
+    };
+}
+
+/// # History Macro
+///
+/// Generate methods to undo and redo the changes recorded in a [History].
+///
+/// Tables with a `history` entry (see [Undo History](macro@table#undo-history)) record the
+/// [Inverse] of every change into a [History] field of the database: the row is deleted again
+/// to undo an insert, the old row is written back to undo an update, and the row is inserted
+/// again (or restored, if it was soft deleted) to undo a delete. The inverses are grouped into
+/// actions with [History::commit]. Undoing an action replays its inverses in reverse order
+/// through the normal methods, so every step is checked and hooks run as usual, and records the
+/// inverses of these steps so that the action can be redone. Making a new change discards the
+/// actions that were undone.
+///
+/// Since undoing is a change like any other, it does not restore rows exactly. Undoing an
+/// update writes the old row back as a new update: the version is bumped instead of reset,
+/// `before_update` hooks run again (and see the row being undone as the old row), and
+/// transitions are checked, so an update that a transition does not allow in reverse cannot be
+/// undone. Use savepoints (see the [journal] macro) to roll back rows exactly.
+///
+/// ## Syntax
+///
+/// This macro is invoked outside of the impl block of the database, as it declares the record
+/// type. It takes the database type, the error type of the database, the name of the
+/// [History] field, and an enum declaration with the tables whose changes are recorded:
+///
+/// ```rust,ignore
+/// history!(
+///     Database, ErrorType, $history_field,
+///     pub enum Edit {
+///         $table: RowType,
+///         ...
+///     }
+/// );
+/// ```
+///
+/// The enum has one variant per table, named after the table in camel case, which holds an
+/// [Inverse] of its row type. Every row type can only be used by one table, and the enum must
+/// implement [Clone]. This generates:
+///
+/// ```rust,ignore
+/// impl From<Inverse<RowType>> for Edit { ... }
+///
+/// impl Database {
+///     /// Undo the last action, returning false if there is nothing to undo.
+///     pub fn undo(&mut self) -> Result<bool, ErrorType>;
+///
+///     /// Redo the last undone action, returning false if there is nothing to redo.
+///     pub fn redo(&mut self) -> Result<bool, ErrorType>;
+/// }
+/// ```
+///
+/// Changes that were not committed yet are committed as an action before undoing. If a step
+/// fails, for example because a row that is reinserted conflicts with a row inserted by a table
+/// that does not record its history, the steps that were already made are reverted, the action
+/// is kept and the error is returned.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::{history, table, History};
+/// # #[derive(Debug)]
+/// # pub enum Error { UserIdExists, UserNotFound }
+/// # type UserId = u64;
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct User {
+///     id: UserId,
+///     name: String,
+/// }
+///
+/// #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     history: History<Edit>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         history history _ => ()
+///     );
+/// }
+///
+/// history!(
+///     Database, Error, history,
+///     #[derive(Clone, Debug)]
+///     pub enum Edit {
+///         users: User,
+///     }
+/// );
+///
+/// let mut database = Database::default();
+/// database.users_insert(User { id: 0, name: "alice".into() }).unwrap();
+/// database.history.commit();
+/// database.users_update(User { id: 0, name: "alicia".into() }).unwrap();
+/// database.users_insert(User { id: 1, name: "bob".into() }).unwrap();
+///
+/// database.undo().unwrap();
+/// assert_eq!(database.users_get(&0).unwrap().name, "alice");
+/// assert!(database.users_get(&1).is_none());
+///
+/// database.redo().unwrap();
+/// assert_eq!(database.users_get(&0).unwrap().name, "alicia");
+/// assert!(database.users_get(&1).is_some());
+/// ```
+#[macro_export]
+macro_rules! history {
+    ($db:ty, $errty:ty, $field:ident, $(#[$meta:meta])* $vis:vis enum $name:ident { $($table:ident: $type:ty),* $(,)? }) => {
+        $crate::paste! {
+            $(#[$meta])*
+            $vis enum $name {
+                $([<$table:camel>]($crate::Inverse<$type>),)*
+            }
+
+            $(impl From<$crate::Inverse<$type>> for $name {
+                fn from(inverse: $crate::Inverse<$type>) -> Self {
+                    $name::[<$table:camel>](inverse)
+                }
+            })*
+
+            impl $db {
+                /// Undo the last action, returning false if there is nothing to undo.
+                $vis fn undo(&mut self) -> Result<bool, $errty> {
+                    match self.$field.begin_undo() {
+                        Some(action) => self.history_replay(action, true).map(|_| true),
+                        None => Ok(false),
+                    }
+                }
+
+                /// Redo the last undone action, returning false if there is nothing to redo.
+                $vis fn redo(&mut self) -> Result<bool, $errty> {
+                    match self.$field.begin_redo() {
+                        Some(action) => self.history_replay(action, false).map(|_| true),
+                        None => Ok(false),
+                    }
+                }
+
+                fn history_replay(&mut self, action: $crate::Vec<$name>, undo: bool) -> Result<(), $errty> {
+                    let result = action
+                        .iter()
+                        .rev()
+                        .try_for_each(|record| self.history_revert(record.clone()));
+                    if result.is_err() {
+                        // the steps of a failed action are reverted on a best effort basis,
+                        // since they were checked when they were made.
+                        for record in self.$field.begin_rollback().into_iter().rev() {
+                            let _ = self.history_revert(record);
+                        }
+                    }
+                    self.$field.finish(action, undo, result.is_ok());
+                    result
+                }
+
+                fn history_revert(&mut self, record: $name) -> Result<(), $errty> {
+                    match record {
+                        $($name::[<$table:camel>](inverse) => self.[<$table _revert>](inverse),)*
+                    }
+                }
+            }
+        }
+    };
+}
+
+/// # Journal Macro
+///
+/// Generate methods to create savepoints and roll back to them, using a [Journal].
+///
+/// Tables with a `journal` entry (see [Savepoints](macro@table#savepoints)) record the
+/// [Inverse] of every change into a [Journal] field of the database while a savepoint exists.
+/// Rolling back to a savepoint puts the rows changed since back exactly as they were, in
+/// reverse order of the changes. Unlike undoing with the [history] macro, nothing is checked and
+/// no hooks run, so versions are reset rather than bumped and transitions do not apply. The
+/// changes are still emitted to the sinks of the table (ignoring their errors, since rolling
+/// back cannot fail) and recorded in its history.
+///
+/// ## Syntax
+///
+/// This macro is invoked outside of the impl block of the database, as it declares the record
+/// type. It takes the database type, the name of the [Journal] field, and an enum declaration
+/// with the journaled tables:
+///
+/// ```rust,ignore
+/// journal!(
+///     Database, $journal_field,
+///     pub enum Step {
+///         $table: RowType,
+///         ...
+///     }
+/// );
+/// ```
+///
+/// The enum has one variant per table, named after the table in camel case, which holds an
+/// [Inverse] of its row type. Every row type can only be used by one table. This generates:
+///
+/// ```rust,ignore
+/// impl From<Inverse<RowType>> for Step { ... }
+///
+/// impl Database {
+///     /// Create a savepoint, which changes made from now on can be rolled back to.
+///     pub fn savepoint(&mut self) -> Savepoint;
+///
+///     /// Undo all changes made since the savepoint, which is kept.
+///     pub fn rollback_to(&mut self, savepoint: Savepoint);
+///
+///     /// Release the savepoint, keeping the changes made since.
+///     pub fn release(&mut self, savepoint: Savepoint);
+/// }
+/// ```
+///
+/// Savepoints are nested: rolling back to or releasing a savepoint also releases all
+/// savepoints created after it. Both methods panic if the savepoint was already released.
+/// Tables without a `journal` entry are not rolled back, and the rows that are put back are not
+/// checked against them: if such a table is changed while a savepoint exists, a rolled back row
+/// can reference a row that no longer exists. Tables referenced by journaled tables should
+/// therefore be journaled as well.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::{journal, table, Journal};
+/// # #[derive(Debug)]
+/// # pub enum Error { UserIdExists, UserNotFound }
+/// # type UserId = u64;
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// pub struct User {
+///     id: UserId,
+///     name: String,
+/// }
+///
+/// #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     journal: Journal<Step>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         journal journal _ => ()
+///     );
+/// }
+///
+/// journal!(
+///     Database, journal,
+///     #[derive(Clone, Debug)]
+///     pub enum Step {
+///         users: User,
+///     }
+/// );
+///
+/// let mut database = Database::default();
+/// let savepoint = database.savepoint();
+/// database.users_insert(User { id: 0, name: "alice".into() }).unwrap();
+///
+/// let step = database.savepoint();
+/// database.users_insert(User { id: 1, name: "bob".into() }).unwrap();
+/// assert!(database.users_insert(User { id: 1, name: "robert".into() }).is_err());
+/// database.rollback_to(step);
+/// database.release(savepoint);
+///
+/// assert_eq!(database.users_get(&0).unwrap().name, "alice");
+/// assert!(database.users_get(&1).is_none());
+/// ```
+#[macro_export]
+macro_rules! journal {
+    ($db:ty, $field:ident, $(#[$meta:meta])* $vis:vis enum $name:ident { $($table:ident: $type:ty),* $(,)? }) => {
+        $crate::paste! {
+            $(#[$meta])*
+            $vis enum $name {
+                $([<$table:camel>]($crate::Inverse<$type>),)*
+            }
+
+            $(impl From<$crate::Inverse<$type>> for $name {
+                fn from(inverse: $crate::Inverse<$type>) -> Self {
+                    $name::[<$table:camel>](inverse)
+                }
+            })*
+
+            impl $db {
+                /// Create a savepoint, which changes made from now on can be rolled back to.
+                $vis fn savepoint(&mut self) -> $crate::Savepoint {
+                    self.$field.savepoint()
+                }
+
+                /// Undo all changes made since the savepoint, which is kept.
+                $vis fn rollback_to(&mut self, savepoint: $crate::Savepoint) {
+                    let steps = self.$field.begin_rollback(savepoint);
+                    for step in steps.into_iter().rev() {
+                        match step {
+                            $($name::[<$table:camel>](inverse) => self.[<$table _reset>](inverse),)*
+                        }
+                    }
+                    self.$field.finish_rollback();
+                }
+
+                /// Release the savepoint, keeping the changes made since.
+                $vis fn release(&mut self, savepoint: $crate::Savepoint) {
+                    self.$field.release(savepoint);
+                }
+            }
+        }
+    };
+}
+
+/// # Write-Ahead Log Macro
+///
+/// Generate methods to recover a database from a [Wal] and to compact it. This macro is only
+/// available with the `wal` feature.
+///
+/// Every change made to the database is appended to the log, which is stored in the database
+/// struct as an `Option<Wal<C>>` and declared as the sink of a `changes` entry of every table
+/// (see [Change Data Capture](macro@table#change-data-capture)). On startup, the snapshot and the
+/// log are read and replayed onto an empty database. Rows of the snapshot are checked and added
+/// to the indices like when loading a [snapshot](macro@snapshot), the records of the log are
+/// checked and applied like the original changes, but hooks are not run and no changes are
+/// emitted. Compacting the database writes all tables to a new snapshot and empties the log.
+///
+/// ## Syntax
+///
+/// The macro is invoked in the impl block of the database, after the tables, with the name of
+/// the field holding the log, the error type of the database and the list of tables and their
+/// row types:
+///
+/// ```rust,ignore
+/// wal!($field, ErrorType, $table: RowType, ...);
+/// ```
+///
+/// Tables can be listed in any order, the snapshot is stored and checked like the ones of the
+/// [snapshot](macro@snapshot) macro. The row types must implement `Serialize` and `Deserialize`.
+/// This generates these methods:
+///
+/// ```rust,ignore
+/// impl Database {
+///     /// Load the snapshot and replay the log onto this (empty) database.
+///     pub fn wal_recover(&mut self) -> Result<WalRecovery, WalError<ErrorType>>;
+///
+///     /// Write all tables to a new snapshot and empty the log.
+///     pub fn wal_compact(&mut self) -> Result<(), WalError<ErrorType>>;
+/// }
+/// ```
+///
+/// Both methods panic if the log field is `None`. The log is taken out of the database while
+/// recovering, so that the replayed changes are not logged again.
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::{table, wal, Wal, WalCodec};
+/// use serde::{de::DeserializeOwned, Deserialize, Serialize};
+/// # pub enum Error { UserIdExists, UserNotFound, LogFailed }
+/// # type UserId = u64;
+///
+/// /// Codec that stores records as JSON.
+/// pub struct Json;
+///
+/// impl WalCodec for Json {
+///     type Error = serde_json::Error;
+///
+///     fn encode<T: Serialize>(&mut self, value: &T, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+///         serde_json::to_writer(output, value)
+///     }
+///
+///     fn decode<T: DeserializeOwned>(&mut self, input: &[u8]) -> Result<T, Self::Error> {
+///         serde_json::from_slice(input)
+///     }
+/// }
+///
+/// #[derive(Clone, Serialize, Deserialize)]
+/// pub struct User {
+///     id: UserId,
+///     name: String,
+/// }
+///
+/// #[derive(Default)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     wal: Option<Wal<Json>>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         changes wal _ => Error::LogFailed
+///     );
+///     wal!(wal, Error, users: User);
+/// }
+///
+/// # let directory = tempfile::tempdir().unwrap();
+/// let mut database = Database::default();
+/// database.wal = Some(Wal::open(directory.path(), Json).unwrap());
+/// database.wal_recover().ok().unwrap();
+/// database.users_insert(User { id: 0, name: "alice".into() }).ok().unwrap();
+/// drop(database);
+///
+/// let mut database = Database::default();
+/// database.wal = Some(Wal::open(directory.path(), Json).unwrap());
+/// database.wal_recover().ok().unwrap();
+/// assert_eq!(database.users_get(&0).unwrap().name, "alice");
+/// ```
+#[cfg(feature = "wal")]
+#[macro_export]
+macro_rules! wal {
+    ($field:ident, $errty:ty, $($table:ident: $type:ty),* $(,)?) => {
+        pub fn wal_recover(&mut self) -> Result<$crate::WalRecovery, $crate::WalError<$errty>> {
+            let mut wal = self.$field.take().expect(concat!(stringify!($field), " is not set"));
+            let result = self.wal_replay(&mut wal);
+            self.$field = Some(wal);
+            result
+        }
+
+        #[doc(hidden)]
+        pub fn wal_replay<C: $crate::WalCodec>(
+            &mut self,
+            wal: &mut $crate::Wal<C>,
+        ) -> Result<$crate::WalRecovery, $crate::WalError<$errty>> {
+            let pending = wal.take_pending();
+            if let Some(data) = pending.snapshot {
+                // the snapshot has the same layout as the ones of the snapshot macro
+                struct Tables(($($crate::Vec<$type>,)*));
+
+                impl<'de> $crate::serde::Deserialize<'de> for Tables {
+                    fn deserialize<D: $crate::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                        $crate::snapshot_tables!(deserializer, "Snapshot", "database snapshot", $($table: $crate::Vec<$type>),*)
+                            .map(Tables)
+                    }
+                }
+
+                let Tables(($($table,)*)) = wal.decode(&data)?;
+                $crate::snapshot_restore!(
+                    self,
+                    |table, row, error| $crate::WalError::Snapshot { table, row, error },
+                    $($table: $table),*
+                );
+            }
+            for (record, (table, data)) in pending.records.into_iter().enumerate() {
+                $(if table == stringify!($table) {
+                    let change: $crate::Change<$type> = wal.decode(&data)?;
+                    $crate::paste! { self.[<$table _apply>](change) }.map_err(|error| $crate::WalError::Log {
+                        table: stringify!($table),
+                        record,
+                        error,
+                    })?;
+                } else)* {
+                    return Err($crate::WalError::UnknownTable(table));
+                }
+            }
+            Ok(pending.recovery)
+        }
+
+        pub fn wal_compact(&mut self) -> Result<(), $crate::WalError<$errty>> {
+            $crate::paste! {
+                struct Tables<$([<$table:camel Rows>]),*> {
+                    $($table: [<$table:camel Rows>],)*
+                }
+
+                impl<$([<$table:camel Rows>]: $crate::serde::Serialize),*> $crate::serde::Serialize for Tables<$([<$table:camel Rows>]),*> {
+                    fn serialize<S: $crate::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                        $crate::snapshot_serialize!(serializer, $($table: self.$table),*)
+                    }
+                }
+            }
+
+            let tables = Tables {
+                $($table: $crate::SnapshotRows(&self.$table),)*
+            };
+            let wal = self.$field.as_mut().expect(concat!(stringify!($field), " is not set"));
+            wal.compact(&tables)?;
+            Ok(())
+        }
+    };
+}
+
+/// Generate read-only lookups on an archived database.
+///
+/// A database whose tables and indices are stored in [BTreeMap][alloc::collections::BTreeMap]
+/// or [HashMap][std::collections::HashMap] fields can be archived with
+/// [rkyv](https://rkyv.org) by deriving `Archive` and `Serialize` on it and on its row types.
+/// The archive contains the indices as well, so it can be memory-mapped with [MappedArchive]
+/// and queried immediately, without loading the rows or rebuilding the indices. Fields that
+/// cannot be archived, such as change sinks, can be skipped with `#[with(rkyv::with::Skip)]`.
+///
+/// ## Syntax
+///
+/// The macro is invoked in an impl block of the archived database type, once for every table,
+/// with the name and row type of the table and the name and type of its primary key. Tables
+/// declared with `soft_delete` also name their tombstone field, so that deleted rows are
+/// skipped like in the generated `_get` method of the database:
+///
+/// ```rust,ignore
+/// archived_table!($table: RowType, $pk: KeyType);
+/// archived_table!($table: RowType, $pk: KeyType, soft_delete $field);
+/// ```
+///
+/// This generates a method to look up archived rows by their archived primary key:
+///
+/// ```rust,ignore
+/// impl ArchivedDatabase {
+///     pub fn users_get(&self, id: &Archived<UserId>) -> Option<&Archived<User>>;
+/// }
+/// ```
+///
+/// Indices are plain archived maps, which can be used directly through the fields of the
+/// archived database. Archives are validated when they are opened, which requires the
+/// archived types to implement `CheckBytes` (with `#[archive(check_bytes)]`).
+///
+/// ## Example
+///
+/// ```rust
+/// # use std::collections::BTreeMap;
+/// use macrodb::{archived_table, table, MappedArchive};
+/// use rkyv::{Archive, Serialize};
+/// # pub enum Error { UserIdExists, UserNotFound, UserEmailExists }
+/// # type UserId = u64;
+///
+/// #[derive(Clone, Archive, Serialize)]
+/// #[archive(check_bytes)]
+/// pub struct User {
+///     id: UserId,
+///     email: String,
+/// }
+///
+/// #[derive(Default, Archive, Serialize)]
+/// #[archive(check_bytes)]
+/// pub struct Database {
+///     users: BTreeMap<UserId, User>,
+///     user_by_email: BTreeMap<String, UserId>,
+/// }
+///
+/// impl Database {
+///     table!(
+///         users: User,
+///         id: UserId,
+///         missing Error => Error::UserNotFound,
+///         primary users id => Error::UserIdExists,
+///         unique user_by_email email => Error::UserEmailExists
+///     );
+/// }
+///
+/// impl ArchivedDatabase {
+///     archived_table!(users: User, id: UserId);
+/// }
+///
+/// # let directory = tempfile::tempdir().unwrap();
+/// # let path = directory.path().join("database");
+/// let mut database = Database::default();
+/// database.users_insert(User { id: 0, email: "alice@example.com".into() }).ok().unwrap();
+/// MappedArchive::save(&database, &path).unwrap();
+///
+/// // SAFETY: the archive is not modified while it is mapped
+/// let archived = unsafe { MappedArchive::<Database>::open(&path) }.unwrap();
+/// assert_eq!(archived.users_get(&0).unwrap().email, "alice@example.com");
+/// assert_eq!(archived.user_by_email.get("alice@example.com"), Some(&0));
+/// ```
+#[cfg(feature = "rkyv")]
+#[macro_export]
+macro_rules! archived_table {
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty) => {
+        $crate::paste! {
+            pub fn [<$table _get>](
+                &self,
+                $pk: &<$pkty as $crate::rkyv::Archive>::Archived,
+            ) -> Option<&<$type as $crate::rkyv::Archive>::Archived> {
+                $crate::ArchivedTableMap::get(&self.$table, $pk)
+            }
+        }
+    };
+    ($table:ident: $type:ty, $pk:ident: $pkty:ty, soft_delete $field:ident) => {
+        $crate::paste! {
+            pub fn [<$table _get>](
+                &self,
+                $pk: &<$pkty as $crate::rkyv::Archive>::Archived,
+            ) -> Option<&<$type as $crate::rkyv::Archive>::Archived> {
+                $crate::ArchivedTableMap::get(&self.$table, $pk)
+                    .filter(|row| !$crate::ArchivedTombstone::is_deleted(&row.$field))
+            }
+        }
+    };
 }
@@ -0,0 +1,277 @@
+//! Traits for the maps and sets that tables and indices are stored in.
+//!
+//! The methods generated by the [table](macro@crate::table) macro only access the database
+//! struct through these traits. This makes the requirements on the storage types explicit: any
+//! map can be used for a table or a unique index as long as it implements [TableMap], and any
+//! map of sets can be used for an index as long as the set implements [IndexSet].
+//! Indices that are queried with range or prefix lookups also need to implement [OrderedMap],
+//! which is not available for the hash maps.
+//!
+//! Implementations for the `alloc` collections are always available, the ones for the `std`
+//! collections require the `std` feature. Implementations
+//! for collections from other crates are enabled by the cargo feature with the same name as the
+//! crate:
+//!
+//! | Feature | Types |
+//! | --- | --- |
+//! | `hashbrown` | `hashbrown::{HashMap, HashSet}` |
+//! | `im` | `im::{HashMap, HashSet, OrdMap, OrdSet}` |
+//! | `indexmap` | `indexmap::{IndexMap, IndexSet}` |
+//! | `avl` | `avl::{AvlTreeMap, AvlTreeSet}` |
+//! | `aatree` | `aatree::{AATreeMap, AATreeSet}` |
+//! | `btree-slab` | `btree_slab::{BTreeMap, BTreeSet}` |
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::borrow::Borrow;
+#[cfg(feature = "std")]
+use core::hash::{BuildHasher, Hash};
+use core::ops::RangeBounds;
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+
+/// Implement [TableMap] for a map type by forwarding to its inherent methods.
+macro_rules! table_map_impl {
+    ($map:ty, $max_key:expr, $($bounds:tt)*) => {
+        impl<$($bounds)*> $crate::TableMap for $map {
+            type Key = K;
+            type Value = V;
+
+            fn get(&self, key: &K) -> Option<&V> {
+                <$map>::get(self, key)
+            }
+
+            fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+                <$map>::get_mut(self, key)
+            }
+
+            fn insert(&mut self, key: K, value: V) -> Option<V> {
+                <$map>::insert(self, key, value)
+            }
+
+            fn remove(&mut self, key: &K) -> Option<V> {
+                <$map>::remove(self, key)
+            }
+
+            fn values(&self) -> impl Iterator<Item = &V> {
+                <$map>::values(self)
+            }
+
+            fn max_key(&self) -> Option<&K>
+            where
+                K: Ord,
+            {
+                let max_key: fn(&Self) -> Option<&K> = $max_key;
+                max_key(self)
+            }
+        }
+    };
+}
+
+/// Implement [IndexSet] for a set type by forwarding to its inherent methods.
+macro_rules! index_set_impl {
+    ($set:ty, $($bounds:tt)*) => {
+        impl<$($bounds)*> $crate::IndexSet for $set {
+            type Value = V;
+
+            fn insert(&mut self, value: V) -> bool {
+                <$set>::insert(self, value)
+            }
+
+            fn remove(&mut self, value: &V) -> bool {
+                <$set>::remove(self, value)
+            }
+
+            fn is_empty(&self) -> bool {
+                <$set>::is_empty(self)
+            }
+
+            fn iter(&self) -> impl Iterator<Item = &V> {
+                <$set>::iter(self)
+            }
+        }
+    };
+}
+
+/// Implement [OrderedMap] for a map type by forwarding to its inherent `range` method.
+macro_rules! ordered_map_impl {
+    ($map:ty, $($bounds:tt)*) => {
+        impl<$($bounds)*> $crate::OrderedMap for $map {
+            fn range<'a, Q, R>(&'a self, range: R) -> impl Iterator<Item = (&'a K, &'a V)> + 'a
+            where
+                K: Borrow<Q>,
+                Q: Ord + ?Sized + 'a,
+                R: RangeBounds<Q> + 'a,
+            {
+                <$map>::range(self, range)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "aatree")]
+mod aatree;
+#[cfg(feature = "avl")]
+mod avl;
+#[cfg(feature = "btree-slab")]
+mod btree_slab;
+#[cfg(feature = "hashbrown")]
+mod hashbrown;
+#[cfg(feature = "im")]
+mod im;
+#[cfg(feature = "indexmap")]
+mod indexmap;
+
+/// Map from keys to values, used to store tables and unique indices.
+///
+/// Tables are stored as maps from `RowId` to `RowType`, unique indices as maps from
+/// `IndexType` to `RowId`.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be used as a table or unique index map",
+    note = "implement `macrodb::TableMap` for it, or enable the macrodb feature for the crate it comes from"
+)]
+pub trait TableMap {
+    /// Type of the keys of this map.
+    type Key;
+
+    /// Type of the values of this map.
+    type Value;
+
+    /// Get a reference to the value stored under `key`.
+    fn get(&self, key: &Self::Key) -> Option<&Self::Value>;
+
+    /// Get a mutable reference to the value stored under `key`.
+    fn get_mut(&mut self, key: &Self::Key) -> Option<&mut Self::Value>;
+
+    /// Insert a value, returning the previous value stored under `key`.
+    fn insert(&mut self, key: Self::Key, value: Self::Value) -> Option<Self::Value>;
+
+    /// Remove the value stored under `key`, returning it.
+    fn remove(&mut self, key: &Self::Key) -> Option<Self::Value>;
+
+    /// Iterate over all values of the map, in the order of the map.
+    fn values(&self) -> impl Iterator<Item = &Self::Value>;
+
+    /// Get the largest key in the map, used to determine the next free primary key.
+    fn max_key(&self) -> Option<&Self::Key>
+    where
+        Self::Key: Ord;
+}
+
+/// Set of row identifiers, used as the value type of indices.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be used as an index set",
+    note = "implement `macrodb::IndexSet` for it, or enable the macrodb feature for the crate it comes from"
+)]
+pub trait IndexSet {
+    /// Type of the values of this set.
+    type Value;
+
+    /// Insert a value, returning `false` if it was already present.
+    fn insert(&mut self, value: Self::Value) -> bool;
+
+    /// Remove a value, returning `false` if it was not present.
+    fn remove(&mut self, value: &Self::Value) -> bool;
+
+    /// Determine if the set is empty.
+    fn is_empty(&self) -> bool;
+
+    /// Iterate over all values of the set, in the order of the set.
+    fn iter(&self) -> impl Iterator<Item = &Self::Value>;
+}
+
+/// Map with ordered keys, used to store indices that support range queries.
+///
+/// The `_range` and `_prefix` lookups generated by the [table_range](macro@crate::table_range)
+/// and [table_prefix](macro@crate::table_prefix) macros require the index map to implement this.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` does not support range queries",
+    note = "use an ordered map such as `BTreeMap` for indices with range or prefix lookups"
+)]
+pub trait OrderedMap: TableMap {
+    /// Iterate over the entries whose keys are within `range`, in ascending key order.
+    fn range<'a, Q, R>(
+        &'a self,
+        range: R,
+    ) -> impl Iterator<Item = (&'a Self::Key, &'a Self::Value)> + 'a
+    where
+        Self::Key: Borrow<Q>,
+        Q: Ord + ?Sized + 'a,
+        R: RangeBounds<Q> + 'a;
+}
+
+/// Map from keys to sets of values, used to store indices.
+///
+/// Indices are stored as maps from `IndexType` to sets of `RowId`. This is implemented for
+/// every [TableMap] whose values are an [IndexSet], so there is usually no need to implement it
+/// by hand.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be used as an index map",
+    note = "indices need to be a `macrodb::TableMap` whose values are a `macrodb::IndexSet`"
+)]
+pub trait MultiMap {
+    /// Type of the keys of this map.
+    type Key;
+
+    /// Type of the values stored in the sets of this map.
+    type Value;
+
+    /// Add `value` to the set stored under `key`, returning `false` if it was already present.
+    fn insert_value(&mut self, key: Self::Key, value: Self::Value) -> bool;
+
+    /// Remove `value` from the set stored under `key`, returning `false` if it was not present.
+    ///
+    /// Once the set is empty, the key is removed from the map.
+    fn remove_value(&mut self, key: &Self::Key, value: &Self::Value) -> bool;
+
+    /// Determine if there are any values stored under `key`.
+    fn has_values(&self, key: &Self::Key) -> bool;
+}
+
+impl<M> MultiMap for M
+where
+    M: TableMap,
+    M::Value: IndexSet + Default,
+{
+    type Key = M::Key;
+    type Value = <M::Value as IndexSet>::Value;
+
+    fn insert_value(&mut self, key: Self::Key, value: Self::Value) -> bool {
+        match self.get_mut(&key) {
+            Some(values) => values.insert(value),
+            None => {
+                let mut values = M::Value::default();
+                values.insert(value);
+                self.insert(key, values);
+                true
+            }
+        }
+    }
+
+    fn remove_value(&mut self, key: &Self::Key, value: &Self::Value) -> bool {
+        let values = match self.get_mut(key) {
+            Some(values) => values,
+            None => return false,
+        };
+        if !values.remove(value) {
+            return false;
+        }
+        if values.is_empty() {
+            self.remove(key);
+        }
+        true
+    }
+
+    fn has_values(&self, key: &Self::Key) -> bool {
+        self.get(key)
+            .map(|values| !values.is_empty())
+            .unwrap_or(false)
+    }
+}
+
+table_map_impl!(BTreeMap<K, V>, |map| map.keys().next_back(), K: Ord, V);
+index_set_impl!(BTreeSet<V>, V: Ord);
+ordered_map_impl!(BTreeMap<K, V>, K: Ord, V);
+
+#[cfg(feature = "std")]
+table_map_impl!(HashMap<K, V, S>, |map| map.keys().max(), K: Hash + Eq, V, S: BuildHasher);
+#[cfg(feature = "std")]
+index_set_impl!(HashSet<V, S>, V: Hash + Eq, S: BuildHasher);
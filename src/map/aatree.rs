@@ -0,0 +1,20 @@
+use crate::OrderedMap;
+use ::aatree::{AATreeMap, AATreeSet};
+use core::borrow::Borrow;
+use core::ops::RangeBounds;
+
+table_map_impl!(AATreeMap<K, V>, |map| map.last_key_value().map(|(key, _)| key), K: Ord, V);
+index_set_impl!(AATreeSet<V>, V: Ord);
+
+// `AATreeMap` has no range query, so this scans the map and keeps the entries within the range.
+impl<K: Ord, V> OrderedMap for AATreeMap<K, V> {
+    fn range<'a, Q, R>(&'a self, range: R) -> impl Iterator<Item = (&'a K, &'a V)> + 'a
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized + 'a,
+        R: RangeBounds<Q> + 'a,
+    {
+        self.iter()
+            .filter(move |(key, _)| range.contains((*key).borrow()))
+    }
+}
@@ -0,0 +1,7 @@
+use ::avl::{AvlTreeMap, AvlTreeSet};
+use core::borrow::Borrow;
+use core::ops::RangeBounds;
+
+table_map_impl!(AvlTreeMap<K, V>, |map| map.keys().next_back(), K: Ord, V);
+index_set_impl!(AvlTreeSet<V>, V: Ord);
+ordered_map_impl!(AvlTreeMap<K, V>, K: Ord, V);
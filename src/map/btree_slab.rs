@@ -0,0 +1,37 @@
+use crate::OrderedMap;
+use ::btree_slab::{BTreeMap, BTreeSet};
+use core::borrow::Borrow;
+use core::ops::{Bound, RangeBounds};
+
+table_map_impl!(BTreeMap<K, V>, |map| map.last_key_value().map(|(key, _)| key), K: Ord, V);
+index_set_impl!(BTreeSet<V>, V: Ord);
+
+// `BTreeMap::range` ends ranges without an upper bound at the start of the map instead of at its
+// end, so these are bounded by the last key of the map instead.
+impl<K: Ord, V> OrderedMap for BTreeMap<K, V> {
+    fn range<'a, Q, R>(&'a self, range: R) -> impl Iterator<Item = (&'a K, &'a V)> + 'a
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized + 'a,
+        R: RangeBounds<Q> + 'a,
+    {
+        let start = range.start_bound();
+        let end = match (range.end_bound(), self.last_key_value()) {
+            (Bound::Unbounded, Some((last, _))) => {
+                let last = last.borrow();
+                let after_last = match start {
+                    Bound::Included(start) | Bound::Excluded(start) => start > last,
+                    Bound::Unbounded => false,
+                };
+                if after_last {
+                    return None.into_iter().flatten();
+                }
+                Bound::Included(last)
+            }
+            (end, _) => end,
+        };
+        Some(BTreeMap::range(self, (start, end)))
+            .into_iter()
+            .flatten()
+    }
+}
@@ -0,0 +1,5 @@
+use ::hashbrown::{HashMap, HashSet};
+use core::hash::{BuildHasher, Hash};
+
+table_map_impl!(HashMap<K, V, S>, |map| map.keys().max(), K: Hash + Eq, V, S: BuildHasher);
+index_set_impl!(HashSet<V, S>, V: Hash + Eq, S: BuildHasher);
@@ -0,0 +1,49 @@
+use crate::IndexSet;
+use ::im::{HashMap, HashSet, OrdMap, OrdSet};
+use core::borrow::Borrow;
+use core::hash::{BuildHasher, Hash};
+use core::ops::RangeBounds;
+
+table_map_impl!(HashMap<K, V, S>, |map| map.keys().max(), K: Hash + Eq + Clone, V: Clone, S: BuildHasher);
+table_map_impl!(OrdMap<K, V>, |map| map.get_max().map(|(key, _)| key), K: Ord + Clone, V: Clone);
+ordered_map_impl!(OrdMap<K, V>, K: Ord + Clone, V: Clone);
+
+impl<V: Hash + Eq + Clone, S: BuildHasher> IndexSet for HashSet<V, S> {
+    type Value = V;
+
+    fn insert(&mut self, value: V) -> bool {
+        HashSet::insert(self, value).is_none()
+    }
+
+    fn remove(&mut self, value: &V) -> bool {
+        HashSet::remove(self, value).is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        HashSet::is_empty(self)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &V> {
+        HashSet::iter(self)
+    }
+}
+
+impl<V: Ord + Clone> IndexSet for OrdSet<V> {
+    type Value = V;
+
+    fn insert(&mut self, value: V) -> bool {
+        OrdSet::insert(self, value).is_none()
+    }
+
+    fn remove(&mut self, value: &V) -> bool {
+        OrdSet::remove(self, value).is_some()
+    }
+
+    fn is_empty(&self) -> bool {
+        OrdSet::is_empty(self)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &V> {
+        OrdSet::iter(self)
+    }
+}
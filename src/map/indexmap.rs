@@ -0,0 +1,56 @@
+use crate::{IndexSet, TableMap};
+use core::hash::{BuildHasher, Hash};
+
+// Removal uses `shift_remove`, which keeps the remaining entries in insertion order at the cost of
+// shifting the entries after the removed one.
+impl<K: Hash + Eq, V, S: BuildHasher> TableMap for ::indexmap::IndexMap<K, V, S> {
+    type Key = K;
+    type Value = V;
+
+    fn get(&self, key: &K) -> Option<&V> {
+        ::indexmap::IndexMap::get(self, key)
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        ::indexmap::IndexMap::get_mut(self, key)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        ::indexmap::IndexMap::insert(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        ::indexmap::IndexMap::shift_remove(self, key)
+    }
+
+    fn values(&self) -> impl Iterator<Item = &V> {
+        ::indexmap::IndexMap::values(self)
+    }
+
+    fn max_key(&self) -> Option<&K>
+    where
+        K: Ord,
+    {
+        self.keys().max()
+    }
+}
+
+impl<V: Hash + Eq, S: BuildHasher> IndexSet for ::indexmap::IndexSet<V, S> {
+    type Value = V;
+
+    fn insert(&mut self, value: V) -> bool {
+        ::indexmap::IndexSet::insert(self, value)
+    }
+
+    fn remove(&mut self, value: &V) -> bool {
+        ::indexmap::IndexSet::shift_remove(self, value)
+    }
+
+    fn is_empty(&self) -> bool {
+        ::indexmap::IndexSet::is_empty(self)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &V> {
+        ::indexmap::IndexSet::iter(self)
+    }
+}
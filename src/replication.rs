@@ -0,0 +1,131 @@
+//! Replication of changes from a leader database to followers, used by the
+//! [replication](macro@crate::replication) macro.
+use crate::{Change, ChangeSink};
+use alloc::collections::VecDeque;
+use core::{convert::Infallible, fmt};
+
+/// Change with the sequence number that the leader assigned to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sequenced<R> {
+    /// Position of the change in the change log of the leader, starting at zero.
+    pub sequence: u64,
+    /// Change that was made.
+    pub change: R,
+}
+
+/// Ordered log of the changes made to a leader database.
+///
+/// The log is used as the sink of a `changes` entry of every replicated table. Every change is
+/// converted into the record type `R` (usually generated by the
+/// [replication](macro@crate::replication) macro) and assigned the next sequence number. Entries
+/// are kept until they are truncated, so that they can be sent again to followers that missed
+/// some of them.
+#[derive(Clone, Debug)]
+pub struct ChangeLog<R> {
+    next: u64,
+    entries: VecDeque<Sequenced<R>>,
+}
+
+impl<R> ChangeLog<R> {
+    /// Create an empty log, whose first change gets sequence number zero.
+    pub fn new() -> Self {
+        ChangeLog {
+            next: 0,
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Sequence number that the next change will be assigned.
+    pub fn sequence(&self) -> u64 {
+        self.next
+    }
+
+    /// Iterate over the retained entries, starting at sequence number `sequence`.
+    pub fn since(&self, sequence: u64) -> impl Iterator<Item = &Sequenced<R>> {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.sequence < sequence);
+        self.entries.range(start..)
+    }
+
+    /// Discard the entries before sequence number `sequence`, for example once all followers
+    /// have applied them.
+    pub fn truncate(&mut self, sequence: u64) {
+        let end = self
+            .entries
+            .partition_point(|entry| entry.sequence < sequence);
+        self.entries.drain(..end);
+    }
+}
+
+impl<R> Default for ChangeLog<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone, R: From<Change<T>>> ChangeSink<T> for ChangeLog<R> {
+    type Error = Infallible;
+
+    fn emit(&mut self, _table: &'static str, change: Change<&T>) -> Result<(), Infallible> {
+        self.entries.push_back(Sequenced {
+            sequence: self.next,
+            change: change.cloned().into(),
+        });
+        self.next += 1;
+        Ok(())
+    }
+}
+
+/// Error returned when a follower applies a replicated change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplicationError<E> {
+    /// Changes before this one are missing, they need to be sent again starting at `expected`.
+    Gap {
+        /// Sequence number of the next change that the follower can apply.
+        expected: u64,
+        /// Sequence number of the change that was received.
+        received: u64,
+    },
+    /// Change was already applied.
+    Duplicate {
+        /// Sequence number of the change that was received.
+        sequence: u64,
+    },
+    /// Change does not apply to the follower, which has diverged from the leader.
+    Rejected {
+        /// Sequence number of the change.
+        sequence: u64,
+        /// Name of the table that the change was made to.
+        table: &'static str,
+        /// Error returned when applying the change.
+        error: E,
+    },
+}
+
+impl<E: fmt::Display> fmt::Display for ReplicationError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplicationError::Gap { expected, received } => {
+                write!(
+                    f,
+                    "expected change {expected}, but received change {received}"
+                )
+            }
+            ReplicationError::Duplicate { sequence } => {
+                write!(f, "change {sequence} was already applied")
+            }
+            ReplicationError::Rejected {
+                sequence,
+                table,
+                error,
+            } => write!(
+                f,
+                "change {sequence} of table {table} was rejected: {error}"
+            ),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> core::error::Error for ReplicationError<E> {}
@@ -0,0 +1,52 @@
+//! Support types for snapshots generated by the [snapshot](macro@crate::snapshot) macro.
+use crate::TableMap;
+use core::fmt;
+use serde::{Serialize, Serializer};
+
+/// Error returned when loading a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError<E, D> {
+    /// Snapshot could not be deserialized.
+    Decode(D),
+    /// Row of the snapshot was rejected by the checks of its table.
+    Invalid {
+        /// Name of the table that the row belongs to.
+        table: &'static str,
+        /// Position of the row in the table of the snapshot.
+        row: usize,
+        /// Error returned by the checks.
+        error: E,
+    },
+}
+
+impl<E: fmt::Display, D: fmt::Display> fmt::Display for SnapshotError<E, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Decode(error) => write!(f, "cannot decode snapshot: {error}"),
+            SnapshotError::Invalid { table, row, error } => {
+                write!(f, "row {row} of table {table} is invalid: {error}")
+            }
+        }
+    }
+}
+
+impl<E, D> core::error::Error for SnapshotError<E, D>
+where
+    E: fmt::Debug + fmt::Display,
+    D: fmt::Debug + fmt::Display,
+{
+}
+
+/// Serializes the rows of a table as a sequence.
+#[doc(hidden)]
+pub struct SnapshotRows<'a, M>(pub &'a M);
+
+impl<M> Serialize for SnapshotRows<'_, M>
+where
+    M: TableMap,
+    M::Value: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.values())
+    }
+}
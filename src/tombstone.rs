@@ -0,0 +1,37 @@
+//! Tombstone fields used by tables with soft deletion.
+
+/// Field type that marks a row as deleted, used by tables declared with `soft_delete`.
+///
+/// This is implemented for [bool] (where `true` marks a deleted row) and for [Option] (where
+/// `Some` marks a deleted row, usually holding the time of deletion).
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` cannot be used as a soft delete field",
+    note = "use a `bool` or an `Option`, or implement `macrodb::Tombstone` for it"
+)]
+pub trait Tombstone {
+    /// Determine if this value marks the row as deleted.
+    fn is_deleted(&self) -> bool;
+
+    /// Value that marks a row as not deleted, used when restoring a row.
+    fn restored() -> Self;
+}
+
+impl Tombstone for bool {
+    fn is_deleted(&self) -> bool {
+        *self
+    }
+
+    fn restored() -> Self {
+        false
+    }
+}
+
+impl<T> Tombstone for Option<T> {
+    fn is_deleted(&self) -> bool {
+        self.is_some()
+    }
+
+    fn restored() -> Self {
+        None
+    }
+}
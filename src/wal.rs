@@ -0,0 +1,653 @@
+//! Write-ahead log used by the [wal](macro@crate::wal) macro.
+//!
+//! A [Wal] keeps the data of a database in a directory with two files: `snapshot` contains all
+//! tables as of the last compaction, and `log` contains every change made since then. The log is
+//! append-only, and every record in it is checksummed, so that a record which was only partially
+//! written (for example, because the process crashed) is detected and discarded when the log is
+//! opened again. A damaged record in the middle of the log is reported as an error instead.
+//!
+//! Both files start with a generation number, which is incremented by every compaction. A log
+//! whose generation is older than that of the snapshot was already compacted into it (the process
+//! crashed before the log was reset) and is ignored.
+//!
+//! How often the log is synced to disk is controlled by its [SyncPolicy]. When the database is
+//! shared between threads, a [WalCommitter] can be used to implement group commit: writers
+//! append their changes while holding the lock on the database, and then wait for the log to be
+//! synced after releasing it, so that a single sync covers the changes of many writers.
+use crate::{Change, ChangeSink};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+/// Magic bytes at the start of the log file.
+const LOG_MAGIC: &[u8; 8] = b"MDBLOG01";
+
+/// Magic bytes at the start of the snapshot file.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"MDBSNP01";
+
+/// Length of the file headers (magic bytes and generation).
+const HEADER_LENGTH: usize = 16;
+
+/// Length of the frame header of a record (length and checksum).
+const FRAME_LENGTH: usize = 8;
+
+/// Serializer used to encode the records of a [Wal].
+///
+/// This allows using any serde data format for the log, for example JSON while debugging and a
+/// binary format in production.
+pub trait WalCodec {
+    /// Error returned when encoding or decoding fails.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Encode `value`, appending it to `output`.
+    fn encode<T: Serialize>(&mut self, value: &T, output: &mut Vec<u8>) -> Result<(), Self::Error>;
+
+    /// Decode a value that was encoded with [encode](WalCodec::encode).
+    fn decode<T: DeserializeOwned>(&mut self, input: &[u8]) -> Result<T, Self::Error>;
+}
+
+/// Policy that determines when a [Wal] syncs the log to disk.
+///
+/// A change is only durable once the log has been synced, changes that were written but not
+/// synced may be lost if the system crashes (but not if only the process crashes). Syncing is
+/// expensive, so the policy trades durability for throughput.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync after every write, so that every change is durable once its method returns
+    /// successfully.
+    #[default]
+    Always,
+    /// Sync after every `n` writes.
+    Writes(usize),
+    /// Sync on the first write after the interval has passed since the last sync.
+    ///
+    /// The interval is only checked when a change is written, there is no background thread.
+    /// Changes written before the database goes quiet stay unsynced until the next write, so
+    /// applications should call [Wal::sync] periodically or before they become idle.
+    Interval(Duration),
+    /// Never sync automatically, leaving it to [Wal::sync], a [WalCommitter] or the operating
+    /// system.
+    Never,
+}
+
+/// Statistics about a recovery performed by the `wal_recover` method.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WalRecovery {
+    /// Whether a snapshot was loaded.
+    pub snapshot: bool,
+    /// Number of log records that were replayed.
+    pub records: usize,
+    /// Number of bytes at the end of the log that were discarded, because they did not form a
+    /// complete record with a valid checksum.
+    pub discarded: u64,
+}
+
+/// Error returned when recovering or compacting a database using a [Wal].
+#[derive(Debug)]
+pub enum WalError<E> {
+    /// Reading, writing or decoding the files of the log failed.
+    Io(io::Error),
+    /// Log contains a record for a table that does not exist.
+    UnknownTable(String),
+    /// Row of the snapshot was rejected by the checks of its table.
+    Snapshot {
+        /// Name of the table that the row belongs to.
+        table: &'static str,
+        /// Position of the row in the table of the snapshot.
+        row: usize,
+        /// Error returned by the checks.
+        error: E,
+    },
+    /// Record of the log could not be applied.
+    Log {
+        /// Name of the table that the record belongs to.
+        table: &'static str,
+        /// Position of the record in the log.
+        record: usize,
+        /// Error returned by the checks.
+        error: E,
+    },
+}
+
+impl<E> From<io::Error> for WalError<E> {
+    fn from(error: io::Error) -> Self {
+        WalError::Io(error)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for WalError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalError::Io(error) => write!(f, "cannot access write-ahead log: {error}"),
+            WalError::UnknownTable(table) => write!(f, "log contains unknown table {table}"),
+            WalError::Snapshot { table, row, error } => {
+                write!(
+                    f,
+                    "row {row} of table {table} in snapshot is invalid: {error}"
+                )
+            }
+            WalError::Log {
+                table,
+                record,
+                error,
+            } => write!(
+                f,
+                "record {record} of table {table} in log is invalid: {error}"
+            ),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for WalError<E> {}
+
+/// Data read when opening a [Wal], which is applied to the database when recovering.
+#[doc(hidden)]
+#[derive(Default)]
+pub struct WalPending {
+    pub snapshot: Option<Vec<u8>>,
+    pub records: Vec<(String, Vec<u8>)>,
+    pub recovery: WalRecovery,
+}
+
+/// Write-ahead log of the changes made to a database.
+///
+/// The log is a [ChangeSink] for any row type that implements [Serialize], and is used by
+/// storing it in the database struct (as an `Option<Wal<C>>`) and declaring a `changes` entry
+/// for every table, which maps the [io::Error] of a failed write to the error type of the table.
+/// See the [wal](macro@crate::wal) macro for the methods used to recover and compact the
+/// database.
+///
+/// Changes are written to the log before they are applied, so when writing (or, depending on
+/// the [SyncPolicy], syncing) a record fails, the method that made the change returns an error
+/// and leaves the database unchanged. The error is kept in the log and returned by
+/// [error](Wal::error). Once an error has occurred (including a failed sync), no further
+/// records are written and every later change fails as well, as the log would otherwise be
+/// missing a change. A record whose sync failed is removed from the log if possible, but may
+/// still be recovered after a restart if that fails too.
+pub struct Wal<C> {
+    codec: C,
+    directory: PathBuf,
+    log: File,
+    generation: u64,
+    buffer: Vec<u8>,
+    error: Option<io::Error>,
+    pending: WalPending,
+    policy: SyncPolicy,
+    unsynced: usize,
+    last_sync: Instant,
+    shared: Arc<WalShared>,
+}
+
+/// State of a [Wal] that is shared with its [WalCommitter]s.
+struct WalShared {
+    /// Handle of the log file used for syncing.
+    file: File,
+    /// Number of records written to the log.
+    written: AtomicU64,
+    /// Whether writing to or syncing the log has failed.
+    failed: AtomicBool,
+    state: Mutex<SyncState>,
+    synced: Condvar,
+}
+
+/// Progress of syncing the log.
+#[derive(Default)]
+struct SyncState {
+    /// Number of records that are known to be synced.
+    synced: u64,
+    /// Whether a sync is in progress.
+    syncing: bool,
+    /// Number of syncs performed.
+    syncs: u64,
+    /// Error of the sync that failed, which is reported to every later commit.
+    error: Option<(io::ErrorKind, String)>,
+}
+
+impl WalShared {
+    fn lock(&self) -> MutexGuard<'_, SyncState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn check(&self) -> io::Result<()> {
+        match self.failed.load(Ordering::Acquire) {
+            true => Err(io::Error::other("write-ahead log has failed")),
+            false => Ok(()),
+        }
+    }
+
+    /// Sync the log, marking all records written so far as synced.
+    fn sync(&self, file: &File) -> io::Result<()> {
+        let target = self.written.load(Ordering::Acquire);
+        let result = file.sync_data();
+        let mut state = self.lock();
+        match &result {
+            Ok(()) => {
+                state.synced = state.synced.max(target);
+                state.syncs += 1;
+            }
+            Err(error) => {
+                self.failed.store(true, Ordering::Release);
+                state.error.get_or_insert((error.kind(), error.to_string()));
+            }
+        }
+        self.synced.notify_all();
+        result
+    }
+}
+
+/// Handle used to wait for changes to be synced to disk, implementing group commit.
+///
+/// Every record written to a [Wal] has a sequence number, which is returned by
+/// [Wal::sequence]. Calling [commit](WalCommitter::commit) with a sequence number blocks until
+/// that record has been synced: if no sync is in progress, the calling thread syncs the log,
+/// otherwise it waits for the running sync and checks again. Since a sync covers all records
+/// written before it started, writers that commit at the same time share a single sync.
+///
+/// Once a sync has failed, it is unknown which records reached the disk, so every later commit
+/// returns the error of that sync, even for records that were synced before. This includes the
+/// writers that were waiting for the failed sync.
+///
+/// The log should use [SyncPolicy::Never] in this case, so that writers do not sync while
+/// holding the lock on the database.
+#[derive(Clone)]
+pub struct WalCommitter {
+    shared: Arc<WalShared>,
+}
+
+impl WalCommitter {
+    /// Wait until the record with the sequence number `sequence` (and all records before it)
+    /// have been synced to disk, syncing the log if necessary.
+    pub fn commit(&self, sequence: u64) -> io::Result<()> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some((kind, error)) = &state.error {
+                return Err(io::Error::new(*kind, error.clone()));
+            }
+            self.shared.check()?;
+            if state.synced >= sequence {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            state = self
+                .shared
+                .synced
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        state.syncing = true;
+        drop(state);
+        let result = self.shared.sync(&self.shared.file);
+        self.shared.lock().syncing = false;
+        self.shared.synced.notify_all();
+        result
+    }
+
+    /// Number of syncs performed on the log.
+    pub fn syncs(&self) -> u64 {
+        self.shared.lock().syncs
+    }
+}
+
+impl<C: WalCodec> Wal<C> {
+    /// Open the log stored in `directory`, creating it if it does not exist.
+    ///
+    /// An incomplete or corrupt record at the end of the log is discarded and the log is
+    /// truncated, so that new records are appended after the last valid one. A corrupt record
+    /// that is followed by valid records is reported as an [io::ErrorKind::InvalidData] error
+    /// instead. The snapshot and
+    /// the remaining records are kept in memory until they are applied to a database with
+    /// `wal_recover`.
+    pub fn open(directory: impl AsRef<Path>, codec: C) -> io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        let (generation, snapshot) = match fs::read(directory.join("snapshot")) {
+            Ok(data) => {
+                let (generation, data) = parse_header(&data, SNAPSHOT_MAGIC)
+                    .ok_or_else(|| invalid_data("snapshot has invalid header"))?;
+                match unframe(data) {
+                    Some((payload, length)) if length == data.len() => {
+                        (generation, Some(payload.to_vec()))
+                    }
+                    _ => return Err(invalid_data("snapshot is corrupt")),
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (0, None),
+            Err(error) => return Err(error),
+        };
+
+        let log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(directory.join("log"))?;
+        let shared = Arc::new(WalShared {
+            file: log.try_clone()?,
+            written: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+            state: Mutex::new(SyncState::default()),
+            synced: Condvar::new(),
+        });
+        let mut wal = Wal {
+            codec,
+            directory,
+            log,
+            generation,
+            buffer: Vec::new(),
+            error: None,
+            pending: WalPending {
+                recovery: WalRecovery {
+                    snapshot: snapshot.is_some(),
+                    ..Default::default()
+                },
+                snapshot,
+                records: Vec::new(),
+            },
+            policy: SyncPolicy::default(),
+            unsynced: 0,
+            last_sync: Instant::now(),
+            shared,
+        };
+        wal.read_log()?;
+        Ok(wal)
+    }
+
+    /// Read the records of the log, discarding a torn tail.
+    ///
+    /// A record that is incomplete or has an invalid checksum is only discarded (along with
+    /// everything after it) if it is the last one. When a valid record follows it, the log was
+    /// corrupted rather than torn, and discarding the rest would silently lose changes.
+    fn read_log(&mut self) -> io::Result<()> {
+        let mut data = Vec::new();
+        self.log.read_to_end(&mut data)?;
+
+        // a log shorter than its header was never written to, or was being reset
+        if data.len() < HEADER_LENGTH {
+            return self.reset_log();
+        }
+        let generation = match parse_header(&data, LOG_MAGIC) {
+            Some((generation, _)) => generation,
+            None => return Err(invalid_data("log has invalid header")),
+        };
+        if generation < self.generation {
+            return self.reset_log();
+        }
+        if generation > self.generation {
+            return Err(invalid_data("log is newer than snapshot"));
+        }
+
+        let mut offset = HEADER_LENGTH;
+        while let Some((payload, length)) = unframe(&data[offset..]) {
+            let (&name_length, payload) = payload
+                .split_first()
+                .ok_or_else(|| invalid_data("log record is empty"))?;
+            let name_length = usize::from(name_length);
+            if payload.len() < name_length {
+                return Err(invalid_data("log record has invalid table name"));
+            }
+            let (name, payload) = payload.split_at(name_length);
+            let name = core::str::from_utf8(name)
+                .map_err(|_| invalid_data("log record has invalid table name"))?;
+            self.pending.records.push((name.into(), payload.to_vec()));
+            offset += length;
+        }
+
+        if corrupted(&data[offset..]) {
+            return Err(invalid_data(
+                "log has a corrupt record followed by valid records",
+            ));
+        }
+        if offset < data.len() {
+            self.pending.recovery.discarded = (data.len() - offset) as u64;
+            self.log.set_len(offset as u64)?;
+            self.log.sync_all()?;
+        }
+        self.pending.recovery.records = self.pending.records.len();
+        self.log.seek(SeekFrom::Start(offset as u64))?;
+        Ok(())
+    }
+
+    /// Truncate the log and write a header with the current generation.
+    fn reset_log(&mut self) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        header.extend_from_slice(LOG_MAGIC);
+        header.extend_from_slice(&self.generation.to_le_bytes());
+        self.log.set_len(0)?;
+        self.log.seek(SeekFrom::Start(0))?;
+        self.log.write_all(&header)?;
+        self.log.sync_all()
+    }
+
+    /// Set the policy that determines when the log is synced, [SyncPolicy::Always] by default.
+    pub fn with_sync(mut self, policy: SyncPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Error that occurred while writing to the log, if any.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Sequence number of the last record written to the log. Records are numbered from one,
+    /// starting when the log is opened.
+    pub fn sequence(&self) -> u64 {
+        self.shared.written.load(Ordering::Acquire)
+    }
+
+    /// Number of syncs performed on the log.
+    pub fn syncs(&self) -> u64 {
+        self.shared.lock().syncs
+    }
+
+    /// Create a handle for waiting until records are synced, see [WalCommitter].
+    pub fn committer(&self) -> WalCommitter {
+        WalCommitter {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Sync all records written so far to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.check()?;
+        self.sync_log()
+    }
+
+    /// Sync the log, resetting the counters of the sync policy.
+    fn sync_log(&mut self) -> io::Result<()> {
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        self.shared.sync(&self.log)
+    }
+
+    /// Generation of the log, which is incremented by every compaction.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Return the error stored in the log, if any.
+    fn check(&self) -> io::Result<()> {
+        match &self.error {
+            Some(error) => Err(io::Error::new(error.kind(), error.to_string())),
+            None => self.shared.check(),
+        }
+    }
+
+    /// Append a record for `table` to the log.
+    fn append<T: Serialize>(&mut self, table: &'static str, value: &T) -> io::Result<()> {
+        let name_length =
+            u8::try_from(table.len()).map_err(|_| invalid_data("table name is too long"))?;
+        let mut payload = Vec::new();
+        payload.push(name_length);
+        payload.extend_from_slice(table.as_bytes());
+        self.codec
+            .encode(value, &mut payload)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        self.buffer.clear();
+        frame(&payload, &mut self.buffer);
+        let position = self.log.stream_position()?;
+        if let Err(error) = self.write() {
+            // the change is not applied, so its record is removed if it was (partially) written
+            let _ = self.log.set_len(position);
+            let _ = self.log.seek(SeekFrom::Start(position));
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Write the record in the buffer, and sync the log if required by the sync policy.
+    fn write(&mut self) -> io::Result<()> {
+        self.log.write_all(&self.buffer)?;
+        self.shared.written.fetch_add(1, Ordering::AcqRel);
+
+        self.unsynced += 1;
+        let due = match self.policy {
+            SyncPolicy::Always => true,
+            SyncPolicy::Writes(writes) => self.unsynced >= writes,
+            SyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+            SyncPolicy::Never => false,
+        };
+        if due {
+            self.sync_log()?;
+        }
+        Ok(())
+    }
+
+    /// Take the snapshot and records read when opening the log.
+    #[doc(hidden)]
+    pub fn take_pending(&mut self) -> WalPending {
+        core::mem::take(&mut self.pending)
+    }
+
+    /// Decode a value using the codec of this log.
+    #[doc(hidden)]
+    pub fn decode<T: DeserializeOwned>(&mut self, data: &[u8]) -> io::Result<T> {
+        self.codec
+            .decode(data)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// Write `tables` as the new snapshot and reset the log.
+    ///
+    /// The snapshot is written to a temporary file which is then renamed, so that a crash during
+    /// compaction leaves either the old or the new snapshot in place.
+    #[doc(hidden)]
+    pub fn compact<T: Serialize>(&mut self, tables: &T) -> io::Result<()> {
+        self.check()?;
+        let generation = self.generation + 1;
+        let mut payload = Vec::new();
+        self.codec
+            .encode(tables, &mut payload)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        let mut data = Vec::with_capacity(HEADER_LENGTH + FRAME_LENGTH + payload.len());
+        data.extend_from_slice(SNAPSHOT_MAGIC);
+        data.extend_from_slice(&generation.to_le_bytes());
+        frame(&payload, &mut data);
+
+        let temporary = self.directory.join("snapshot.tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&temporary, self.directory.join("snapshot"))?;
+        // persisting the rename is best-effort, not all platforms can sync directories
+        if let Ok(directory) = File::open(&self.directory) {
+            let _ = directory.sync_all();
+        }
+
+        self.generation = generation;
+        if let Err(error) = self.reset_log() {
+            self.shared.failed.store(true, Ordering::Release);
+            self.error = Some(io::Error::new(error.kind(), error.to_string()));
+            return Err(error);
+        }
+        // all records written so far are part of the snapshot, which has been synced
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        let mut state = self.shared.lock();
+        state.synced = self.shared.written.load(Ordering::Acquire);
+        Ok(())
+    }
+}
+
+impl<C: WalCodec, T: Serialize> ChangeSink<T> for Wal<C> {
+    type Error = io::Error;
+
+    fn emit(&mut self, table: &'static str, change: Change<&T>) -> io::Result<()> {
+        self.check()?;
+        if let Err(error) = self.append(table, &change) {
+            self.shared.failed.store(true, Ordering::Release);
+            self.error = Some(io::Error::new(error.kind(), error.to_string()));
+            return Err(error);
+        }
+        Ok(())
+    }
+}
+
+/// Create an error for data that is not in the expected format.
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parse a file header, returning the generation and the remaining data.
+fn parse_header<'a>(data: &'a [u8], magic: &[u8; 8]) -> Option<(u64, &'a [u8])> {
+    if data.len() < HEADER_LENGTH || &data[..8] != magic {
+        return None;
+    }
+    let generation = u64::from_le_bytes(data[8..HEADER_LENGTH].try_into().ok()?);
+    Some((generation, &data[HEADER_LENGTH..]))
+}
+
+/// Append `payload` to `output`, prefixed with its length and checksum.
+fn frame(payload: &[u8], output: &mut Vec<u8>) {
+    let length = u32::try_from(payload.len()).expect("record too large");
+    output.extend_from_slice(&length.to_le_bytes());
+    output.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    output.extend_from_slice(payload);
+}
+
+/// Check whether the invalid frame at the start of `data` is followed by a valid one, which
+/// means that it was not torn by a crash while appending it.
+fn corrupted(data: &[u8]) -> bool {
+    let Some(length) = data.get(..4) else {
+        return false;
+    };
+    let length = u32::from_le_bytes(length.try_into().unwrap_or_default()) as usize;
+    match FRAME_LENGTH
+        .checked_add(length)
+        .and_then(|end| data.get(end..))
+    {
+        Some(rest) => unframe(rest).is_some(),
+        None => false,
+    }
+}
+
+/// Parse the frame at the start of `data`, returning its payload and total length, or `None` if
+/// it is incomplete or its checksum does not match.
+fn unframe(data: &[u8]) -> Option<(&[u8], usize)> {
+    if data.len() < FRAME_LENGTH {
+        return None;
+    }
+    let length = u32::from_le_bytes(data[..4].try_into().ok()?) as usize;
+    let checksum = u32::from_le_bytes(data[4..FRAME_LENGTH].try_into().ok()?);
+    let payload = data.get(FRAME_LENGTH..FRAME_LENGTH.checked_add(length)?)?;
+    if crc32fast::hash(payload) != checksum {
+        return None;
+    }
+    Some((payload, FRAME_LENGTH + length))
+}
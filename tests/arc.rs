@@ -0,0 +1,101 @@
+use macrodb::table;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+type UserId = u64;
+
+/// User record, deliberately not implementing Clone.
+#[derive(Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    name: String,
+    email: String,
+    age: u32,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Error {
+    UserNotFound,
+    UserIdExists,
+    UserEmailExists,
+}
+
+#[derive(Clone, Default)]
+struct Database {
+    users: BTreeMap<UserId, Arc<User>>,
+    user_by_email: BTreeMap<String, UserId>,
+    users_by_age: BTreeMap<u32, BTreeSet<UserId>>,
+}
+
+impl Database {
+    table!(
+        users: Arc<User>,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        unique user_by_email email => Error::UserEmailExists,
+        index users_by_age age => ()
+    );
+}
+
+fn user(id: UserId, email: &str, age: u32) -> Arc<User> {
+    Arc::new(User {
+        id,
+        name: format!("user-{id}"),
+        email: email.into(),
+        age,
+    })
+}
+
+#[test]
+fn can_insert_and_get() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "a@example.com", 20)).unwrap();
+    database.users_insert(user(1, "b@example.com", 30)).unwrap();
+
+    let row = database.users_get(&0).cloned().unwrap();
+    assert_eq!(row, user(0, "a@example.com", 20));
+    assert_eq!(database.user_by_email.get("b@example.com"), Some(&1));
+    assert_eq!(
+        database.users_insert(user(2, "a@example.com", 20)),
+        Err(Error::UserEmailExists)
+    );
+}
+
+#[test]
+fn update_keeps_readers_intact() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "a@example.com", 20)).unwrap();
+    let reader = database.users_get(&0).cloned().unwrap();
+
+    let old = database
+        .users_update(user(0, "new@example.com", 21))
+        .unwrap();
+    assert!(Arc::ptr_eq(&old, &reader));
+    assert_eq!(reader.email, "a@example.com");
+    assert_eq!(database.users_get(&0).unwrap().email, "new@example.com");
+    assert_eq!(database.user_by_email.get("a@example.com"), None);
+    assert_eq!(database.user_by_email.get("new@example.com"), Some(&0));
+    assert_eq!(database.users_by_age.get(&20), None);
+    assert_eq!(
+        database.users_by_age.get(&21),
+        Some(&[0].into_iter().collect())
+    );
+}
+
+#[test]
+fn snapshot_shares_rows() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "a@example.com", 20)).unwrap();
+    let snapshot = database.clone();
+    assert!(Arc::ptr_eq(
+        database.users_get(&0).unwrap(),
+        snapshot.users_get(&0).unwrap()
+    ));
+
+    let deleted = database.users_delete(0).unwrap();
+    assert!(database.users.is_empty());
+    assert!(database.users_by_age.is_empty());
+    assert!(Arc::ptr_eq(&deleted, snapshot.users_get(&0).unwrap()));
+}
@@ -0,0 +1,142 @@
+use macrodb::{archived_table, table, MappedArchive};
+use rkyv::{Archive, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Archive, Serialize)]
+#[archive(check_bytes)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Archive, Serialize)]
+#[archive(check_bytes)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    GroupIdExists,
+    GroupNotFound,
+}
+
+#[derive(Default, Archive, Serialize)]
+#[archive(check_bytes)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: HashMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: HashMap<GroupId, Group>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        soft_delete deleted => true,
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists
+    );
+}
+
+impl ArchivedDatabase {
+    archived_table!(users: User, id: UserId, soft_delete deleted);
+    archived_table!(groups: Group, id: GroupId);
+}
+
+fn database() -> Database {
+    let mut database = Database::default();
+    for (id, name) in [(0, "admins"), (1, "users")] {
+        database
+            .groups_insert(Group {
+                id,
+                name: name.into(),
+            })
+            .unwrap();
+    }
+    for (id, email, group) in [(0, "alice", 0), (1, "bob", 1), (2, "carol", 1)] {
+        database
+            .users_insert(User {
+                id,
+                email: email.into(),
+                group,
+                deleted: false,
+            })
+            .unwrap();
+    }
+    database.users_delete(2).unwrap();
+    database
+}
+
+#[test]
+fn mapped_archive_supports_lookups() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("database");
+    let database = database();
+    MappedArchive::save(&database, &path).unwrap();
+
+    // SAFETY: the archive is not modified while it is mapped
+    let archived = unsafe { MappedArchive::<Database>::open(&path) }.unwrap();
+    for id in 0..2 {
+        let (row, user) = (
+            archived.users_get(&id).unwrap(),
+            database.users_get(&id).unwrap(),
+        );
+        assert_eq!((row.id, row.email.as_str()), (user.id, user.email.as_str()));
+    }
+    assert_eq!(archived.groups_get(&1).unwrap().name, "users");
+    assert!(archived.groups_get(&2).is_none());
+
+    // deleted rows are archived, but not returned
+    assert!(archived.users_get(&2).is_none());
+    assert!(archived.users.get(&2).unwrap().deleted);
+
+    // indices are archived and don't need to be rebuilt
+    assert_eq!(archived.user_by_email.get("bob"), Some(&1));
+    assert_eq!(archived.user_by_email.get("carol"), None);
+    let members: Vec<UserId> = archived
+        .users_by_group
+        .get(&1)
+        .unwrap()
+        .iter()
+        .copied()
+        .collect();
+    assert_eq!(members, [1]);
+}
+
+#[test]
+fn mapped_archive_rejects_invalid_files() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("database");
+    // SAFETY: the archive is not modified while it is mapped
+    assert!(unsafe { MappedArchive::<Database>::open(&path) }.is_err());
+
+    MappedArchive::save(&database(), &path).unwrap();
+    let data = fs::read(&path).unwrap();
+    fs::write(&path, &data[..data.len() - 4]).unwrap();
+    // SAFETY: the archive is not modified while it is mapped
+    let error = unsafe { MappedArchive::<Database>::open(&path) }
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,206 @@
+use macrodb::{table, Change, ChangeSink};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    name: String,
+    group: GroupId,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserNameExists,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+    LogFailed,
+}
+
+/// Change to any of the tables of the database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Event {
+    User(Change<User>),
+    Group(Change<Group>),
+}
+
+impl ChangeSink<User> for Vec<Event> {
+    type Error = Infallible;
+
+    fn emit(&mut self, table: &'static str, change: Change<&User>) -> Result<(), Infallible> {
+        assert_eq!(table, "users");
+        self.push(Event::User(change.cloned()));
+        Ok(())
+    }
+}
+
+impl ChangeSink<Group> for Vec<Event> {
+    type Error = Infallible;
+
+    fn emit(&mut self, table: &'static str, change: Change<&Group>) -> Result<(), Infallible> {
+        assert_eq!(table, "groups");
+        self.push(Event::Group(change.cloned()));
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_name: BTreeMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+    events: Vec<Event>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        unique user_by_name name => Error::UserNameExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => (),
+        changes events _ => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        reverse users_by_group id => Error::GroupNotEmpty,
+        changes events _ => ()
+    );
+}
+
+fn group() -> Group {
+    Group {
+        id: 0,
+        name: "admins".into(),
+    }
+}
+
+fn user(id: UserId, name: &str) -> User {
+    User {
+        id,
+        name: name.into(),
+        group: 0,
+    }
+}
+
+#[test]
+fn emits_changes() {
+    let mut database = Database::default();
+    database.groups_insert(group()).unwrap();
+    database.users_insert(user(0, "alice")).unwrap();
+    database.users_update(user(0, "bob")).unwrap();
+    database.users_delete(0).unwrap();
+    database.groups_delete(0).unwrap();
+
+    assert_eq!(
+        database.events,
+        [
+            Event::Group(Change::Inserted(group())),
+            Event::User(Change::Inserted(user(0, "alice"))),
+            Event::User(Change::Updated {
+                old: user(0, "alice"),
+                new: user(0, "bob"),
+            }),
+            Event::User(Change::Deleted(user(0, "bob"))),
+            Event::Group(Change::Deleted(group())),
+        ]
+    );
+}
+
+#[test]
+fn failed_operations_emit_nothing() {
+    let mut database = Database::default();
+    assert_eq!(
+        database.users_insert(user(0, "alice")),
+        Err(Error::GroupNotFound)
+    );
+    database.groups_insert(group()).unwrap();
+    database.users_insert(user(0, "alice")).unwrap();
+    database.users_insert(user(1, "bob")).unwrap();
+    database.events.clear();
+
+    assert_eq!(
+        database.users_insert(user(2, "alice")),
+        Err(Error::UserNameExists)
+    );
+    assert_eq!(
+        database.users_update(user(1, "alice")),
+        Err(Error::UserNameExists)
+    );
+    assert_eq!(
+        database.users_update(user(2, "carol")),
+        Err(Error::UserNotFound)
+    );
+    assert_eq!(database.groups_delete(0), Err(Error::GroupNotEmpty));
+    assert_eq!(database.users_delete(2), Err(Error::UserNotFound));
+    assert!(database.events.is_empty());
+}
+
+/// Sink that fails while it is offline.
+#[derive(Clone, Debug, Default)]
+struct Log {
+    offline: bool,
+    groups: Vec<Change<Group>>,
+}
+
+impl ChangeSink<Group> for Log {
+    type Error = ();
+
+    fn emit(&mut self, _table: &'static str, change: Change<&Group>) -> Result<(), ()> {
+        if self.offline {
+            return Err(());
+        }
+        self.groups.push(change.cloned());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct LoggedDatabase {
+    groups: BTreeMap<GroupId, Group>,
+    events: Vec<Event>,
+    log: Log,
+}
+
+impl LoggedDatabase {
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        changes events _ => (),
+        changes log _ => Error::LogFailed
+    );
+}
+
+#[test]
+fn failing_sink_is_emitted_to_first() {
+    let mut database = LoggedDatabase::default();
+    database.log.offline = true;
+    assert_eq!(database.groups_insert(group()), Err(Error::LogFailed));
+    assert!(database.groups.is_empty());
+    assert!(database.events.is_empty());
+
+    database.log.offline = false;
+    database.groups_insert(group()).unwrap();
+    assert_eq!(database.events, [Event::Group(Change::Inserted(group()))]);
+    assert_eq!(database.log.groups, [Change::Inserted(group())]);
+}
@@ -13,6 +13,7 @@ struct User {
     country: String,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Error {
     UserIdExists,
@@ -142,11 +143,10 @@ fn can_delete_user() {
     database.users_insert(user.clone()).unwrap();
     database.users_delete(user.id).unwrap();
     assert_eq!(database.users.get(&user.id), None);
-    assert!(database.users_by_age.get(&user.age).is_none());
-    assert!(database
+    assert!(!database.users_by_age.contains_key(&user.age));
+    assert!(!database
         .users_by_location
-        .get(&(user.city.clone(), user.country.clone()))
-        .is_none());
+        .contains_key(&(user.city.clone(), user.country.clone())));
     assert_eq!(
         database
             .user_by_name
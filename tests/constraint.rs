@@ -9,6 +9,7 @@ struct User {
     name: String,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Error {
     UserIdExists,
@@ -75,3 +76,49 @@ fn cannot_update_user_empty_name() {
     let result = database.users_update(user);
     assert_eq!(result, Err(Error::UserNameEmpty));
 }
+
+#[test]
+fn validate_insert_reports_all_errors() {
+    let mut database = Database::default();
+    let user = User {
+        id: database.users_next_id(),
+        name: "John".into(),
+    };
+    database.users_insert(user.clone()).unwrap();
+    assert!(database
+        .users_validate_insert(&User {
+            id: user.id + 1,
+            name: "Jane".into()
+        })
+        .is_empty());
+    assert_eq!(
+        database.users_validate_insert(&user),
+        [Error::UserIdExists, Error::UserNameExists]
+    );
+    let empty = User {
+        id: user.id,
+        name: "".into(),
+    };
+    assert_eq!(
+        database.users_validate_insert(&empty),
+        [Error::UserIdExists, Error::UserNameEmpty]
+    );
+}
+
+#[test]
+fn validate_update_reports_all_errors() {
+    let mut database = Database::default();
+    let mut user = User {
+        id: database.users_next_id(),
+        name: "John".into(),
+    };
+    database.users_insert(user.clone()).unwrap();
+    assert!(database.users_validate_update(&user).is_empty());
+    user.name = "".into();
+    assert_eq!(
+        database.users_validate_update(&user),
+        [Error::UserNameEmpty]
+    );
+    user.id += 1;
+    assert_eq!(database.users_validate_update(&user), [Error::UserNotFound]);
+}
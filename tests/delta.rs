@@ -0,0 +1,221 @@
+use macrodb::{delta, snapshot, table, SnapshotError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: HashMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    users_dirty: BTreeSet<UserId>,
+    groups: HashMap<GroupId, Group>,
+    groups_dirty: HashSet<GroupId>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        soft_delete deleted => true,
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => (),
+        dirty users_dirty _ => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        reverse users_by_group id => Error::GroupNotEmpty,
+        dirty groups_dirty _ => ()
+    );
+    snapshot!(Error, groups: Group, users: User);
+    delta!(Error, groups: Group => groups_dirty: GroupId, users: User => users_dirty: UserId);
+
+    /// Tables and indices of the database, without the dirty sets.
+    #[allow(clippy::type_complexity)]
+    fn state(
+        &self,
+    ) -> (
+        &BTreeMap<UserId, User>,
+        &HashMap<String, UserId>,
+        &BTreeMap<GroupId, BTreeSet<UserId>>,
+        &HashMap<GroupId, Group>,
+    ) {
+        (
+            &self.users,
+            &self.user_by_email,
+            &self.users_by_group,
+            &self.groups,
+        )
+    }
+}
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+        deleted: false,
+    }
+}
+
+fn group(id: GroupId, name: &str) -> Group {
+    Group {
+        id,
+        name: name.into(),
+    }
+}
+
+fn database() -> Database {
+    let mut database = Database::default();
+    database.groups_insert(group(0, "admins")).unwrap();
+    database.groups_insert(group(1, "users")).unwrap();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.users_insert(user(1, "bob", 1)).unwrap();
+    database.users_insert(user(2, "carol", 1)).unwrap();
+    database
+}
+
+fn save(database: &Database) -> serde_json::Value {
+    database.delta_save(serde_json::value::Serializer).unwrap()
+}
+
+#[test]
+fn delta_contains_changed_rows() {
+    let mut database = database();
+    database.delta_mark();
+    assert!(database.users_dirty.is_empty() && database.groups_dirty.is_empty());
+
+    database.users_update(user(1, "robert", 1)).unwrap();
+    database.users_delete(2).unwrap();
+    database.users_insert(user(3, "dave", 0)).unwrap();
+    database.users_delete(3).unwrap();
+    database.users_purge(3).unwrap();
+    // failed changes don't mark rows
+    assert!(database.users_insert(user(4, "alice", 0)).is_err());
+    assert!(database.groups_delete(1).is_err());
+
+    let delta = save(&database);
+    assert_eq!(delta["groups"], serde_json::json!({"keys": [], "rows": []}));
+    assert_eq!(delta["users"]["keys"], serde_json::json!([1, 2, 3]));
+    let rows: Vec<User> = serde_json::from_value(delta["users"]["rows"].clone()).unwrap();
+    assert_eq!(
+        rows,
+        [
+            user(1, "robert", 1),
+            User {
+                deleted: true,
+                ..user(2, "carol", 1)
+            }
+        ]
+    );
+}
+
+#[test]
+fn chain_of_deltas_restores_database() {
+    let mut database = database();
+    let base = database
+        .snapshot_save(serde_json::value::Serializer)
+        .unwrap();
+    database.delta_mark();
+
+    // swap email addresses and move a user to a new group
+    database.groups_insert(group(2, "guests")).unwrap();
+    database.users_update(user(0, "temporary", 0)).unwrap();
+    database.users_update(user(1, "alice", 2)).unwrap();
+    database.users_update(user(0, "bob", 0)).unwrap();
+    database.users_delete(2).unwrap();
+    let first = save(&database);
+    database.delta_mark();
+
+    // empty a group and delete it, restore a deleted user
+    database.users_update(user(0, "bob", 2)).unwrap();
+    database.groups_delete(0).unwrap();
+    database.users_restore(2).unwrap();
+    database.users_insert(user(3, "carol2", 1)).unwrap();
+    let second = save(&database);
+    database.delta_mark();
+
+    let mut restored = Database::snapshot_load(base).unwrap();
+    restored.delta_apply(first).unwrap();
+    restored.delta_apply(second).unwrap();
+    assert_eq!(restored.state(), database.state());
+
+    // applying deltas does not mark rows as changed
+    assert!(restored.users_dirty.is_empty() && restored.groups_dirty.is_empty());
+    assert_eq!(restored.groups_delete(1), Err(Error::GroupNotEmpty));
+}
+
+#[test]
+fn apply_rejects_deltas_of_other_databases() {
+    let mut database = database();
+    database.delta_mark();
+    database.users_update(user(0, "alice", 1)).unwrap();
+    database.groups_delete(0).unwrap();
+    let delta = save(&database);
+
+    // a group that is still referenced by another user cannot be deleted
+    let mut other = self::database();
+    other.users_insert(user(3, "dave", 0)).unwrap();
+    assert!(matches!(
+        other.clone().delta_apply(delta.clone()),
+        Err(SnapshotError::Invalid {
+            table: "groups",
+            row: 0,
+            error: Error::GroupNotEmpty
+        })
+    ));
+
+    // rows are checked like the rows of a snapshot
+    let mut other = self::database();
+    other.users_update(user(2, "carol", 0)).unwrap();
+    other.users_insert(user(4, "dave", 1)).unwrap();
+    let mut conflicting = delta.clone();
+    conflicting["users"]["rows"][0]["email"] = "dave".into();
+    assert!(matches!(
+        other.delta_apply(conflicting),
+        Err(SnapshotError::Invalid {
+            table: "users",
+            row: 0,
+            error: Error::UserEmailExists
+        })
+    ));
+
+    let mut malformed = delta;
+    malformed["users"]["deleted"] = serde_json::json!([]);
+    assert!(matches!(
+        self::database().delta_apply(malformed),
+        Err(SnapshotError::Decode(_))
+    ));
+}
@@ -0,0 +1,270 @@
+use macrodb::{diff, table, Change, TableDiff};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    rev: u64,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    UserStale,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: HashMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+    changes: Vec<Change<Group>>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        version rev => Error::UserStale,
+        soft_delete deleted => true,
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        reverse users_by_group id => Error::GroupNotEmpty,
+        changes changes _ => ()
+    );
+}
+
+diff!(
+    Database,
+    Error,
+    #[derive(Clone, Debug, PartialEq)]
+    struct DatabaseDiff {
+        groups: Group => id,
+        users: User => id,
+    }
+);
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+        rev: 0,
+        deleted: false,
+    }
+}
+
+fn group(id: GroupId, name: &str) -> Group {
+    Group {
+        id,
+        name: name.into(),
+    }
+}
+
+fn database() -> Database {
+    let mut database = Database::default();
+    database.groups_insert(group(0, "admins")).unwrap();
+    database.groups_insert(group(1, "users")).unwrap();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.users_insert(user(1, "bob", 1)).unwrap();
+    database.users_insert(user(2, "carol", 1)).unwrap();
+    database.changes.clear();
+    database
+}
+
+#[test]
+fn diff_reports_changed_rows() {
+    let database = database();
+    assert!(database.diff(&database).is_empty());
+
+    let mut other = database.clone();
+    other.users_update(user(1, "robert", 1)).unwrap();
+    other.users_delete(2).unwrap();
+    other.groups_insert(group(2, "guests")).unwrap();
+
+    let diff = database.diff(&other);
+    assert_eq!(
+        diff.groups,
+        TableDiff {
+            inserted: vec![group(2, "guests")],
+            deleted: vec![],
+            changed: vec![],
+        }
+    );
+    assert_eq!(diff.users.inserted, []);
+    assert_eq!(diff.users.deleted, [user(2, "carol", 1)]);
+    assert_eq!(
+        diff.users.changed,
+        [(
+            user(1, "bob", 1),
+            User {
+                rev: 1,
+                ..user(1, "robert", 1)
+            }
+        )]
+    );
+
+    // the reverse diff undoes the changes
+    let reverse = other.diff(&database);
+    assert_eq!(reverse.users.inserted, diff.users.deleted);
+    assert_eq!(reverse.groups.deleted, diff.groups.inserted);
+}
+
+#[test]
+fn apply_replays_diff() {
+    let mut database = database();
+    let mut other = database.clone();
+    // move all users to a new group and delete the old ones
+    other.groups_insert(group(2, "everyone")).unwrap();
+    other.users_update(user(0, "alice", 2)).unwrap();
+    other.users_update(user(1, "bob", 2)).unwrap();
+    other.users_delete(2).unwrap();
+    other.groups_delete(0).unwrap();
+    other.groups_delete(1).unwrap();
+
+    let diff = database.diff(&other);
+    database.apply(diff).unwrap();
+    assert!(database.diff(&other).is_empty());
+    assert_eq!(database.users_by_group, other.users_by_group);
+    assert_eq!(database.user_by_email, other.user_by_email);
+
+    // changes are made through the normal methods
+    assert_eq!(database.changes, other.changes);
+    assert_eq!(database.users_get(&0).unwrap().rev, 1);
+}
+
+#[test]
+fn apply_rebases_versions() {
+    let mut database = database();
+    let mut other = database.clone();
+    other.users_update(user(0, "alicia", 0)).unwrap();
+    let mut row = other.users_get(&0).unwrap().clone();
+    row.email = "ally".into();
+    other.users_update(row).unwrap();
+    assert_eq!(other.users_get(&0).unwrap().rev, 2);
+
+    database.apply(database.diff(&other)).unwrap();
+    let row = database.users_get(&0).unwrap();
+    assert_eq!((row.email.as_str(), row.rev), ("ally", 1));
+}
+
+#[test]
+fn apply_checks_changes() {
+    let mut database = database();
+    let mut other = database.clone();
+    other.users_insert(user(3, "dave", 0)).unwrap();
+    let diff = database.diff(&other);
+
+    database.users_insert(user(4, "dave", 1)).unwrap();
+    assert_eq!(database.apply(diff.clone()), Err(Error::UserEmailExists));
+
+    let mut database = self::database();
+    database.users_delete(0).unwrap();
+    database.groups_delete(0).unwrap();
+    assert_eq!(database.apply(diff), Err(Error::GroupNotFound));
+}
+
+#[test]
+fn apply_moves_unique_values() {
+    let mut database = database();
+    let mut other = database.clone();
+    // carol's email is taken by a new user, and bob's by an existing one
+    other.users_delete(2).unwrap();
+    other.users_insert(user(3, "carol", 0)).unwrap();
+    other.users_update(user(1, "robert", 1)).unwrap();
+    other.users_update(user(0, "bob", 0)).unwrap();
+
+    database.apply(database.diff(&other)).unwrap();
+    assert!(database.diff(&other).is_empty());
+    assert_eq!(database.user_by_email, other.user_by_email);
+}
+
+#[test]
+fn apply_swaps_unique_values() {
+    let mut database = database();
+    let mut other = database.clone();
+    // bob and carol swap their emails, with carol replaced by a new user
+    other.users_delete(1).unwrap();
+    other.users_update(user(2, "bob", 1)).unwrap();
+    other.users_insert(user(3, "carol", 1)).unwrap();
+
+    database.apply(database.diff(&other)).unwrap();
+    assert!(database.diff(&other).is_empty());
+    assert_eq!(database.user_by_email, other.user_by_email);
+    assert_eq!(database.users_by_group, other.users_by_group);
+}
+
+#[test]
+fn apply_rolls_back_failed_diffs() {
+    let mut database = database();
+    let original = database.clone();
+    let mut other = database.clone();
+    other.groups_insert(group(2, "guests")).unwrap();
+    other.users_delete(0).unwrap();
+    other.users_insert(user(3, "dave", 2)).unwrap();
+    other.users_update(user(1, "robert", 1)).unwrap();
+    let diff = database.diff(&other);
+
+    // the new user conflicts with a user that is not part of the diff
+    database.users_insert(user(4, "dave", 1)).unwrap();
+    let before = database.clone();
+    database.changes.clear();
+    assert_eq!(database.apply(diff), Err(Error::UserEmailExists));
+    assert_eq!(database.users, before.users);
+    assert_eq!(database.groups, before.groups);
+    assert_eq!(database.user_by_email, before.user_by_email);
+    assert_eq!(database.users_by_group, before.users_by_group);
+    // the group was inserted and removed again
+    assert_eq!(
+        database.changes,
+        [
+            Change::Inserted(group(2, "guests")),
+            Change::Deleted(group(2, "guests"))
+        ]
+    );
+
+    // rows that swap a unique value conflict with each other
+    let mut other = original.clone();
+    other.users_update(user(1, "robert", 1)).unwrap();
+    other.users_update(user(2, "bob", 1)).unwrap();
+    other
+        .users_update(User {
+            rev: 1,
+            ..user(1, "carol", 1)
+        })
+        .unwrap();
+    let mut database = original.clone();
+    assert_eq!(
+        database.apply(database.diff(&other)),
+        Err(Error::UserEmailExists)
+    );
+    assert_eq!(database.users, original.users);
+    assert_eq!(database.user_by_email, original.user_by_email);
+}
@@ -0,0 +1,187 @@
+use macrodb::{table, wal, SyncPolicy, Wal, WalCodec};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::Duration;
+
+type UserId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct User {
+    id: UserId,
+    email: String,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    LogFailed,
+}
+
+/// Codec that stores records as JSON.
+struct Json;
+
+impl WalCodec for Json {
+    type Error = serde_json::Error;
+
+    fn encode<T: Serialize>(&mut self, value: &T, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        serde_json::to_writer(output, value)
+    }
+
+    fn decode<T: DeserializeOwned>(&mut self, input: &[u8]) -> Result<T, Self::Error> {
+        serde_json::from_slice(input)
+    }
+}
+
+#[derive(Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: BTreeMap<String, UserId>,
+    wal: Option<Wal<Json>>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        unique user_by_email email => Error::UserEmailExists,
+        changes wal _ => Error::LogFailed
+    );
+    wal!(wal, Error, users: User);
+
+    /// Open the database stored in `path`.
+    fn open(path: &Path, policy: SyncPolicy) -> Self {
+        let mut database = Database {
+            wal: Some(Wal::open(path, Json).unwrap().with_sync(policy)),
+            ..Default::default()
+        };
+        database.wal_recover().unwrap();
+        database
+    }
+
+    fn wal(&self) -> &Wal<Json> {
+        self.wal.as_ref().unwrap()
+    }
+}
+
+fn user(id: UserId) -> User {
+    User {
+        id,
+        email: format!("user{id}@example.com"),
+    }
+}
+
+#[test]
+fn sync_policies() {
+    let cases = [
+        (SyncPolicy::Always, 7),
+        (SyncPolicy::Writes(3), 2),
+        (SyncPolicy::Interval(Duration::ZERO), 7),
+        (SyncPolicy::Interval(Duration::from_secs(3600)), 0),
+        (SyncPolicy::Never, 0),
+    ];
+    for (policy, syncs) in cases {
+        let directory = tempfile::tempdir().unwrap();
+        let mut database = Database::open(directory.path(), policy);
+        for id in 0..7 {
+            database.users_insert(user(id)).unwrap();
+        }
+        assert_eq!(database.wal().sequence(), 7);
+        assert_eq!(database.wal().syncs(), syncs, "{policy:?}");
+
+        database.wal.as_mut().unwrap().sync().unwrap();
+        assert_eq!(database.wal().syncs(), syncs + 1, "{policy:?}");
+    }
+}
+
+#[test]
+fn group_commit() {
+    let directory = tempfile::tempdir().unwrap();
+    let database = Database::open(directory.path(), SyncPolicy::Never);
+    let committer = database.wal().committer();
+    let database = Arc::new(Mutex::new(database));
+    let barrier = Arc::new(Barrier::new(8));
+
+    let threads: Vec<_> = (0..8)
+        .map(|thread| {
+            let database = database.clone();
+            let committer = committer.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                for id in 0..25 {
+                    let sequence = {
+                        let mut database = database.lock().unwrap();
+                        database.users_insert(user(thread * 100 + id)).unwrap();
+                        database.wal().sequence()
+                    };
+                    // every thread has written its record before any of them commits, so that
+                    // the first sync of the round covers all of them
+                    barrier.wait();
+                    committer.commit(sequence).unwrap();
+                    barrier.wait();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // one sync per round instead of one per commit
+    let syncs = committer.syncs();
+    assert_eq!(syncs, 25);
+
+    // committing records that are already synced does not sync again
+    committer.commit(200).unwrap();
+    assert_eq!(committer.syncs(), syncs);
+
+    drop(database);
+    let database = Database::open(directory.path(), SyncPolicy::Never);
+    assert_eq!(database.users.len(), 200);
+    assert_eq!(database.user_by_email.len(), 200);
+}
+
+#[test]
+fn recovers_from_crash_at_any_point() {
+    let directory = tempfile::tempdir().unwrap();
+    let mut database = Database::open(directory.path(), SyncPolicy::Writes(4));
+    let mut lengths = vec![fs::metadata(directory.path().join("log")).unwrap().len()];
+    for id in 0..10 {
+        database.users_insert(user(id)).unwrap();
+        lengths.push(fs::metadata(directory.path().join("log")).unwrap().len());
+    }
+    drop(database);
+    let log = fs::read(directory.path().join("log")).unwrap();
+
+    for length in 0..=log.len() as u64 {
+        let copy = tempfile::tempdir().unwrap();
+        fs::write(copy.path().join("log"), &log).unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(copy.path().join("log"))
+            .unwrap()
+            .set_len(length)
+            .unwrap();
+
+        // all changes whose records were completely written are recovered
+        let complete = lengths.iter().filter(|end| **end <= length).count();
+        let rows = complete.saturating_sub(1);
+        let mut database = Database::open(copy.path(), SyncPolicy::Writes(4));
+        assert_eq!(database.users.len(), rows, "length {length}");
+        assert_eq!(database.user_by_email.len(), rows, "length {length}");
+
+        // and the log can be written to again
+        database.users_insert(user(100)).unwrap();
+        drop(database);
+        let database = Database::open(copy.path(), SyncPolicy::Writes(4));
+        assert_eq!(database.users.len(), rows + 1, "length {length}");
+    }
+}
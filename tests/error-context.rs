@@ -0,0 +1,139 @@
+use macrodb::table;
+use std::collections::{BTreeMap, BTreeSet};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Group {
+    id: GroupId,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Error {
+    UserExists(UserId),
+    UserNotFound(UserId),
+    EmailTaken { email: String, by: UserId },
+    GroupMissing { user: UserId, group: GroupId },
+    GroupExists,
+    GroupNotFound(GroupId),
+    GroupNotEmpty(GroupId),
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: BTreeMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => |id| Error::UserNotFound(*id),
+        primary users id => |_, id| Error::UserExists(*id),
+        unique user_by_email email => |row: &User, by: &UserId| Error::EmailTaken {
+            email: row.email.clone(),
+            by: *by,
+        },
+        foreign groups group => |row, group| Error::GroupMissing {
+            user: row.id,
+            group: *group,
+        },
+        index users_by_group group => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => |id| Error::GroupNotFound(*id),
+        primary groups id => Error::GroupExists,
+        reverse users_by_group id => |_, id| Error::GroupNotEmpty(*id)
+    );
+}
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+    }
+}
+
+fn database() -> Database {
+    let mut database = Database::default();
+    database.groups_insert(Group { id: 1 }).unwrap();
+    database
+        .users_insert(user(0, "alice@example.com", 1))
+        .unwrap();
+    database
+        .users_insert(user(1, "bob@example.com", 1))
+        .unwrap();
+    database
+}
+
+#[test]
+fn insert_errors_see_row_and_key() {
+    let mut database = database();
+    assert_eq!(
+        database.users_insert(user(0, "carol@example.com", 1)),
+        Err(Error::UserExists(0))
+    );
+    assert_eq!(
+        database.users_insert(user(2, "bob@example.com", 1)),
+        Err(Error::EmailTaken {
+            email: "bob@example.com".into(),
+            by: 1
+        })
+    );
+    assert_eq!(
+        database.users_insert(user(2, "carol@example.com", 7)),
+        Err(Error::GroupMissing { user: 2, group: 7 })
+    );
+    assert_eq!(
+        database.groups_insert(Group { id: 1 }),
+        Err(Error::GroupExists)
+    );
+}
+
+#[test]
+fn update_errors_see_row_and_key() {
+    let mut database = database();
+    assert_eq!(
+        database.users_update(user(0, "bob@example.com", 1)),
+        Err(Error::EmailTaken {
+            email: "bob@example.com".into(),
+            by: 1
+        })
+    );
+    assert_eq!(
+        database.users_update(user(5, "carol@example.com", 1)),
+        Err(Error::UserNotFound(5))
+    );
+    assert_eq!(
+        database.users_validate_update(&user(0, "bob@example.com", 3)),
+        [
+            Error::EmailTaken {
+                email: "bob@example.com".into(),
+                by: 1
+            },
+            Error::GroupMissing { user: 0, group: 3 }
+        ]
+    );
+}
+
+#[test]
+fn delete_errors_see_key() {
+    let mut database = database();
+    assert_eq!(database.users_delete(5), Err(Error::UserNotFound(5)));
+    assert_eq!(database.groups_delete(3), Err(Error::GroupNotFound(3)));
+    assert_eq!(database.groups_delete(1), Err(Error::GroupNotEmpty(1)));
+}
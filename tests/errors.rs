@@ -0,0 +1,272 @@
+use macrodb::{errors, table, ErrorKind};
+use std::collections::{BTreeMap, BTreeSet};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    name: String,
+    group: GroupId,
+    created: u64,
+    rev: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+errors!(
+    /// Errors of the database.
+    pub enum Error {
+        users {
+            missing,
+            primary,
+            unique user_by_name,
+            foreign groups,
+            immutable created,
+            version rev,
+            constraint name_empty,
+            transition group_changed,
+        },
+        groups {
+            missing,
+            primary,
+            unique group_by_name,
+            reverse users_by_group,
+        },
+    }
+);
+
+/// Application error that database errors are mapped into.
+#[derive(Debug, PartialEq, Eq)]
+enum AppError {
+    NotFound(&'static str),
+    Conflict(Error),
+}
+
+impl From<Error> for AppError {
+    fn from(error: Error) -> Self {
+        match error.kind() {
+            ErrorKind::NotFound => AppError::NotFound(error.table()),
+            _ => AppError::Conflict(error),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_name: BTreeMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+    group_by_name: BTreeMap<String, GroupId>,
+}
+
+impl Database {
+    fn check_user(&self, user: &User) -> Result<(), Error> {
+        if user.name.is_empty() {
+            return Err(Error::UsersNameEmpty);
+        }
+        Ok(())
+    }
+
+    fn check_group_change(&self, old: &User, new: &User) -> Result<(), Error> {
+        if old.group != new.group && old.rev == 0 {
+            return Err(Error::UsersGroupChanged);
+        }
+        Ok(())
+    }
+
+    // the errors of every entry are the variants generated for it
+    table!(
+        users: User,
+        id: UserId,
+        errors Error,
+        primary users id,
+        unique user_by_name name,
+        foreign groups group,
+        immutable created,
+        version rev,
+        constraint check_user _,
+        transition check_group_change _,
+        index users_by_group group
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupsNotFound,
+        primary groups id => Error::GroupsExists,
+        unique group_by_name name => Error::GroupByNameExists,
+        reverse users_by_group id => Error::UsersByGroupNotEmpty
+    );
+}
+
+#[test]
+fn errors_have_kind_table_and_message() {
+    let cases = [
+        (
+            Error::UsersNotFound,
+            ErrorKind::NotFound,
+            "users",
+            "users row not found",
+        ),
+        (
+            Error::UsersExists,
+            ErrorKind::AlreadyExists,
+            "users",
+            "users row already exists",
+        ),
+        (
+            Error::UserByNameExists,
+            ErrorKind::AlreadyExists,
+            "users",
+            "users row already exists in user_by_name",
+        ),
+        (
+            Error::UsersGroupsNotFound,
+            ErrorKind::MissingReference,
+            "users",
+            "users row references missing groups row",
+        ),
+        (
+            Error::UsersCreatedImmutable,
+            ErrorKind::Immutable,
+            "users",
+            "users field created cannot be changed",
+        ),
+        (
+            Error::UsersRevStale,
+            ErrorKind::Stale,
+            "users",
+            "users row was changed since rev was read",
+        ),
+        (
+            Error::UsersNameEmpty,
+            ErrorKind::Constraint,
+            "users",
+            "users row violates constraint name_empty",
+        ),
+        (
+            Error::UsersGroupChanged,
+            ErrorKind::Transition,
+            "users",
+            "users row change rejected by transition group_changed",
+        ),
+        (
+            Error::UsersByGroupNotEmpty,
+            ErrorKind::StillReferenced,
+            "groups",
+            "groups row is still referenced by users_by_group",
+        ),
+    ];
+    for (error, kind, table, message) in cases {
+        assert_eq!(error.kind(), kind);
+        assert_eq!(error.table(), table);
+        assert_eq!(error.to_string(), message);
+    }
+}
+
+#[test]
+fn errors_implement_error() {
+    let error: Box<dyn std::error::Error> = Box::new(Error::GroupsNotFound);
+    assert_eq!(error.to_string(), "groups row not found");
+}
+
+#[test]
+fn errors_map_into_application_error() {
+    fn delete_group(database: &mut Database, id: GroupId) -> Result<Group, AppError> {
+        Ok(database.groups_delete(id)?)
+    }
+
+    let mut database = Database::default();
+    assert_eq!(
+        delete_group(&mut database, 0),
+        Err(AppError::NotFound("groups"))
+    );
+    database
+        .groups_insert(Group {
+            id: 0,
+            name: "admins".into(),
+        })
+        .unwrap();
+    database
+        .users_insert(User {
+            id: 0,
+            name: "alice".into(),
+            group: 0,
+            created: 0,
+            rev: 0,
+        })
+        .unwrap();
+    assert_eq!(
+        delete_group(&mut database, 0),
+        Err(AppError::Conflict(Error::UsersByGroupNotEmpty))
+    );
+}
+
+#[test]
+fn tables_use_generated_errors() {
+    let mut database = Database::default();
+    let user = User {
+        id: 0,
+        name: "alice".into(),
+        group: 0,
+        created: 0,
+        rev: 0,
+    };
+    assert_eq!(database.users_delete(0), Err(Error::UsersNotFound));
+    assert_eq!(
+        database.users_insert(user.clone()),
+        Err(Error::UsersGroupsNotFound)
+    );
+    for id in [0, 1] {
+        database
+            .groups_insert(Group {
+                id,
+                name: id.to_string(),
+            })
+            .unwrap();
+    }
+    database.users_insert(user.clone()).unwrap();
+    assert_eq!(database.users_insert(user.clone()), Err(Error::UsersExists));
+    assert_eq!(
+        database.users_insert(User {
+            id: 1,
+            ..user.clone()
+        }),
+        Err(Error::UserByNameExists)
+    );
+    assert_eq!(
+        database.users_insert(User {
+            id: 1,
+            name: String::new(),
+            ..user.clone()
+        }),
+        Err(Error::UsersNameEmpty)
+    );
+    assert_eq!(
+        database.users_update(User {
+            created: 1,
+            ..user.clone()
+        }),
+        Err(Error::UsersCreatedImmutable)
+    );
+    assert_eq!(
+        database.users_update(User {
+            rev: 1,
+            ..user.clone()
+        }),
+        Err(Error::UsersRevStale)
+    );
+    assert_eq!(
+        database.users_update(User {
+            group: 1,
+            ..user.clone()
+        }),
+        Err(Error::UsersGroupChanged)
+    );
+}
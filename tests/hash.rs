@@ -0,0 +1,323 @@
+use macrodb::{snapshot, table, TableDigest, TableHash};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: BTreeMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    users_hash: TableHash,
+    groups: BTreeMap<GroupId, Group>,
+    groups_hash: TableHash,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        soft_delete deleted => true,
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => (),
+        hash users_hash _ => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        reverse users_by_group id => Error::GroupNotEmpty,
+        hash groups_hash _ => ()
+    );
+
+    /// Build a new database with the rows that are not deleted.
+    fn rebuild(&self) -> Database {
+        let mut database = Database::default();
+        for group in self.groups.values() {
+            database.groups_insert(group.clone()).unwrap();
+        }
+        for user in self.users.values().filter(|user| !user.deleted) {
+            database.users_insert(user.clone()).unwrap();
+        }
+        database
+    }
+}
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+        deleted: false,
+    }
+}
+
+fn group(id: GroupId, name: &str) -> Group {
+    Group {
+        id,
+        name: name.into(),
+    }
+}
+
+#[test]
+fn hash_is_maintained() {
+    let mut database = Database::default();
+    database.groups_insert(group(0, "admins")).unwrap();
+    database.groups_insert(group(1, "users")).unwrap();
+    for id in 0..20 {
+        database
+            .users_insert(user(id, &format!("user{id}"), id % 2))
+            .unwrap();
+    }
+    let hash = database.users_hash;
+    assert_eq!(hash.len(), 20);
+
+    // the hash does not depend on the order of the rows
+    let mut reversed = Database::default();
+    reversed.groups_insert(group(1, "users")).unwrap();
+    reversed.groups_insert(group(0, "admins")).unwrap();
+    for id in (0..20).rev() {
+        reversed
+            .users_insert(user(id, &format!("user{id}"), id % 2))
+            .unwrap();
+    }
+    assert_eq!(reversed.users_hash, hash);
+    assert_eq!(reversed.groups_hash, database.groups_hash);
+
+    database.users_update(user(3, "renamed", 0)).unwrap();
+    assert_ne!(database.users_hash.value(), hash.value());
+    database.users_update(user(3, "user3", 1)).unwrap();
+    assert_eq!(database.users_hash, hash);
+
+    database.users_delete(4).unwrap();
+    database.users_delete(5).unwrap();
+    database.users_purge(5).unwrap();
+    database.users_insert(user(20, "user20", 0)).unwrap();
+    assert_eq!(database.users_hash, database.rebuild().users_hash);
+    assert_eq!(database.users_hash.len(), 19);
+
+    database.users_restore(4).unwrap();
+    assert_eq!(database.users_hash, database.rebuild().users_hash);
+
+    // failed changes don't change the hash
+    let hash = database.users_hash;
+    assert!(database.users_insert(user(21, "user3", 0)).is_err());
+    assert!(database.users_update(user(6, "user6", 7)).is_err());
+    assert_eq!(database.users_hash, hash);
+
+    database.groups_insert(group(2, "guests")).unwrap();
+    database.groups_delete(2).unwrap();
+    assert_eq!(database.groups_hash, database.rebuild().groups_hash);
+    assert_eq!(TableHash::default().len(), 0);
+}
+
+fn keys(
+    table: &BTreeMap<UserId, User>,
+    ranges: &[(Option<UserId>, Option<UserId>)],
+) -> Vec<UserId> {
+    table
+        .keys()
+        .copied()
+        .filter(|key| {
+            ranges.iter().any(|(start, end)| {
+                start.is_none_or(|start| *key >= start) && end.is_none_or(|end| *key < end)
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn digest_locates_mismatches() {
+    let table: BTreeMap<UserId, User> = (0..2000)
+        .map(|id| (id * 2, user(id * 2, &format!("user{id}"), 0)))
+        .collect();
+    let digest = TableDigest::new(&table);
+    assert_eq!(digest, TableDigest::new(&table.clone()));
+    assert!(digest.mismatches(&TableDigest::new(&table)).is_empty());
+
+    let mut other = table.clone();
+    other.get_mut(&34).unwrap().email = "changed".into();
+    other.remove(&1000);
+    other.insert(2001, user(2001, "inserted", 0));
+    other.insert(5000, user(5000, "last", 0));
+    let theirs = TableDigest::new(&other);
+    assert_ne!(digest.root(), theirs.root());
+
+    let ranges = digest.mismatches(&theirs);
+    assert_eq!(ranges, theirs.mismatches(&digest));
+    let ours = keys(&table, &ranges);
+    let changed = keys(&other, &ranges);
+    for key in [34, 1000, 998] {
+        assert!(ours.contains(&key), "{key} in {ranges:?}");
+    }
+    for key in [34, 2001, 5000] {
+        assert!(changed.contains(&key), "{key} in {ranges:?}");
+    }
+    // only rows near the changes are compared
+    assert!(ours.len() < 100, "{ranges:?}");
+
+    // rows outside of the ranges are the same
+    let same: Vec<_> = table
+        .iter()
+        .filter(|(key, _)| !ours.contains(key))
+        .collect();
+    assert!(same.iter().all(|(key, row)| other.get(key) == Some(*row)));
+
+    let empty = TableDigest::new(&BTreeMap::<UserId, User>::new());
+    assert_eq!(empty.mismatches(&digest), [(None, None)]);
+    assert!(empty.mismatches(&empty).is_empty());
+}
+
+#[derive(Clone, Debug, Default)]
+struct Replica {
+    users: BTreeMap<UserId, User>,
+    user_by_email: BTreeMap<String, UserId>,
+    users_digest: TableDigest<UserId>,
+}
+
+impl Replica {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        soft_delete deleted => true,
+        unique user_by_email email => Error::UserEmailExists,
+        hash users_digest _ => ()
+    );
+    snapshot!(Error, users: User);
+}
+
+#[test]
+fn digest_is_maintained() {
+    let mut replica = Replica::default();
+    // rows are inserted out of order, so that segments are split and merged
+    for id in (0..300).map(|id| id * 7 % 300) {
+        replica
+            .users_insert(user(id, &format!("user{id}"), 0))
+            .unwrap();
+    }
+    let live = |replica: &Replica| {
+        let rows = replica.users.iter().filter(|(_, row)| !row.deleted);
+        rows.map(|(key, row)| (*key, row.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    assert_eq!(replica.users_digest, TableDigest::new(&replica.users));
+
+    for id in (0..300).step_by(3) {
+        replica.users_delete(id).unwrap();
+    }
+    for id in (0..300).step_by(9) {
+        replica.users_purge(id).unwrap();
+    }
+    for id in (3..300).step_by(9) {
+        replica.users_restore(id).unwrap();
+    }
+    for id in (1..300).step_by(5).filter(|id| id % 3 != 0) {
+        replica
+            .users_update(user(id, &format!("renamed{id}"), 0))
+            .unwrap();
+    }
+    assert_eq!(replica.users_digest, TableDigest::new(&live(&replica)));
+    assert_eq!(
+        replica.users_digest.hash().len(),
+        live(&replica).len() as u64
+    );
+
+    // loading a snapshot builds the same digest
+    let snapshot = replica
+        .snapshot_save(serde_json::value::Serializer)
+        .unwrap();
+    let loaded = Replica::snapshot_load(snapshot).unwrap();
+    assert_eq!(loaded.users_digest, replica.users_digest);
+}
+
+#[test]
+fn digest_queries_levels_and_ranges() {
+    let table: BTreeMap<UserId, User> = (0..5000)
+        .map(|id| (id, user(id, &format!("user{id}"), 0)))
+        .collect();
+    let digest = TableDigest::new(&table);
+
+    // every level covers the whole table, with larger segments on higher levels
+    let mut previous = usize::MAX;
+    for level in 0..=digest.levels() {
+        let segments = digest.level(level);
+        let rows: u64 = segments.iter().map(|segment| segment.hash.len()).sum();
+        assert_eq!(rows, 5000);
+        assert!(segments.len() <= previous);
+        previous = segments.len();
+        for pair in segments.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+    assert!(digest.level(0).len() > 100);
+    assert_eq!(digest.level(digest.levels()).len(), 1);
+
+    // the hash of a segment is the hash of its rows
+    for segment in digest.level(1).iter().take(10) {
+        let rows: BTreeMap<_, _> = table
+            .iter()
+            .filter(|(key, _)| {
+                segment.start.is_none_or(|start| **key >= start)
+                    && segment.end.is_none_or(|end| **key < end)
+            })
+            .map(|(key, row)| (*key, row.clone()))
+            .collect();
+        assert_eq!(segment.hash, TableDigest::new(&rows).hash());
+        let start = segment.start.unwrap_or_default();
+        let range = match segment.end {
+            Some(end) => digest.digest(start..end),
+            None => digest.digest(start..),
+        };
+        if segment.start.is_some() {
+            assert_eq!(range, segment.hash);
+        }
+
+        // the segments below a segment are found by its range
+        let below = match (segment.start, segment.end) {
+            (Some(start), Some(end)) => digest.segments(0, start..end),
+            (Some(start), None) => digest.segments(0, start..),
+            (None, Some(end)) => digest.segments(0, ..end),
+            (None, None) => digest.level(0),
+        };
+        let rows: u64 = below.iter().map(|segment| segment.hash.len()).sum();
+        assert_eq!(rows, segment.hash.len());
+    }
+    assert_eq!(digest.digest(..), digest.hash());
+
+    // the digest can be sent to another replica
+    let json = serde_json::to_string(&digest).unwrap();
+    let decoded: TableDigest<UserId> = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, digest);
+}
@@ -0,0 +1,249 @@
+use macrodb::{history, table, History};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    rev: u64,
+    updated: u64,
+    locked: bool,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    UserStale,
+    UserLocked,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: HashMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+    clock: u64,
+    history: History<Edit>,
+}
+
+impl Database {
+    fn stamp_user(&mut self, _old: &User, new: &mut User) -> Result<(), Error> {
+        self.clock += 1;
+        new.updated = self.clock;
+        Ok(())
+    }
+
+    fn check_lock(&self, old: &User, _new: &User) -> Result<(), Error> {
+        if old.locked {
+            return Err(Error::UserLocked);
+        }
+
+        Ok(())
+    }
+
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        version rev => Error::UserStale,
+        soft_delete deleted => true,
+        before_update stamp_user _ => (),
+        transition check_lock _ => (),
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => (),
+        history history _ => ()
+    );
+    // changes to groups are not recorded
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        reverse users_by_group id => Error::GroupNotEmpty
+    );
+}
+
+history!(
+    Database,
+    Error,
+    history,
+    #[derive(Clone, Debug)]
+    enum Edit {
+        users: User,
+    }
+);
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+        rev: 0,
+        updated: 0,
+        locked: false,
+        deleted: false,
+    }
+}
+
+fn database() -> Database {
+    let mut database = Database::default();
+    for (id, name) in [(0, "admins"), (1, "users")] {
+        database
+            .groups_insert(Group {
+                id,
+                name: name.into(),
+            })
+            .unwrap();
+    }
+    database
+}
+
+#[test]
+fn undo_and_redo_actions() {
+    let mut database = database();
+    assert_eq!(database.undo(), Ok(false));
+
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.users_insert(user(1, "bob", 1)).unwrap();
+    database.history.commit();
+    database.users_update(user(0, "alicia", 1)).unwrap();
+    database.users_delete(1).unwrap();
+    database.users_purge(1).unwrap();
+    let before = database.clone();
+
+    // undoing the uncommitted changes brings back bob
+    assert_eq!(database.undo(), Ok(true));
+    // undoing an update is an update as well, so the version is not reset
+    let alice = User {
+        rev: 2,
+        updated: 2,
+        ..user(0, "alice", 0)
+    };
+    assert_eq!(database.users_get(&0), Some(&alice));
+    assert_eq!(database.users_get(&1), Some(&user(1, "bob", 1)));
+    assert_eq!(database.users_by_group[&0], BTreeSet::from([0]));
+
+    assert_eq!(database.undo(), Ok(true));
+    assert!(database.users.is_empty());
+    assert!(database.user_by_email.is_empty());
+    assert!(!database.history.can_undo());
+
+    assert_eq!(database.redo(), Ok(true));
+    assert_eq!(database.redo(), Ok(true));
+    assert_eq!(database.redo(), Ok(false));
+    assert_eq!(database.users_get(&0).unwrap().email, "alicia");
+    assert_eq!(database.users_get(&1), None);
+    assert_eq!(database.users_by_group, before.users_by_group);
+    assert_eq!(database.user_by_email, before.user_by_email);
+
+    // a new change discards the undone actions
+    database.undo().unwrap();
+    database.users_insert(user(2, "carol", 0)).unwrap();
+    assert!(!database.history.can_redo());
+}
+
+#[test]
+fn undo_runs_hooks_and_checks() {
+    let mut database = database();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.history.commit();
+    database.users_update(user(0, "alicia", 0)).unwrap();
+    database.history.commit();
+    assert_eq!(database.users_get(&0).unwrap().updated, 1);
+
+    // the hook stamps the row again, and the version keeps increasing
+    database.undo().unwrap();
+    let row = database.users_get(&0).unwrap();
+    assert_eq!((row.email.as_str(), row.rev, row.updated), ("alice", 2, 2));
+    database.redo().unwrap();
+    let row = database.users_get(&0).unwrap();
+    assert_eq!((row.email.as_str(), row.rev, row.updated), ("alicia", 3, 3));
+
+    // updates that the transition rejects in reverse cannot be undone
+    let mut row = row.clone();
+    row.locked = true;
+    database.users_update(row).unwrap();
+    let before = database.clone();
+    assert_eq!(database.undo(), Err(Error::UserLocked));
+    assert_eq!(database.users, before.users);
+    // the hook ran before the transition was checked
+    assert_eq!(database.clock, before.clock + 1);
+    assert!(database.history.can_undo());
+}
+
+#[test]
+fn undo_soft_delete_and_restore() {
+    let mut database = database();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.history.commit();
+    database.users_delete(0).unwrap();
+    database.history.commit();
+    database.users_restore(0).unwrap();
+    database.history.commit();
+
+    database.undo().unwrap();
+    assert!(database.users_get(&0).is_none());
+    assert!(database.users[&0].deleted);
+    database.undo().unwrap();
+    assert_eq!(database.users_get(&0).unwrap().email, "alice");
+    database.undo().unwrap();
+    assert!(database.users.is_empty());
+
+    // the purged row can be inserted again by redoing
+    database.redo().unwrap();
+    assert_eq!(database.users_get(&0).unwrap().email, "alice");
+    database.redo().unwrap();
+    database.redo().unwrap();
+    assert_eq!(database.users_get(&0).unwrap().email, "alice");
+    assert!(!database.history.can_redo());
+}
+
+#[test]
+fn failed_undo_is_rolled_back() {
+    let mut database = database();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.users_insert(user(1, "bob", 1)).unwrap();
+    database.history.commit();
+    database.users_delete(1).unwrap();
+    database.users_purge(1).unwrap();
+    database.users_delete(0).unwrap();
+    database.users_purge(0).unwrap();
+    database.groups_delete(1).unwrap();
+    let before = database.clone();
+
+    // alice is inserted again before bob, whose group is gone
+    assert_eq!(database.undo(), Err(Error::GroupNotFound));
+    assert_eq!(database.users, before.users);
+    assert_eq!(database.user_by_email, before.user_by_email);
+    assert_eq!(database.users_by_group, before.users_by_group);
+    assert!(database.history.can_undo());
+    assert!(!database.history.can_redo());
+
+    database
+        .groups_insert(Group {
+            id: 1,
+            name: "users".into(),
+        })
+        .unwrap();
+    assert_eq!(database.undo(), Ok(true));
+    assert_eq!(database.users.len(), 2);
+}
@@ -0,0 +1,158 @@
+use macrodb::table;
+use std::collections::{BTreeMap as Map, BTreeSet as Set};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+type DocumentId = u64;
+type TenantId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Document {
+    id: DocumentId,
+    tenant: TenantId,
+    owner: String,
+    created: u64,
+    title: String,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    DocumentIdExists,
+    DocumentNotFound,
+    DocumentTenantImmutable,
+    DocumentOriginImmutable,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    documents: Map<DocumentId, Document>,
+    documents_by_tenant: Map<TenantId, Set<DocumentId>>,
+}
+
+impl Database {
+    table!(
+        documents: Document,
+        id: DocumentId,
+        missing Error => Error::DocumentNotFound,
+        primary documents id => Error::DocumentIdExists,
+        immutable tenant => Error::DocumentTenantImmutable,
+        immutable documents (owner, created) => Error::DocumentOriginImmutable,
+        index documents_by_tenant tenant => ()
+    );
+}
+
+fn document() -> Document {
+    Document {
+        id: 0,
+        tenant: 1,
+        owner: "alice".into(),
+        created: 100,
+        title: "title".into(),
+    }
+}
+
+#[test]
+fn can_update_mutable_fields() {
+    let mut database = Database::default();
+    database.documents_insert(document()).unwrap();
+    let new = Document {
+        title: "other".into(),
+        ..document()
+    };
+    database.documents_update(new.clone()).unwrap();
+    assert_eq!(database.documents_get(&0), Some(&new));
+}
+
+#[test]
+fn cannot_update_immutable_field() {
+    let mut database = Database::default();
+    database.documents_insert(document()).unwrap();
+    let result = database.documents_update(Document {
+        tenant: 2,
+        ..document()
+    });
+    assert_eq!(result, Err(Error::DocumentTenantImmutable));
+    assert_eq!(database.documents_get(&0), Some(&document()));
+    assert_eq!(
+        database.documents_by_tenant.get(&1),
+        Some(&[0].into_iter().collect())
+    );
+    assert_eq!(database.documents_by_tenant.get(&2), None);
+}
+
+#[test]
+fn cannot_update_immutable_compound_field() {
+    let mut database = Database::default();
+    database.documents_insert(document()).unwrap();
+    let result = database.documents_update(Document {
+        created: 200,
+        ..document()
+    });
+    assert_eq!(result, Err(Error::DocumentOriginImmutable));
+    let result = database.documents_update(Document {
+        owner: "bob".into(),
+        ..document()
+    });
+    assert_eq!(result, Err(Error::DocumentOriginImmutable));
+}
+
+static COMPARISONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Key that counts how often it is compared for equality.
+#[derive(Clone, Debug, PartialOrd, Ord, Eq)]
+struct Owner(u64);
+
+impl PartialEq for Owner {
+    fn eq(&self, other: &Self) -> bool {
+        COMPARISONS.fetch_add(1, Ordering::Relaxed);
+        self.0 == other.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Folder {
+    id: u64,
+    owner: Owner,
+    name: String,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Folders {
+    folders: Map<u64, Folder>,
+    folders_by_owner: Map<Owner, Set<u64>>,
+}
+
+impl Folders {
+    table!(
+        folders: Folder,
+        id: u64,
+        missing Error => Error::DocumentNotFound,
+        primary folders id => Error::DocumentIdExists,
+        immutable owner => Error::DocumentOriginImmutable,
+        index folders_by_owner owner => ()
+    );
+}
+
+#[test]
+fn update_skips_indices_of_immutable_fields() {
+    let mut database = Folders::default();
+    let folder = Folder {
+        id: 0,
+        owner: Owner(1),
+        name: "folder".into(),
+    };
+    database.folders_insert(folder.clone()).unwrap();
+    COMPARISONS.store(0, Ordering::Relaxed);
+    database
+        .folders_update(Folder {
+            name: "other".into(),
+            ..folder
+        })
+        .unwrap();
+    // only the immutable check compares the owners
+    assert_eq!(COMPARISONS.load(Ordering::Relaxed), 1);
+    assert_eq!(
+        database.folders_by_owner.get(&Owner(1)),
+        Some(&[0].into_iter().collect())
+    );
+}
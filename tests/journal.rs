@@ -0,0 +1,234 @@
+use macrodb::{journal, table, Journal};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    rev: u64,
+    updated: u64,
+    locked: bool,
+    deleted: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    UserStale,
+    UserLocked,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: HashMap<UserId, User>,
+    user_by_email: HashMap<String, UserId>,
+    users_by_group: HashMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+    clock: u64,
+    journal: Journal<Step>,
+}
+
+impl Database {
+    fn stamp_user(&mut self, _old: &User, new: &mut User) -> Result<(), Error> {
+        self.clock += 1;
+        new.updated = self.clock;
+        Ok(())
+    }
+
+    fn check_lock(&self, old: &User, _new: &User) -> Result<(), Error> {
+        if old.locked {
+            return Err(Error::UserLocked);
+        }
+
+        Ok(())
+    }
+
+    // rolling back restores the rows of the hash maps exactly, without running the checks,
+    // hooks and transitions declared here
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        version rev => Error::UserStale,
+        soft_delete deleted => Some(42),
+        before_update stamp_user _ => (),
+        transition check_lock _ => (),
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => (),
+        journal journal _ => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        reverse users_by_group id => Error::GroupNotEmpty
+    );
+}
+
+journal!(
+    Database,
+    journal,
+    #[derive(Clone, Debug)]
+    enum Step {
+        users: User,
+    }
+);
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+        rev: 0,
+        updated: 0,
+        locked: false,
+        deleted: None,
+    }
+}
+
+fn group(id: GroupId, name: &str) -> Group {
+    Group {
+        id,
+        name: name.into(),
+    }
+}
+
+fn database() -> Database {
+    let mut database = Database::default();
+    database.groups_insert(group(0, "admins")).unwrap();
+    database.groups_insert(group(1, "users")).unwrap();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.users_insert(user(1, "bob", 0)).unwrap();
+    database
+}
+
+fn assert_same(database: &Database, other: &Database) {
+    assert_eq!(database.users, other.users);
+    assert_eq!(database.user_by_email, other.user_by_email);
+    assert_eq!(database.groups, other.groups);
+    let groups = |database: &Database| {
+        database
+            .users_by_group
+            .iter()
+            .filter(|(_, users)| !users.is_empty())
+            .map(|(group, users)| (*group, users.clone()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    assert_eq!(groups(database), groups(other));
+}
+
+#[test]
+fn rollback_keeps_earlier_work() {
+    let mut database = database();
+    assert!(database.journal.is_empty());
+
+    let savepoint = database.savepoint();
+    database.users_update(user(1, "bob", 1)).unwrap();
+    let before = database.clone();
+
+    // a sub-step that fails half way is undone
+    let step = database.savepoint();
+    database.users_delete(0).unwrap();
+    database.users_purge(0).unwrap();
+    database.users_delete(1).unwrap();
+    database.users_restore(1).unwrap();
+    database
+        .users_update(User {
+            rev: 1,
+            ..user(1, "robert", 0)
+        })
+        .unwrap();
+    database.users_insert(user(2, "carol", 1)).unwrap();
+    assert_eq!(database.groups_delete(1), Err(Error::GroupNotEmpty));
+    database.rollback_to(step);
+    assert_same(&database, &before);
+
+    // the savepoint is kept after rolling back
+    database.users_insert(user(2, "carol", 1)).unwrap();
+    database.rollback_to(step);
+    assert_same(&database, &before);
+
+    database.release(step);
+    database.rollback_to(savepoint);
+    assert_same(&database, &self::database());
+
+    database.release(savepoint);
+    assert_eq!(database.journal.depth(), 0);
+    assert!(database.journal.is_empty());
+}
+
+#[test]
+fn release_keeps_changes() {
+    let mut database = database();
+    let outer = database.savepoint();
+    let inner = database.savepoint();
+    database.users_insert(user(2, "carol", 0)).unwrap();
+    database.release(inner);
+    assert_eq!(database.journal.depth(), 1);
+
+    // the released changes are still rolled back with the outer savepoint
+    database.rollback_to(outer);
+    assert_same(&database, &self::database());
+
+    // changes made without a savepoint are not journaled
+    database.release(outer);
+    database.users_insert(user(2, "carol", 0)).unwrap();
+    assert!(database.journal.is_empty());
+}
+
+#[test]
+fn rollback_restores_rows_exactly() {
+    let mut database = database();
+    let original = database.clone();
+    let savepoint = database.savepoint();
+    database.users_update(user(0, "alicia", 1)).unwrap();
+    let mut alice = database.users_get(&0).unwrap().clone();
+    alice.locked = true;
+    database.users_update(alice).unwrap();
+    database.users_delete(1).unwrap();
+    database.users_restore(1).unwrap();
+    database.users_delete(1).unwrap();
+    assert_eq!(database.clock, 2);
+
+    // versions are reset, and neither hooks nor transitions run
+    database.rollback_to(savepoint);
+    assert_same(&database, &original);
+    assert_eq!(database.users_get(&0), Some(&user(0, "alice", 0)));
+    assert_eq!(database.clock, 2);
+    assert!(database.journal.is_empty());
+}
+
+#[test]
+fn rollback_does_not_check_other_tables() {
+    let mut database = database();
+    let savepoint = database.savepoint();
+    database.users_delete(1).unwrap();
+    database.users_purge(1).unwrap();
+    database.users_delete(0).unwrap();
+    database.users_purge(0).unwrap();
+    // groups are not journaled, so this is not rolled back
+    database.groups_delete(0).unwrap();
+
+    database.rollback_to(savepoint);
+    assert_eq!(database.users, self::database().users);
+    assert_eq!(database.users_get(&0).unwrap().group, 0);
+    assert_eq!(database.groups_get(&0), None);
+}
@@ -0,0 +1,158 @@
+//! Database defined in a `no_std` crate, using only the `alloc` collections.
+//!
+//! Building this crate on its own (`cargo build -p macrodb-no-std`) checks that macrodb and the
+//! code generated by its macros do not depend on the standard library.
+#![no_std]
+
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use macrodb::{table, table_prefix, table_range};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    SensorIdExists,
+    SensorNotFound,
+    SensorNameExists,
+    SensorNameEmpty,
+    ReadingIdExists,
+    ReadingNotFound,
+    ReadingSensorMissing,
+    SensorHasReadings,
+}
+
+pub type SensorId = u16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sensor {
+    pub id: SensorId,
+    pub name: String,
+}
+
+pub type ReadingId = u32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reading {
+    pub id: ReadingId,
+    pub sensor: SensorId,
+    pub value: i32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Database {
+    sensors: BTreeMap<SensorId, Sensor>,
+    sensor_by_name: BTreeMap<String, SensorId>,
+    readings: BTreeMap<ReadingId, Reading>,
+    readings_by_sensor: BTreeMap<SensorId, BTreeSet<ReadingId>>,
+    readings_by_value: BTreeMap<i32, BTreeSet<ReadingId>>,
+}
+
+impl Database {
+    fn sensor_name_not_empty(&self, sensor: &Sensor) -> Result<(), Error> {
+        if sensor.name.is_empty() {
+            return Err(Error::SensorNameEmpty);
+        }
+
+        Ok(())
+    }
+
+    table!(
+        sensors: Sensor,
+        id: SensorId,
+        missing Error => Error::SensorNotFound,
+        primary sensors id => Error::SensorIdExists,
+        unique sensor_by_name name => Error::SensorNameExists,
+        constraint sensor_name_not_empty _ => (),
+        reverse readings_by_sensor id => Error::SensorHasReadings
+    );
+    table!(
+        readings: Reading,
+        id: ReadingId,
+        missing Error => Error::ReadingNotFound,
+        primary readings id => Error::ReadingIdExists,
+        foreign sensors sensor => Error::ReadingSensorMissing,
+        index readings_by_sensor sensor => (),
+        index readings_by_value value => ()
+    );
+    table_prefix!(sensors: Sensor, unique sensor_by_name);
+    table_range!(readings: Reading, index readings_by_value: i32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn sensor(id: SensorId, name: &str) -> Sensor {
+        Sensor {
+            id,
+            name: name.into(),
+        }
+    }
+
+    #[test]
+    fn can_insert_update_delete() {
+        let mut database = Database::default();
+        database.sensors_insert(sensor(0, "temperature")).unwrap();
+        database
+            .readings_insert(Reading {
+                id: database.readings_next_id(),
+                sensor: 0,
+                value: 21,
+            })
+            .unwrap();
+        assert_eq!(database.sensors_delete(0), Err(Error::SensorHasReadings));
+        database.sensors_update(sensor(0, "temp")).unwrap();
+        database.readings_delete(0).unwrap();
+        assert_eq!(database.sensors_delete(0), Ok(sensor(0, "temp")));
+    }
+
+    #[test]
+    fn checks_constraints() {
+        let mut database = Database::default();
+        assert_eq!(
+            database.sensors_insert(sensor(0, "")),
+            Err(Error::SensorNameEmpty)
+        );
+        assert_eq!(
+            database.readings_insert(Reading {
+                id: 0,
+                sensor: 0,
+                value: 0
+            }),
+            Err(Error::ReadingSensorMissing)
+        );
+    }
+
+    #[test]
+    fn can_query_ranges() {
+        let mut database = Database::default();
+        database.sensors_insert(sensor(0, "temperature")).unwrap();
+        database.sensors_insert(sensor(1, "pressure")).unwrap();
+        database.sensors_insert(sensor(2, "temperature-2")).unwrap();
+        for value in [-5, 0, 5, 10] {
+            database
+                .readings_insert(Reading {
+                    id: database.readings_next_id(),
+                    sensor: 0,
+                    value,
+                })
+                .unwrap();
+        }
+
+        let values: Vec<i32> = database
+            .readings_by_value_range(0..10)
+            .map(|reading| reading.value)
+            .collect();
+        assert_eq!(values, [0, 5]);
+
+        let ids: Vec<SensorId> = database
+            .sensor_by_name_prefix("temp")
+            .map(|sensor| sensor.id)
+            .collect();
+        assert_eq!(ids, [0, 2]);
+    }
+}
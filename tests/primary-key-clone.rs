@@ -10,6 +10,7 @@ struct User {
     age: u16,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Error {
     UserIdExists,
@@ -1,7 +1,9 @@
 use aatree::{AATreeMap, AATreeSet};
 use avl::{AvlTreeMap, AvlTreeSet};
+use btree_slab::{BTreeMap as BTreeSlabMap, BTreeSet as BTreeSlabSet};
 use hashbrown::{HashMap as HashMapBrown, HashSet as HashSetBrown};
-use im::{HashMap as HashMapIm, OrdMap};
+use im::{HashMap as HashMapIm, OrdMap, OrdSet};
+use indexmap::{IndexMap, IndexSet};
 use macrodb::table;
 use rand::{thread_rng, Rng};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
@@ -131,11 +133,39 @@ struct AADatabase {
     groups_by_privileged: AATreeMap<bool, AATreeSet<GroupId>>,
 }
 
+#[derive(Default)]
+struct IndexMapDatabase {
+    users: IndexMap<UserId, User>,
+    user_by_email: IndexMap<String, UserId>,
+    user_by_name: IndexMap<String, UserId>,
+    users_by_age: IndexMap<UserAge, IndexSet<UserId>>,
+    users_by_group: IndexMap<GroupId, IndexSet<UserId>>,
+
+    groups: IndexMap<GroupId, Group>,
+    group_by_name: IndexMap<String, GroupId>,
+    groups_by_privileged: IndexMap<bool, IndexSet<GroupId>>,
+}
+
+#[derive(Default)]
+struct BTreeSlabDatabase {
+    users: BTreeSlabMap<UserId, User>,
+    user_by_email: BTreeSlabMap<String, UserId>,
+    user_by_name: BTreeSlabMap<String, UserId>,
+    users_by_age: BTreeSlabMap<UserAge, BTreeSlabSet<UserId>>,
+    users_by_group: BTreeSlabMap<GroupId, BTreeSlabSet<UserId>>,
+
+    groups: BTreeSlabMap<GroupId, Group>,
+    group_by_name: BTreeSlabMap<String, GroupId>,
+    groups_by_privileged: OrdMap<bool, OrdSet<GroupId>>,
+}
+
 table_impl!(AvlDatabase);
 table_impl!(HashBrownDatabase);
 table_impl!(AADatabase);
 table_impl!(ImDatabase);
 table_impl!(StdDatabase);
+table_impl!(IndexMapDatabase);
+table_impl!(BTreeSlabDatabase);
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum UsersOperation {
@@ -305,28 +335,54 @@ macro_rules! apply {
     ($database:expr, $operation:expr) => {
         match $operation {
             Operation::Users(op) => match op {
-                UsersOperation::Insert(data) => $database.users_insert(data),
+                UsersOperation::Insert(data) => {
+                    let check = $database.users_can_insert(&data);
+                    let result = $database.users_insert(data);
+                    assert_eq!(check, result);
+                    result
+                }
                 UsersOperation::Update(data) => {
                     let id = data.id;
-                    $database
+                    let check = $database.users_can_update(&data);
+                    let result = $database
                         .users_update(data)
-                        .map(|prev| assert_eq!(prev.id, id))
+                        .map(|prev| assert_eq!(prev.id, id));
+                    assert_eq!(check, result);
+                    result
+                }
+                UsersOperation::Delete(id) => {
+                    let check = $database.users_can_delete(&id);
+                    let result = $database
+                        .users_delete(id)
+                        .map(|prev| assert_eq!(prev.id, id));
+                    assert_eq!(check, result);
+                    result
                 }
-                UsersOperation::Delete(id) => $database
-                    .users_delete(id)
-                    .map(|prev| assert_eq!(prev.id, id)),
             },
             Operation::Groups(op) => match op {
-                GroupsOperation::Insert(data) => $database.groups_insert(data),
+                GroupsOperation::Insert(data) => {
+                    let check = $database.groups_can_insert(&data);
+                    let result = $database.groups_insert(data);
+                    assert_eq!(check, result);
+                    result
+                }
                 GroupsOperation::Update(data) => {
                     let id = data.id;
-                    $database
+                    let check = $database.groups_can_update(&data);
+                    let result = $database
                         .groups_update(data)
-                        .map(|prev| assert_eq!(prev.id, id))
+                        .map(|prev| assert_eq!(prev.id, id));
+                    assert_eq!(check, result);
+                    result
+                }
+                GroupsOperation::Delete(id) => {
+                    let check = $database.groups_can_delete(&id);
+                    let result = $database
+                        .groups_delete(id)
+                        .map(|prev| assert_eq!(prev.id, id));
+                    assert_eq!(check, result);
+                    result
                 }
-                GroupsOperation::Delete(id) => $database
-                    .groups_delete(id)
-                    .map(|prev| assert_eq!(prev.id, id)),
             },
         }
     };
@@ -340,7 +396,7 @@ fn std_database() {
     let mut successes = 0;
 
     let batch_size = 100;
-    for _ in (0..100_00).step_by(batch_size) {
+    for _ in (0..10_000).step_by(batch_size) {
         let operations: Vec<Operation> = (0..batch_size)
             .map(|_| Operation::random(&mut rng))
             .collect();
@@ -372,7 +428,7 @@ fn im_database() {
     let mut successes = 0;
 
     let batch_size = 100;
-    for _ in (0..100_00).step_by(batch_size) {
+    for _ in (0..10_000).step_by(batch_size) {
         let operations: Vec<Operation> = (0..batch_size)
             .map(|_| Operation::random(&mut rng))
             .collect();
@@ -404,7 +460,7 @@ fn hashbrown_database() {
     let mut successes = 0;
 
     let batch_size = 100;
-    for _ in (0..100_00).step_by(batch_size) {
+    for _ in (0..10_000).step_by(batch_size) {
         let operations: Vec<Operation> = (0..batch_size)
             .map(|_| Operation::random(&mut rng))
             .collect();
@@ -436,7 +492,7 @@ fn avl_database() {
     let mut successes = 0;
 
     let batch_size = 100;
-    for _ in (0..100_00).step_by(batch_size) {
+    for _ in (0..10_000).step_by(batch_size) {
         let operations: Vec<Operation> = (0..batch_size)
             .map(|_| Operation::random(&mut rng))
             .collect();
@@ -468,7 +524,102 @@ fn aa_database() {
     let mut successes = 0;
 
     let batch_size = 100;
-    for _ in (0..100_00).step_by(batch_size) {
+    for _ in (0..10_000).step_by(batch_size) {
+        let operations: Vec<Operation> = (0..batch_size)
+            .map(|_| Operation::random(&mut rng))
+            .collect();
+        for operation in operations.into_iter() {
+            match apply!(database, operation) {
+                Ok(()) => {
+                    successes += 1;
+                }
+                Err(err) => {
+                    *errors.entry(err).or_default() += 1;
+                }
+            }
+        }
+
+        check!(database);
+    }
+
+    assert!(successes > 0);
+    for error in Error::iter() {
+        assert!(*errors.get(&error).unwrap() > 0);
+    }
+}
+
+#[test]
+fn indexmap_database() {
+    let mut rng = thread_rng();
+    let mut database = IndexMapDatabase::default();
+    let mut errors: BTreeMap<Error, usize> = BTreeMap::new();
+    let mut successes = 0;
+
+    let batch_size = 100;
+    for _ in (0..10_000).step_by(batch_size) {
+        let operations: Vec<Operation> = (0..batch_size)
+            .map(|_| Operation::random(&mut rng))
+            .collect();
+        for operation in operations.into_iter() {
+            match apply!(database, operation) {
+                Ok(()) => {
+                    successes += 1;
+                }
+                Err(err) => {
+                    *errors.entry(err).or_default() += 1;
+                }
+            }
+        }
+
+        check!(database);
+    }
+
+    assert!(successes > 0);
+    for error in Error::iter() {
+        assert!(*errors.get(&error).unwrap() > 0);
+    }
+}
+
+#[test]
+fn indexmap_keeps_insertion_order() {
+    let mut database = IndexMapDatabase::default();
+    for id in 0..3 {
+        database
+            .groups_insert(Group {
+                id,
+                name: format!("group-{id}"),
+                privileged: false,
+            })
+            .unwrap();
+    }
+    for id in 0..4 {
+        database
+            .users_insert(User {
+                id,
+                group: 0,
+                name: format!("user-{id}"),
+                email: format!("user-{id}@example.com"),
+                age: 20,
+            })
+            .unwrap();
+    }
+    database.users_delete(1).unwrap();
+    database.groups_delete(1).unwrap();
+
+    assert!(database.users.keys().eq(&[0, 2, 3]));
+    assert!(database.groups.keys().eq(&[0, 2]));
+    assert!(database.users_by_group[&0].iter().eq(&[0, 2, 3]));
+}
+
+#[test]
+fn btree_slab_database() {
+    let mut rng = thread_rng();
+    let mut database = BTreeSlabDatabase::default();
+    let mut errors: BTreeMap<Error, usize> = BTreeMap::new();
+    let mut successes = 0;
+
+    let batch_size = 100;
+    for _ in (0..10_000).step_by(batch_size) {
         let operations: Vec<Operation> = (0..batch_size)
             .map(|_| Operation::random(&mut rng))
             .collect();
@@ -491,3 +642,192 @@ fn aa_database() {
         assert!(*errors.get(&error).unwrap() > 0);
     }
 }
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Member {
+    id: UserId,
+    team: GroupId,
+    email: String,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Team {
+    id: GroupId,
+    deleted: bool,
+}
+
+#[derive(Default)]
+struct SoftDeleteDatabase {
+    members: BTreeMap<UserId, Member>,
+    member_by_email: BTreeMap<String, UserId>,
+    members_by_team: BTreeMap<GroupId, BTreeSet<UserId>>,
+
+    teams: BTreeMap<GroupId, Team>,
+}
+
+impl SoftDeleteDatabase {
+    table!(
+        members: Member,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary members id => Error::UserIdExists,
+        soft_delete deleted => true,
+        unique member_by_email email => Error::UserEmailExists,
+        foreign live teams team => Error::GroupNotFound,
+        index members_by_team team => ()
+    );
+    table!(
+        teams: Team,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary teams id => Error::GroupIdExists,
+        soft_delete deleted => true,
+        reverse members_by_team id => Error::GroupNotEmpty
+    );
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum SoftDeleteOperation {
+    InsertMember(Member),
+    UpdateMember(Member),
+    DeleteMember(UserId),
+    RestoreMember(UserId),
+    PurgeMember(UserId),
+    InsertTeam(Team),
+    UpdateTeam(Team),
+    DeleteTeam(GroupId),
+    RestoreTeam(GroupId),
+    PurgeTeam(GroupId),
+}
+
+impl SoftDeleteOperation {
+    fn random_member<R: Rng + ?Sized>(rng: &mut R) -> Member {
+        Member {
+            id: rng.gen_range(0..100),
+            team: rng.gen_range(0..10),
+            email: format!("email-{}", rng.gen_range(0..200)),
+            // occasionally try to write tombstoned rows, which has to be rejected
+            deleted: rng.gen_ratio(1, 10),
+        }
+    }
+
+    fn random_team<R: Rng + ?Sized>(rng: &mut R) -> Team {
+        Team {
+            id: rng.gen_range(0..10),
+            deleted: rng.gen_ratio(1, 10),
+        }
+    }
+
+    fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        match rng.gen_range(0..9) {
+            0 | 1 => SoftDeleteOperation::InsertMember(Self::random_member(rng)),
+            2 => SoftDeleteOperation::UpdateMember(Self::random_member(rng)),
+            3 => SoftDeleteOperation::DeleteMember(rng.gen_range(0..100)),
+            4 => SoftDeleteOperation::RestoreMember(rng.gen_range(0..100)),
+            5 => SoftDeleteOperation::PurgeMember(rng.gen_range(0..100)),
+            6 => SoftDeleteOperation::InsertTeam(Self::random_team(rng)),
+            7 => SoftDeleteOperation::UpdateTeam(Self::random_team(rng)),
+            8 => match rng.gen_range(0..3) {
+                0 => SoftDeleteOperation::DeleteTeam(rng.gen_range(0..10)),
+                1 => SoftDeleteOperation::RestoreTeam(rng.gen_range(0..10)),
+                _ => SoftDeleteOperation::PurgeTeam(rng.gen_range(0..10)),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    fn apply(self, database: &mut SoftDeleteDatabase) -> Result<(), Error> {
+        match self {
+            SoftDeleteOperation::InsertMember(data) => {
+                let check = database.members_can_insert(&data);
+                let result = database.members_insert(data);
+                assert_eq!(check, result);
+                result
+            }
+            SoftDeleteOperation::UpdateMember(data) => {
+                let check = database.members_can_update(&data);
+                let result = database.members_update(data).map(|_| ());
+                assert_eq!(check, result);
+                result
+            }
+            SoftDeleteOperation::DeleteMember(id) => {
+                let check = database.members_can_delete(&id);
+                let result = database.members_delete(id).map(|_| ());
+                assert_eq!(check, result);
+                result
+            }
+            SoftDeleteOperation::RestoreMember(id) => database.members_restore(id),
+            SoftDeleteOperation::PurgeMember(id) => database.members_purge(id).map(|_| ()),
+            SoftDeleteOperation::InsertTeam(data) => {
+                let check = database.teams_can_insert(&data);
+                let result = database.teams_insert(data);
+                assert_eq!(check, result);
+                result
+            }
+            SoftDeleteOperation::UpdateTeam(data) => {
+                let check = database.teams_can_update(&data);
+                let result = database.teams_update(data).map(|_| ());
+                assert_eq!(check, result);
+                result
+            }
+            SoftDeleteOperation::DeleteTeam(id) => {
+                let check = database.teams_can_delete(&id);
+                let result = database.teams_delete(id).map(|_| ());
+                assert_eq!(check, result);
+                result
+            }
+            SoftDeleteOperation::RestoreTeam(id) => database.teams_restore(id),
+            SoftDeleteOperation::PurgeTeam(id) => database.teams_purge(id).map(|_| ()),
+        }
+    }
+}
+
+#[test]
+fn soft_delete_database() {
+    let mut rng = thread_rng();
+    let mut database = SoftDeleteDatabase::default();
+    let mut errors: BTreeMap<Error, usize> = BTreeMap::new();
+    let mut successes = 0;
+
+    for _ in 0..100 {
+        for _ in 0..100 {
+            match SoftDeleteOperation::random(&mut rng).apply(&mut database) {
+                Ok(()) => successes += 1,
+                Err(err) => *errors.entry(err).or_default() += 1,
+            }
+        }
+
+        // only live rows are indexed, and live members belong to live teams
+        for (id, member) in database.members.iter() {
+            assert_eq!(&member.id, id);
+            let indexed = database.member_by_email.get(&member.email) == Some(id);
+            assert_eq!(indexed, !member.deleted);
+            if !member.deleted {
+                assert!(database.members_by_team[&member.team].contains(id));
+                assert!(!database.teams[&member.team].deleted);
+            }
+        }
+        for (email, id) in database.member_by_email.iter() {
+            assert_eq!(&database.members_get(id).unwrap().email, email);
+        }
+        for (team, members) in database.members_by_team.iter() {
+            assert!(database.teams_get(team).is_some());
+            for id in members.iter() {
+                assert_eq!(&database.members_get(id).unwrap().team, team);
+            }
+        }
+    }
+
+    assert!(successes > 0);
+    for error in [
+        Error::UserNotFound,
+        Error::UserIdExists,
+        Error::UserEmailExists,
+        Error::GroupNotFound,
+        Error::GroupIdExists,
+        Error::GroupNotEmpty,
+    ] {
+        assert!(*errors.get(&error).unwrap() > 0);
+    }
+}
@@ -0,0 +1,279 @@
+use macrodb::{replication, table, ChangeLog, ReplicationError, Sequenced};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::mpsc;
+use std::thread;
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    rev: u64,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    UserStale,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: HashMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+    log: ChangeLog<Record>,
+    sequence: u64,
+    inserted: usize,
+}
+
+impl Database {
+    fn count_insert(&mut self, _user: &mut User) -> Result<(), Error> {
+        self.inserted += 1;
+        Ok(())
+    }
+
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        version rev => Error::UserStale,
+        soft_delete deleted => true,
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => (),
+        before_insert count_insert _ => (),
+        changes log _ => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        reverse users_by_group id => Error::GroupNotEmpty,
+        changes log _ => ()
+    );
+
+    /// Tables and indices of the database, for comparison.
+    #[allow(clippy::type_complexity)]
+    fn state(
+        &self,
+    ) -> (
+        &BTreeMap<UserId, User>,
+        &HashMap<String, UserId>,
+        &BTreeMap<GroupId, BTreeSet<UserId>>,
+        &BTreeMap<GroupId, Group>,
+    ) {
+        (
+            &self.users,
+            &self.user_by_email,
+            &self.users_by_group,
+            &self.groups,
+        )
+    }
+}
+
+replication!(
+    Database,
+    Error,
+    sequence,
+    #[derive(Clone, Debug, PartialEq)]
+    enum Record {
+        groups: Group,
+        users: User,
+    }
+);
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+        rev: 0,
+        deleted: false,
+    }
+}
+
+fn group(id: GroupId, name: &str) -> Group {
+    Group {
+        id,
+        name: name.into(),
+    }
+}
+
+/// Apply a few changes of every kind to the database.
+fn populate(database: &mut Database) {
+    database.groups_insert(group(0, "admins")).unwrap();
+    database.groups_insert(group(1, "users")).unwrap();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.users_insert(user(1, "bob", 1)).unwrap();
+    database.users_insert(user(2, "carol", 1)).unwrap();
+    database.users_update(user(1, "robert", 0)).unwrap();
+    database.users_delete(2).unwrap();
+    database.users_insert(user(3, "carol", 1)).unwrap();
+    database.users_delete(0).unwrap();
+    database.users_restore(0).unwrap();
+    database.users_delete(3).unwrap();
+    database.users_purge(3).unwrap();
+    database.users_purge(2).unwrap();
+    database.groups_delete(1).unwrap();
+}
+
+#[test]
+fn follower_applies_changes_from_channel() {
+    let (sender, receiver) = mpsc::channel::<Sequenced<Record>>();
+    let follower = thread::spawn(move || {
+        let mut follower = Database::default();
+        for entry in receiver {
+            follower.apply_change(entry).unwrap();
+        }
+        follower
+    });
+
+    let mut leader = Database::default();
+    populate(&mut leader);
+    for entry in leader.log.since(0) {
+        sender.send(entry.clone()).unwrap();
+    }
+    drop(sender);
+
+    let follower = follower.join().unwrap();
+    assert_eq!(follower.state(), leader.state());
+    assert_eq!(follower.sequence, leader.log.sequence());
+    assert_eq!(follower.users_get(&1).unwrap().rev, 1);
+
+    // hooks are not run on the follower, and it does not log the changes again
+    assert_eq!(leader.inserted, 4);
+    assert_eq!(follower.inserted, 0);
+    assert_eq!(follower.log.sequence(), 0);
+}
+
+#[test]
+fn follower_detects_gaps() {
+    let mut leader = Database::default();
+    populate(&mut leader);
+    let entries: Vec<_> = leader.log.since(0).cloned().collect();
+
+    let mut follower = Database::default();
+    for entry in &entries[..5] {
+        follower.apply_change(entry.clone()).unwrap();
+    }
+    assert_eq!(
+        follower.apply_change(entries[6].clone()),
+        Err(ReplicationError::Gap {
+            expected: 5,
+            received: 6
+        })
+    );
+    assert_eq!(
+        follower.apply_change(entries[3].clone()),
+        Err(ReplicationError::Duplicate { sequence: 3 })
+    );
+
+    // the missing changes are sent again
+    for entry in leader.log.since(follower.sequence) {
+        follower.apply_change(entry.clone()).unwrap();
+    }
+    assert_eq!(follower.state(), leader.state());
+
+    leader.log.truncate(follower.sequence);
+    assert_eq!(leader.log.since(0).count(), 0);
+    leader.groups_insert(group(2, "guests")).unwrap();
+    let entries: Vec<_> = leader.log.since(0).collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].sequence, 14);
+}
+
+#[test]
+fn follower_rejects_diverged_changes() {
+    let mut leader = Database::default();
+    leader.groups_insert(group(0, "admins")).unwrap();
+    leader.users_insert(user(0, "alice", 0)).unwrap();
+    leader.users_update(user(0, "alicia", 0)).unwrap();
+
+    let mut follower = Database::default();
+    let mut entries = leader.log.since(0).cloned();
+    follower.apply_change(entries.next().unwrap()).unwrap();
+    follower.users_insert(user(0, "other", 0)).unwrap();
+    assert_eq!(
+        follower.apply_change(entries.next().unwrap()),
+        Err(ReplicationError::Rejected {
+            sequence: 1,
+            table: "users",
+            error: Error::UserIdExists
+        })
+    );
+    assert_eq!(follower.sequence, 1);
+
+    let mut follower = Database::default();
+    let mut entries = leader.log.since(0).cloned();
+    follower.apply_change(entries.next().unwrap()).unwrap();
+    // the follower skips the insert of the user
+    entries.next().unwrap();
+    follower.sequence += 1;
+    assert_eq!(
+        follower.apply_change(entries.next().unwrap()),
+        Err(ReplicationError::Rejected {
+            sequence: 2,
+            table: "users",
+            error: Error::UserNotFound
+        })
+    );
+}
+
+#[test]
+fn follower_rejects_conflicting_unique_keys() {
+    let mut leader = Database::default();
+    leader.groups_insert(group(0, "admins")).unwrap();
+    leader.users_insert(user(0, "alice", 0)).unwrap();
+    leader.users_insert(user(1, "bob", 0)).unwrap();
+    leader.users_update(user(1, "carol", 0)).unwrap();
+
+    let mut follower = Database::default();
+    let mut entries = leader.log.since(0).cloned();
+    follower.apply_change(entries.next().unwrap()).unwrap();
+    // the follower has a different user with the email of the replicated one
+    follower.users_insert(user(5, "alice", 0)).unwrap();
+    follower.users_insert(user(6, "carol", 0)).unwrap();
+    let state = follower.clone();
+    assert_eq!(
+        follower.apply_change(entries.next().unwrap()),
+        Err(ReplicationError::Rejected {
+            sequence: 1,
+            table: "users",
+            error: Error::UserEmailExists
+        })
+    );
+    assert_eq!(follower.state(), state.state());
+
+    // skip the rejected insert of alice, so that the update of bob conflicts
+    follower.sequence += 1;
+    follower.apply_change(entries.next().unwrap()).unwrap();
+    assert_eq!(
+        follower.apply_change(entries.next().unwrap()),
+        Err(ReplicationError::Rejected {
+            sequence: 3,
+            table: "users",
+            error: Error::UserEmailExists
+        })
+    );
+    assert_eq!(follower.users_get(&1).unwrap().email, "bob");
+    assert_eq!(follower.user_by_email.get("carol"), Some(&6));
+}
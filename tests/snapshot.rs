@@ -0,0 +1,205 @@
+use macrodb::{snapshot, table, SnapshotError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+    GroupNameEmpty,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: HashMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: HashMap<GroupId, Group>,
+}
+
+impl Database {
+    fn check_group(&self, group: &Group) -> Result<(), Error> {
+        if group.name.is_empty() {
+            return Err(Error::GroupNameEmpty);
+        }
+        Ok(())
+    }
+
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        soft_delete deleted => true,
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        constraint check_group _ => (),
+        reverse users_by_group id => Error::GroupNotEmpty
+    );
+    // users are loaded before the groups they reference
+    snapshot!(Error, users: User, groups: Group);
+}
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+        deleted: false,
+    }
+}
+
+fn database() -> Database {
+    let mut database = Database::default();
+    for (id, name) in [(0, "admins"), (1, "users")] {
+        database
+            .groups_insert(Group {
+                id,
+                name: name.into(),
+            })
+            .unwrap();
+    }
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.users_insert(user(1, "bob", 1)).unwrap();
+    database.users_insert(user(2, "carol", 1)).unwrap();
+    database
+}
+
+fn save(database: &Database) -> serde_json::Value {
+    database
+        .snapshot_save(serde_json::value::Serializer)
+        .unwrap()
+}
+
+fn load(value: serde_json::Value) -> Result<Database, SnapshotError<Error, serde_json::Error>> {
+    Database::snapshot_load(value)
+}
+
+#[test]
+fn snapshot_contains_only_tables() {
+    let value = save(&database());
+    let tables: Vec<&String> = value.as_object().unwrap().keys().collect();
+    assert_eq!(tables, ["groups", "users"]);
+    assert_eq!(value["users"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn load_rebuilds_indices() {
+    let mut database = database();
+    database.users_delete(2).unwrap();
+    database.users_insert(user(3, "carol", 0)).unwrap();
+
+    let data = serde_json::to_string(&save(&database)).unwrap();
+    let mut loaded =
+        Database::snapshot_load(&mut serde_json::Deserializer::from_str(&data)).unwrap();
+    assert_eq!(loaded, database);
+    assert_eq!(loaded.users_get(&2), None);
+    assert!(loaded.users.get(&2).unwrap().deleted);
+    assert_eq!(loaded.user_by_email.get("carol"), Some(&3));
+    assert_eq!(loaded.groups_delete(1), Err(Error::GroupNotEmpty));
+}
+
+#[test]
+fn load_from_sequence() {
+    let value = save(&database());
+    let value = serde_json::json!([value["users"], value["groups"]]);
+    assert_eq!(load(value).unwrap(), database());
+}
+
+#[test]
+fn load_rejects_invalid_rows() {
+    let mut value = save(&database());
+    value["users"][2]["email"] = "alice".into();
+    assert!(matches!(
+        load(value),
+        Err(SnapshotError::Invalid {
+            table: "users",
+            row: 2,
+            error: Error::UserEmailExists
+        })
+    ));
+
+    let mut value = save(&database());
+    value["users"][1]["group"] = 7.into();
+    assert!(matches!(
+        load(value),
+        Err(SnapshotError::Invalid {
+            table: "users",
+            row: 1,
+            error: Error::GroupNotFound
+        })
+    ));
+
+    let mut value = save(&database());
+    value["users"][1]["id"] = 0.into();
+    assert!(matches!(
+        load(value),
+        Err(SnapshotError::Invalid {
+            table: "users",
+            row: 1,
+            error: Error::UserIdExists
+        })
+    ));
+
+    let mut value = save(&database());
+    value["groups"][0]["name"] = "".into();
+    let error = load(value).unwrap_err();
+    assert!(matches!(
+        error,
+        SnapshotError::Invalid {
+            table: "groups",
+            error: Error::GroupNameEmpty,
+            ..
+        }
+    ));
+    assert!(error.to_string().contains("GroupNameEmpty"));
+}
+
+#[test]
+fn load_rejects_malformed_snapshots() {
+    let mut value = save(&database());
+    value.as_object_mut().unwrap().remove("groups");
+    assert!(matches!(load(value), Err(SnapshotError::Decode(_))));
+
+    let mut value = save(&database());
+    value["sessions"] = serde_json::json!([]);
+    assert!(matches!(load(value), Err(SnapshotError::Decode(_))));
+
+    let mut value = save(&database());
+    value["users"][0]["id"] = "zero".into();
+    assert!(matches!(load(value), Err(SnapshotError::Decode(_))));
+}
@@ -0,0 +1,206 @@
+use macrodb::table;
+use std::collections::{BTreeMap, BTreeSet};
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    deleted_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Group {
+    id: GroupId,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Post {
+    id: u64,
+    group: GroupId,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+    PostIdExists,
+    PostNotFound,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: BTreeMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+    posts: BTreeMap<u64, Post>,
+    posts_by_group: BTreeMap<GroupId, BTreeSet<u64>>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        soft_delete deleted_at => Some(now()),
+        unique user_by_email email => Error::UserEmailExists,
+        foreign live groups group => Error::GroupNotFound,
+        index users_by_group group => ()
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        soft_delete deleted => true,
+        reverse users_by_group id => Error::GroupNotEmpty,
+        reverse posts_by_group id => Error::GroupNotEmpty
+    );
+    table!(
+        posts: Post,
+        id: u64,
+        missing Error => Error::PostNotFound,
+        primary posts id => Error::PostIdExists,
+        foreign groups group => Error::GroupNotFound,
+        index posts_by_group group => ()
+    );
+}
+
+/// Timestamp used for deleted rows.
+fn now() -> u64 {
+    7
+}
+
+fn user(id: UserId, email: &str) -> User {
+    User {
+        id,
+        email: email.into(),
+        group: 0,
+        deleted_at: None,
+    }
+}
+
+fn database() -> Database {
+    let mut database = Database::default();
+    database
+        .groups_insert(Group {
+            id: 0,
+            deleted: false,
+        })
+        .unwrap();
+    database.users_insert(user(0, "alice@example.com")).unwrap();
+    database
+}
+
+#[test]
+fn delete_keeps_row() {
+    let mut database = database();
+    let deleted = database.users_delete(0).unwrap();
+    assert_eq!(deleted.deleted_at, Some(7));
+    assert_eq!(database.users.get(&0), Some(&deleted));
+    assert_eq!(database.users_get(&0), None);
+    assert!(database.user_by_email.is_empty());
+    assert!(database.users_by_group.is_empty());
+
+    assert_eq!(database.users_delete(0), Err(Error::UserNotFound));
+    assert_eq!(
+        database.users_update(user(0, "bob@example.com")),
+        Err(Error::UserNotFound)
+    );
+    assert_eq!(
+        database.users_insert(user(0, "bob@example.com")),
+        Err(Error::UserIdExists)
+    );
+
+    // rows cannot be inserted as deleted
+    let deleted = User {
+        deleted_at: Some(7),
+        ..user(1, "bob@example.com")
+    };
+    assert_eq!(
+        database.users_validate_insert(&deleted),
+        [Error::UserNotFound]
+    );
+    assert_eq!(database.users_insert(deleted), Err(Error::UserNotFound));
+    assert_eq!(database.users.get(&1), None);
+}
+
+#[test]
+fn unique_values_can_be_reused() {
+    let mut database = database();
+    database.users_delete(0).unwrap();
+    database.users_insert(user(1, "alice@example.com")).unwrap();
+    assert_eq!(database.user_by_email.get("alice@example.com"), Some(&1));
+}
+
+#[test]
+fn restore_reruns_checks() {
+    let mut database = database();
+    assert_eq!(database.users_restore(0), Err(Error::UserNotFound));
+    database.users_delete(0).unwrap();
+    database.users_insert(user(1, "alice@example.com")).unwrap();
+
+    // email has been taken in the meantime
+    assert_eq!(database.users_restore(0), Err(Error::UserEmailExists));
+    assert_eq!(database.users.get(&0).unwrap().deleted_at, Some(7));
+
+    database.users_delete(1).unwrap();
+    database.users_restore(0).unwrap();
+    assert_eq!(database.users_get(&0), Some(&user(0, "alice@example.com")));
+    assert_eq!(database.user_by_email.get("alice@example.com"), Some(&0));
+    assert_eq!(database.users_by_group.get(&0), Some(&[0].into()));
+}
+
+#[test]
+fn deleted_rows_are_not_referenced() {
+    let mut database = database();
+    assert_eq!(database.groups_delete(0), Err(Error::GroupNotEmpty));
+    database.users_delete(0).unwrap();
+    database.groups_delete(0).unwrap();
+    assert_eq!(
+        database.users_insert(user(1, "bob@example.com")),
+        Err(Error::GroupNotFound)
+    );
+
+    // restoring a user requires the group to be restored first
+    assert_eq!(database.users_restore(0), Err(Error::GroupNotFound));
+    database.groups_restore(0).unwrap();
+    database.users_restore(0).unwrap();
+}
+
+#[test]
+fn purge_removes_deleted_rows() {
+    let mut database = database();
+    assert_eq!(database.users_purge(0), Err(Error::UserNotFound));
+    database.users_delete(0).unwrap();
+    assert_eq!(database.users_purge(0).unwrap().email, "alice@example.com");
+    assert!(database.users.is_empty());
+    assert_eq!(database.users_restore(0), Err(Error::UserNotFound));
+    database.users_insert(user(0, "alice@example.com")).unwrap();
+}
+
+#[test]
+fn purge_checks_references() {
+    let mut database = database();
+    database.users_delete(0).unwrap();
+    database.groups_delete(0).unwrap();
+
+    // posts can reference deleted groups, which then cannot be purged
+    database.posts_insert(Post { id: 0, group: 0 }).unwrap();
+    assert_eq!(database.groups_purge(0), Err(Error::GroupNotEmpty));
+    assert!(database.groups.contains_key(&0));
+
+    database.posts_delete(0).unwrap();
+    database.groups_purge(0).unwrap();
+    assert!(database.groups.is_empty());
+}
@@ -0,0 +1,115 @@
+use macrodb::table;
+use std::collections::BTreeMap as Map;
+
+type OrderId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Placed,
+    Paid,
+    Shipped,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Order {
+    id: OrderId,
+    status: Status,
+    total: u64,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    OrderIdExists,
+    OrderNotFound,
+    OrderTotalZero,
+    OrderStatusBackwards,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    orders: Map<OrderId, Order>,
+}
+
+impl Database {
+    fn check_order(&self, order: &Order) -> Result<(), Error> {
+        if order.total == 0 {
+            return Err(Error::OrderTotalZero);
+        }
+
+        Ok(())
+    }
+
+    fn check_status(&self, old: &Order, new: &Order) -> Result<(), Error> {
+        if new.status < old.status {
+            return Err(Error::OrderStatusBackwards);
+        }
+
+        Ok(())
+    }
+
+    table!(
+        orders: Order,
+        id: OrderId,
+        missing Error => Error::OrderNotFound,
+        primary orders id => Error::OrderIdExists,
+        constraint check_order _ => (),
+        transition check_status _ => ()
+    );
+}
+
+#[test]
+fn transition_not_checked_on_insert() {
+    let mut database = Database::default();
+    let order = Order {
+        id: 0,
+        status: Status::Shipped,
+        total: 10,
+    };
+    database.orders_insert(order).unwrap();
+}
+
+#[test]
+fn can_move_status_forward() {
+    let mut database = Database::default();
+    let mut order = Order {
+        id: 0,
+        status: Status::Placed,
+        total: 10,
+    };
+    database.orders_insert(order.clone()).unwrap();
+    order.status = Status::Paid;
+    database.orders_update(order.clone()).unwrap();
+    order.status = Status::Shipped;
+    database.orders_update(order.clone()).unwrap();
+    database.orders_update(order.clone()).unwrap();
+}
+
+#[test]
+fn cannot_move_status_backwards() {
+    let mut database = Database::default();
+    let mut order = Order {
+        id: 0,
+        status: Status::Paid,
+        total: 10,
+    };
+    database.orders_insert(order.clone()).unwrap();
+    order.status = Status::Placed;
+    let result = database.orders_update(order);
+    assert_eq!(result, Err(Error::OrderStatusBackwards));
+    assert_eq!(database.orders_get(&0).unwrap().status, Status::Paid);
+}
+
+#[test]
+fn constraint_still_checked_on_update() {
+    let mut database = Database::default();
+    let mut order = Order {
+        id: 0,
+        status: Status::Placed,
+        total: 10,
+    };
+    database.orders_insert(order.clone()).unwrap();
+    order.total = 0;
+    let result = database.orders_update(order);
+    assert_eq!(result, Err(Error::OrderTotalZero));
+}
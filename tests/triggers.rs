@@ -0,0 +1,207 @@
+use macrodb::table;
+use std::collections::BTreeMap;
+
+type UserId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    email: String,
+    updated: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Change {
+    id: u64,
+    user: UserId,
+    email: String,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    UserEmailInvalid,
+    UserReserved,
+    ChangeIdExists,
+    ChangeNotFound,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: BTreeMap<String, UserId>,
+    clock: u64,
+    audit: Vec<String>,
+    changes: BTreeMap<u64, Change>,
+}
+
+impl Database {
+    fn normalize_user(&mut self, user: &mut User) -> Result<(), Error> {
+        if !user.email.contains('@') {
+            return Err(Error::UserEmailInvalid);
+        }
+        user.email = user.email.to_lowercase();
+        self.clock += 1;
+        user.updated = self.clock;
+        Ok(())
+    }
+
+    fn stamp_user(&mut self, old: &User, new: &mut User) -> Result<(), Error> {
+        if old.email == "root@example.com" {
+            return Err(Error::UserReserved);
+        }
+        self.normalize_user(new)?;
+        self.audit
+            .push(format!("updated {} -> {}", old.email, new.email));
+        // the old row is still stored while the hook runs, so it can be referenced
+        self.changes_insert(Change {
+            id: self.changes.len() as u64,
+            user: old.id,
+            email: old.email.clone(),
+        })?;
+        Ok(())
+    }
+
+    fn audit_user(&mut self, old: &User) {
+        self.audit.push(format!("deleted {}", old.email));
+    }
+
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        before_insert normalize_user _ => (),
+        before_update stamp_user _ => (),
+        after_delete audit_user _ => (),
+        unique user_by_email email => Error::UserEmailExists
+    );
+
+    table!(
+        changes: Change,
+        id: u64,
+        missing Error => Error::ChangeNotFound,
+        primary changes id => Error::ChangeIdExists,
+        foreign users user => Error::UserNotFound
+    );
+}
+
+fn user(id: UserId, email: &str) -> User {
+    User {
+        id,
+        email: email.into(),
+        updated: 0,
+    }
+}
+
+#[test]
+fn before_insert_normalizes() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "Alice@Example.com")).unwrap();
+    assert_eq!(
+        database.users_get(&0),
+        Some(&User {
+            id: 0,
+            email: "alice@example.com".into(),
+            updated: 1,
+        })
+    );
+    assert_eq!(database.user_by_email.get("alice@example.com"), Some(&0));
+
+    // checks run on the normalized row
+    assert_eq!(
+        database.users_insert(user(1, "ALICE@example.com")),
+        Err(Error::UserEmailExists)
+    );
+}
+
+#[test]
+fn before_insert_can_abort() {
+    let mut database = Database::default();
+    assert_eq!(
+        database.users_insert(user(0, "invalid")),
+        Err(Error::UserEmailInvalid)
+    );
+    assert!(database.users.is_empty());
+    assert!(database.user_by_email.is_empty());
+}
+
+#[test]
+fn before_update_sees_old_row() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "alice@example.com")).unwrap();
+    let old = database.users_update(user(0, "Bob@Example.com")).unwrap();
+    assert_eq!(old.email, "alice@example.com");
+    assert_eq!(database.users_get(&0).unwrap().email, "bob@example.com");
+    assert_eq!(database.users_get(&0).unwrap().updated, 2);
+    assert_eq!(database.user_by_email.get("bob@example.com"), Some(&0));
+    assert_eq!(
+        database.audit,
+        ["updated alice@example.com -> bob@example.com"]
+    );
+}
+
+#[test]
+fn before_update_can_reference_old_row() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "alice@example.com")).unwrap();
+    database.users_insert(user(1, "carol@example.com")).unwrap();
+    database.users_update(user(0, "bob@example.com")).unwrap();
+    database.users_update(user(0, "dave@example.com")).unwrap();
+    assert_eq!(
+        database.changes.values().cloned().collect::<Vec<_>>(),
+        [
+            Change {
+                id: 0,
+                user: 0,
+                email: "alice@example.com".into(),
+            },
+            Change {
+                id: 1,
+                user: 0,
+                email: "bob@example.com".into(),
+            },
+        ]
+    );
+
+    // the indices still point to the stored row while the hook runs
+    assert_eq!(database.user_by_email.get("dave@example.com"), Some(&0));
+    assert_eq!(database.user_by_email.get("carol@example.com"), Some(&1));
+    assert_eq!(database.user_by_email.len(), 2);
+    assert_eq!(database.users.len(), 2);
+
+    // the audit rows are a normal table
+    database.changes_delete(0).unwrap();
+    database.changes_delete(1).unwrap();
+    assert_eq!(database.changes_delete(1), Err(Error::ChangeNotFound));
+}
+
+#[test]
+fn before_update_can_abort() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "root@example.com")).unwrap();
+    assert_eq!(
+        database.users_update(user(0, "other@example.com")),
+        Err(Error::UserReserved)
+    );
+    assert_eq!(
+        database.users_update(user(1, "other@example.com")),
+        Err(Error::UserNotFound)
+    );
+    assert_eq!(database.users_get(&0).unwrap().email, "root@example.com");
+    assert_eq!(database.user_by_email.get("root@example.com"), Some(&0));
+    assert!(database.audit.is_empty());
+}
+
+#[test]
+fn after_delete_runs() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "alice@example.com")).unwrap();
+    assert_eq!(database.users_delete(1), Err(Error::UserNotFound));
+    assert!(database.audit.is_empty());
+    database.users_delete(0).unwrap();
+    assert_eq!(database.audit, ["deleted alice@example.com"]);
+}
@@ -1,7 +1,8 @@
-use macrodb::table;
+use macrodb::{table, table_prefix, table_range};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 /// Errors that can result from database operations.
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum UserError {
     UserNotFound,
@@ -55,6 +56,10 @@ impl Users {
         index users_by_age age => (),
         index users_by_name name => ()
     );
+    table_range!(users: User, index users_by_name: String);
+    table_range!(users: User, unique user_by_email: String);
+    table_prefix!(users: User, index users_by_name);
+    table_prefix!(users: User, unique user_by_email);
 }
 
 #[test]
@@ -334,18 +339,180 @@ fn can_delete_two_users() {
 #[test]
 fn can_get_next_id() {
     let mut data = Users::default();
-    assert_eq!(data.users.contains_key(&data.users_next_id()), false);
+    assert!(!data.users.contains_key(&data.users_next_id()));
     data.users_insert(User::default()).unwrap();
-    assert_eq!(data.users.contains_key(&data.users_next_id()), false);
+    assert!(!data.users.contains_key(&data.users_next_id()));
     data.users_insert(User {
         id: 1,
         email: "other@example.com".into(),
         ..Default::default()
     })
     .unwrap();
-    assert_eq!(data.users.contains_key(&data.users_next_id()), false);
+    assert!(!data.users.contains_key(&data.users_next_id()));
     data.users_delete(0).unwrap();
-    assert_eq!(data.users.contains_key(&data.users_next_id()), false);
+    assert!(!data.users.contains_key(&data.users_next_id()));
     data.users_delete(1).unwrap();
-    assert_eq!(data.users.contains_key(&data.users_next_id()), false);
+    assert!(!data.users.contains_key(&data.users_next_id()));
+}
+
+fn insert_users(data: &mut Users, users: &[(&str, &str, u32)]) {
+    for (name, email, age) in users {
+        data.users_insert(User {
+            id: data.users_next_id(),
+            name: name.to_string(),
+            email: email.to_string(),
+            age: *age,
+        })
+        .unwrap();
+    }
+}
+
+#[test]
+fn can_query_index_range() {
+    let mut data = Users::default();
+    insert_users(
+        &mut data,
+        &[
+            ("alice", "alice@example.com", 30),
+            ("bob", "bob@example.com", 17),
+            ("carol", "carol@example.com", 45),
+            ("dave", "dave@example.com", 30),
+        ],
+    );
+
+    let emails: Vec<&str> = data
+        .users_by_name_range("b".to_string().."d".to_string())
+        .map(|user| user.email.as_str())
+        .collect();
+    assert_eq!(emails, ["bob@example.com", "carol@example.com"]);
+
+    let ages: Vec<u32> = data.users_by_name_range(..).map(|user| user.age).collect();
+    assert_eq!(ages, [30, 17, 45, 30]);
+
+    assert_eq!(data.users_by_name_range("e".to_string()..).count(), 0);
+}
+
+#[test]
+fn can_query_unique_range() {
+    let mut data = Users::default();
+    insert_users(
+        &mut data,
+        &[
+            ("alice", "alice@example.com", 30),
+            ("bob", "bob@example.com", 17),
+            ("carol", "carol@example.com", 45),
+        ],
+    );
+
+    let names: Vec<&str> = data
+        .user_by_email_range("b".to_string().."c".to_string())
+        .map(|user| user.name.as_str())
+        .collect();
+    assert_eq!(names, ["bob"]);
+}
+
+#[test]
+fn can_query_index_prefix() {
+    let mut data = Users::default();
+    insert_users(
+        &mut data,
+        &[
+            ("abigail", "abigail@example.com", 30),
+            ("bob", "bob@example.com", 17),
+            ("abe", "abe@example.com", 45),
+            ("a", "a@example.com", 45),
+        ],
+    );
+
+    let names: Vec<&str> = data
+        .users_by_name_prefix("ab")
+        .map(|user| user.name.as_str())
+        .collect();
+    assert_eq!(names, ["abe", "abigail"]);
+
+    assert_eq!(data.users_by_name_prefix("").count(), 4);
+    assert_eq!(data.users_by_name_prefix("c").count(), 0);
+}
+
+#[test]
+fn can_query_unique_prefix() {
+    let mut data = Users::default();
+    insert_users(
+        &mut data,
+        &[
+            ("alice", "alice@example.com", 30),
+            ("bob", "bob@example.org", 17),
+            ("alice", "alice@example.org", 45),
+        ],
+    );
+
+    let emails: Vec<&str> = data
+        .user_by_email_prefix("alice@")
+        .map(|user| user.email.as_str())
+        .collect();
+    assert_eq!(emails, ["alice@example.com", "alice@example.org"]);
+}
+
+/// User database with indices stored in ordered maps from other crates
+#[derive(Default)]
+struct OrderedUsers {
+    users: avl::AvlTreeMap<UserId, User>,
+    user_by_email: aatree::AATreeMap<String, UserId>,
+    users_by_age: btree_slab::BTreeMap<u32, btree_slab::BTreeSet<UserId>>,
+    users_by_name: im::OrdMap<String, im::OrdSet<UserId>>,
+}
+
+impl OrderedUsers {
+    table!(
+        users: User,
+        id: UserId,
+        missing UserError => UserError::UserNotFound,
+        primary users id => UserError::UserIdExists,
+        unique user_by_email email => UserError::UserEmailExists,
+        index users_by_age age => (),
+        index users_by_name name => ()
+    );
+    table_range!(users: User, index users_by_age: u32);
+    table_range!(users: User, unique user_by_email: String);
+    table_prefix!(users: User, index users_by_name);
+}
+
+#[test]
+fn can_query_ranges_of_other_maps() {
+    let mut data = OrderedUsers::default();
+    for (id, (name, email, age)) in [
+        ("alice", "alice@example.com", 30),
+        ("bob", "bob@example.com", 17),
+        ("abe", "abe@example.com", 45),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        data.users_insert(User {
+            id: id as UserId,
+            name: name.into(),
+            email: email.into(),
+            age,
+        })
+        .unwrap();
+    }
+
+    let names: Vec<&str> = data
+        .users_by_age_range(18..)
+        .map(|user| user.name.as_str())
+        .collect();
+    assert_eq!(names, ["alice", "abe"]);
+    assert_eq!(data.users_by_age_range(50..).count(), 0);
+
+    let names: Vec<&str> = data
+        .user_by_email_range("alice".to_string()..="bob".to_string())
+        .map(|user| user.name.as_str())
+        .collect();
+    assert_eq!(names, ["alice"]);
+
+    let names: Vec<&str> = data
+        .users_by_name_prefix("a")
+        .map(|user| user.name.as_str())
+        .collect();
+    assert_eq!(names, ["abe", "alice"]);
 }
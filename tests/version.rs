@@ -0,0 +1,107 @@
+use macrodb::table;
+use std::collections::BTreeMap;
+
+type UserId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct User {
+    id: UserId,
+    email: String,
+    rev: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    StaleWrite,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: BTreeMap<String, UserId>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        version rev => Error::StaleWrite,
+        unique user_by_email email => Error::UserEmailExists
+    );
+}
+
+fn user(id: UserId, email: &str, rev: u32) -> User {
+    User {
+        id,
+        email: email.into(),
+        rev,
+    }
+}
+
+#[test]
+fn update_increments_version() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    let old = database.users_update(user(0, "bob", 0)).unwrap();
+    assert_eq!(old, user(0, "alice", 0));
+    assert_eq!(database.users_get(&0), Some(&user(0, "bob", 1)));
+    assert_eq!(database.user_by_email.get("bob"), Some(&0));
+
+    database.users_update(user(0, "carol", 1)).unwrap();
+    assert_eq!(database.users_get(&0), Some(&user(0, "carol", 2)));
+}
+
+#[test]
+fn version_wraps_around() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "alice", u32::MAX)).unwrap();
+    database.users_update(user(0, "bob", u32::MAX)).unwrap();
+    assert_eq!(database.users_get(&0), Some(&user(0, "bob", 0)));
+}
+
+#[test]
+fn stale_update_rejected() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    let first = database.users_get(&0).unwrap().clone();
+    let second = first.clone();
+
+    database
+        .users_update(User {
+            email: "bob".into(),
+            ..first
+        })
+        .unwrap();
+    let result = database.users_update(User {
+        email: "carol".into(),
+        ..second
+    });
+    assert_eq!(result, Err(Error::StaleWrite));
+    assert_eq!(database.users_get(&0), Some(&user(0, "bob", 1)));
+    assert_eq!(database.user_by_email.get("carol"), None);
+
+    // versions from the future are stale, too
+    let result = database.users_update(user(0, "carol", 5));
+    assert_eq!(result, Err(Error::StaleWrite));
+}
+
+#[test]
+fn stale_update_reported_by_checks() {
+    let mut database = Database::default();
+    database.users_insert(user(0, "alice", 3)).unwrap();
+    database.users_insert(user(1, "bob", 0)).unwrap();
+    assert_eq!(database.users_can_update(&user(0, "carol", 3)), Ok(()));
+    assert_eq!(
+        database.users_can_update(&user(0, "carol", 2)),
+        Err(Error::StaleWrite)
+    );
+    assert_eq!(
+        database.users_validate_update(&user(0, "bob", 2)),
+        [Error::StaleWrite, Error::UserEmailExists]
+    );
+}
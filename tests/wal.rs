@@ -0,0 +1,369 @@
+use macrodb::{table, wal, Wal, WalCodec, WalError, WalRecovery};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+type UserId = u64;
+type GroupId = u64;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct User {
+    id: UserId,
+    email: String,
+    group: GroupId,
+    rev: u64,
+    deleted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Group {
+    id: GroupId,
+    name: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Error {
+    UserIdExists,
+    UserNotFound,
+    UserEmailExists,
+    UserStale,
+    GroupIdExists,
+    GroupNotFound,
+    GroupNotEmpty,
+    LogFailed,
+}
+
+/// Codec that stores records as JSON, and fails to encode them while `broken` is set.
+#[derive(Default)]
+struct Json {
+    broken: Arc<AtomicBool>,
+}
+
+impl WalCodec for Json {
+    type Error = serde_json::Error;
+
+    fn encode<T: Serialize>(&mut self, value: &T, output: &mut Vec<u8>) -> Result<(), Self::Error> {
+        if self.broken.load(Ordering::Relaxed) {
+            return Err(serde::ser::Error::custom("codec is broken"));
+        }
+        serde_json::to_writer(output, value)
+    }
+
+    fn decode<T: DeserializeOwned>(&mut self, input: &[u8]) -> Result<T, Self::Error> {
+        serde_json::from_slice(input)
+    }
+}
+
+#[derive(Default)]
+struct Database {
+    users: BTreeMap<UserId, User>,
+    user_by_email: BTreeMap<String, UserId>,
+    users_by_group: BTreeMap<GroupId, BTreeSet<UserId>>,
+    groups: BTreeMap<GroupId, Group>,
+    wal: Option<Wal<Json>>,
+}
+
+impl Database {
+    table!(
+        users: User,
+        id: UserId,
+        missing Error => Error::UserNotFound,
+        primary users id => Error::UserIdExists,
+        version rev => Error::UserStale,
+        soft_delete deleted => true,
+        unique user_by_email email => Error::UserEmailExists,
+        foreign groups group => Error::GroupNotFound,
+        index users_by_group group => (),
+        changes wal _ => Error::LogFailed
+    );
+    table!(
+        groups: Group,
+        id: GroupId,
+        missing Error => Error::GroupNotFound,
+        primary groups id => Error::GroupIdExists,
+        reverse users_by_group id => Error::GroupNotEmpty,
+        changes wal _ => Error::LogFailed
+    );
+    // users reference groups, which are listed later
+    wal!(wal, Error, users: User, groups: Group);
+
+    /// Open the database stored in `path`.
+    fn open(path: &Path) -> (Self, WalRecovery) {
+        let mut database = Database {
+            wal: Some(Wal::open(path, Json::default()).unwrap()),
+            ..Default::default()
+        };
+        let recovery = database.wal_recover().unwrap();
+        (database, recovery)
+    }
+
+    /// Tables and indices of the database, for comparison.
+    #[allow(clippy::type_complexity)]
+    fn state(
+        &self,
+    ) -> (
+        &BTreeMap<UserId, User>,
+        &BTreeMap<String, UserId>,
+        &BTreeMap<GroupId, BTreeSet<UserId>>,
+        &BTreeMap<GroupId, Group>,
+    ) {
+        (
+            &self.users,
+            &self.user_by_email,
+            &self.users_by_group,
+            &self.groups,
+        )
+    }
+}
+
+fn user(id: UserId, email: &str, group: GroupId) -> User {
+    User {
+        id,
+        email: email.into(),
+        group,
+        rev: 0,
+        deleted: false,
+    }
+}
+
+fn group(id: GroupId, name: &str) -> Group {
+    Group {
+        id,
+        name: name.into(),
+    }
+}
+
+/// Apply a few changes of every kind to the database.
+fn populate(database: &mut Database) {
+    database.groups_insert(group(0, "admins")).unwrap();
+    database.groups_insert(group(1, "users")).unwrap();
+    database.users_insert(user(0, "alice", 0)).unwrap();
+    database.users_insert(user(1, "bob", 1)).unwrap();
+    database.users_insert(user(2, "carol", 1)).unwrap();
+    database.users_update(user(1, "robert", 0)).unwrap();
+    database.users_delete(2).unwrap();
+    database.users_insert(user(3, "carol", 1)).unwrap();
+    database.users_delete(0).unwrap();
+    database.users_restore(0).unwrap();
+    database.users_delete(3).unwrap();
+    database.users_purge(3).unwrap();
+    database.groups_insert(group(2, "guests")).unwrap();
+    database.groups_delete(2).unwrap();
+}
+
+fn log_length(path: &Path) -> u64 {
+    fs::metadata(path.join("log")).unwrap().len()
+}
+
+#[test]
+fn recovers_changes_from_log() {
+    let directory = tempfile::tempdir().unwrap();
+    let (mut database, recovery) = Database::open(directory.path());
+    assert_eq!(recovery, WalRecovery::default());
+    populate(&mut database);
+    assert!(database.wal.as_ref().unwrap().error().is_none());
+
+    let (recovered, recovery) = Database::open(directory.path());
+    assert_eq!(recovered.state(), database.state());
+    assert_eq!(recovered.users_get(&1).unwrap().rev, 1);
+    assert_eq!(recovered.users_get(&2), None);
+    assert_eq!(
+        recovery,
+        WalRecovery {
+            snapshot: false,
+            records: 14,
+            discarded: 0,
+        }
+    );
+}
+
+#[test]
+fn recovers_from_snapshot_and_log() {
+    let directory = tempfile::tempdir().unwrap();
+    let (mut database, _) = Database::open(directory.path());
+    populate(&mut database);
+    database.wal_compact().unwrap();
+    assert_eq!(database.wal.as_ref().unwrap().generation(), 1);
+    // the snapshot is stored like the ones of the snapshot macro, with a field per table
+    let snapshot = fs::read(directory.path().join("snapshot")).unwrap();
+    assert!(snapshot.windows(10).any(|bytes| bytes == br#"{"users":["#));
+    let compacted = log_length(directory.path());
+
+    database.users_insert(user(4, "dave", 1)).unwrap();
+    database.users_update(user(4, "david", 1)).unwrap();
+    assert!(log_length(directory.path()) > compacted);
+
+    let (mut recovered, recovery) = Database::open(directory.path());
+    assert_eq!(recovered.state(), database.state());
+    assert!(recovery.snapshot);
+    assert_eq!(recovery.records, 2);
+
+    // changes after recovering are appended to the same log
+    recovered.users_delete(4).unwrap();
+    let (again, recovery) = Database::open(directory.path());
+    assert_eq!(again.state(), recovered.state());
+    assert_eq!(recovery.records, 3);
+}
+
+#[test]
+fn discards_torn_writes() {
+    let directory = tempfile::tempdir().unwrap();
+    let (mut database, _) = Database::open(directory.path());
+    populate(&mut database);
+    let expected = log_length(directory.path());
+    database.users_insert(user(4, "dave", 1)).unwrap();
+    let full = log_length(directory.path());
+    drop(database);
+
+    // every possible truncation of the last record loses only that record
+    for length in expected..full {
+        let copy = tempfile::tempdir().unwrap();
+        fs::copy(directory.path().join("log"), copy.path().join("log")).unwrap();
+        OpenOptions::new()
+            .write(true)
+            .open(copy.path().join("log"))
+            .unwrap()
+            .set_len(length)
+            .unwrap();
+
+        let (mut recovered, recovery) = Database::open(copy.path());
+        assert_eq!(recovery.records, 14);
+        assert_eq!(recovery.discarded, length - expected);
+        assert_eq!(recovered.users_get(&4), None);
+        assert_eq!(log_length(copy.path()), expected);
+
+        // the log stays usable after discarding the torn record
+        recovered.users_insert(user(4, "erin", 0)).unwrap();
+        let (again, recovery) = Database::open(copy.path());
+        assert_eq!(recovery.records, 15);
+        assert_eq!(again.users_get(&4).unwrap().email, "erin");
+    }
+}
+
+#[test]
+fn discards_corrupt_records() {
+    let directory = tempfile::tempdir().unwrap();
+    let (mut database, _) = Database::open(directory.path());
+    populate(&mut database);
+    let expected = database.state().0.clone();
+    database.users_insert(user(4, "dave", 1)).unwrap();
+    drop(database);
+
+    let mut data = fs::read(directory.path().join("log")).unwrap();
+    let last = data.len() - 3;
+    data[last] ^= 0xff;
+    fs::write(directory.path().join("log"), &data).unwrap();
+
+    let (recovered, recovery) = Database::open(directory.path());
+    assert_eq!(recovered.users, expected);
+    assert!(recovery.discarded > 0);
+}
+
+#[test]
+fn rejects_corrupt_records_before_valid_ones() {
+    let directory = tempfile::tempdir().unwrap();
+    let (mut database, _) = Database::open(directory.path());
+    populate(&mut database);
+    drop(database);
+
+    // flip a byte in the payload of the first record, after its header and frame header
+    let mut data = fs::read(directory.path().join("log")).unwrap();
+    data[16 + 8 + 2] ^= 0xff;
+    fs::write(directory.path().join("log"), &data).unwrap();
+
+    let error = Wal::open(directory.path(), Json::default()).err().unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(fs::read(directory.path().join("log")).unwrap(), data);
+}
+
+#[test]
+fn failed_writes_abort_changes() {
+    let directory = tempfile::tempdir().unwrap();
+    let codec = Json::default();
+    let broken = codec.broken.clone();
+    let mut database = Database {
+        wal: Some(Wal::open(directory.path(), codec).unwrap()),
+        ..Default::default()
+    };
+    database.wal_recover().unwrap();
+    populate(&mut database);
+    let users = database.users.clone();
+    let length = log_length(directory.path());
+
+    broken.store(true, Ordering::Relaxed);
+    assert_eq!(
+        database.users_insert(user(4, "dave", 1)).unwrap_err(),
+        Error::LogFailed
+    );
+    assert_eq!(database.user_by_email.get("dave"), None);
+    assert!(database.wal.as_ref().unwrap().error().is_some());
+    let wal = database.wal.as_ref().unwrap();
+    assert!(wal.committer().commit(wal.sequence()).is_err());
+
+    // the log is missing a change once a write failed, so later changes fail as well
+    broken.store(false, Ordering::Relaxed);
+    assert_eq!(database.users_delete(1), Err(Error::LogFailed));
+    assert_eq!(database.users, users);
+    assert_eq!(log_length(directory.path()), length);
+    drop(database);
+
+    let (recovered, _) = Database::open(directory.path());
+    assert_eq!(recovered.users, users);
+}
+
+#[test]
+fn ignores_log_of_interrupted_compaction() {
+    let directory = tempfile::tempdir().unwrap();
+    let (mut database, _) = Database::open(directory.path());
+    populate(&mut database);
+    let log = fs::read(directory.path().join("log")).unwrap();
+    database.wal_compact().unwrap();
+    drop(database);
+
+    // crash after the snapshot was written, but before the log was reset
+    fs::write(directory.path().join("log"), &log).unwrap();
+    let (mut recovered, recovery) = Database::open(directory.path());
+    assert!(recovery.snapshot);
+    assert_eq!(recovery.records, 0);
+    assert_eq!(recovered.users_get(&1).unwrap().email, "robert");
+
+    recovered.users_delete(1).unwrap();
+    let (again, recovery) = Database::open(directory.path());
+    assert_eq!(recovery.records, 1);
+    assert_eq!(again.state(), recovered.state());
+}
+
+#[test]
+fn rejects_inconsistent_files() {
+    let directory = tempfile::tempdir().unwrap();
+    let (mut database, _) = Database::open(directory.path());
+    populate(&mut database);
+    database.wal_compact().unwrap();
+    drop(database);
+
+    // log of the next generation without its snapshot
+    fs::remove_file(directory.path().join("snapshot")).unwrap();
+    assert!(Wal::open(directory.path(), Json::default()).is_err());
+
+    // record that does not apply to the database
+    let directory = tempfile::tempdir().unwrap();
+    let (mut database, _) = Database::open(directory.path());
+    database.groups_insert(group(0, "admins")).unwrap();
+    drop(database);
+    let mut database = Database {
+        wal: Some(Wal::open(directory.path(), Json::default()).unwrap()),
+        ..Default::default()
+    };
+    database.groups.insert(0, group(0, "admins"));
+    assert!(matches!(
+        database.wal_recover(),
+        Err(WalError::Log {
+            table: "groups",
+            record: 0,
+            error: Error::GroupIdExists
+        })
+    ));
+}